@@ -44,6 +44,7 @@ pub struct Relay {
     pub ip: Option<IpAddr>,
     #[serde(default = "default::relay::certificates")]
     pub certificates: Vec<PathBuf>,
+    pub client_certificate: Option<ClientCertificate>,
     #[serde(
         default = "default::relay::udp_relay_mode",
         deserialize_with = "deserialize_from_str"
@@ -58,6 +59,15 @@ pub struct Relay {
     pub alpn: Vec<String>,
     #[serde(default = "default::relay::zero_rtt_handshake")]
     pub zero_rtt_handshake: bool,
+    #[serde(default = "default::relay::connect_bound")]
+    pub connect_bound: bool,
+    /// Relays SOCKS5 `BIND` requests with the `Bind` extension command, which the server must be
+    /// configured to serve. They are refused otherwise.
+    #[serde(default = "default::relay::bind")]
+    pub bind: bool,
+    /// How long the server should keep an idle UDP session, sent with `Associate` when the session
+    /// starts. The server's default is kept if unset.
+    pub udp_session_timeout: Option<Duration>,
     #[serde(default = "default::relay::disable_sni")]
     pub disable_sni: bool,
     #[serde(default = "default::relay::timeout")]
@@ -72,6 +82,14 @@ pub struct Relay {
     pub gc_lifetime: Duration,
 }
 
+/// The certificate presented to a server that requires one, with its private key
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientCertificate {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Local {
@@ -90,12 +108,8 @@ impl Config {
 
         while let Some(arg) = parser.next()? {
             match arg {
-                Arg::Short('c') | Arg::Long("config") => {
-                    if path.is_none() {
-                        path = Some(parser.value()?);
-                    } else {
-                        return Err(ConfigError::Argument(arg.unexpected()));
-                    }
+                Arg::Short('c') | Arg::Long("config") if path.is_none() => {
+                    path = Some(parser.value()?);
                 }
                 Arg::Short('v') | Arg::Long("version") => {
                     return Err(ConfigError::Version(env!("CARGO_PKG_VERSION")))
@@ -141,6 +155,14 @@ mod default {
             false
         }
 
+        pub fn connect_bound() -> bool {
+            false
+        }
+
+        pub fn bind() -> bool {
+            false
+        }
+
         pub fn disable_sni() -> bool {
             false
         }
@@ -17,6 +17,7 @@ use register_count::{Counter, Register};
 use rustls::{version, ClientConfig as RustlsClientConfig};
 use socks5_proto::Address as Socks5Address;
 use std::{
+    collections::HashSet,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -28,11 +29,12 @@ use tokio::{
     sync::{Mutex as AsyncMutex, OnceCell as AsyncOnceCell},
     time,
 };
-use tuic::Address;
+use tuic::{Address, EXTENSION_COMMANDS};
 use tuic_quinn::{side, Connect, Connection as Model, Task};
 use uuid::Uuid;
 
-static ENDPOINT: OnceCell<Mutex<Endpoint>> = OnceCell::new();
+// connecting may rebind the endpoint, but only ever happens under the lock of `CONNECTION`
+static ENDPOINT: OnceCell<Endpoint> = OnceCell::new();
 static CONNECTION: AsyncOnceCell<AsyncMutex<Connection>> = AsyncOnceCell::const_new();
 static TIMEOUT: AtomicCell<Duration> = AtomicCell::new(Duration::from_secs(0));
 
@@ -45,6 +47,9 @@ pub struct Endpoint {
     password: Arc<[u8]>,
     udp_relay_mode: UdpRelayMode,
     zero_rtt_handshake: bool,
+    connect_bound: bool,
+    bind: bool,
+    udp_session_timeout: Option<Duration>,
     heartbeat: Duration,
     gc_interval: Duration,
     gc_lifetime: Duration,
@@ -54,13 +59,20 @@ impl Endpoint {
     pub fn set_config(cfg: Relay) -> Result<(), Error> {
         let certs = utils::load_certs(cfg.certificates, cfg.disable_native_certs)?;
 
-        let mut crypto = RustlsClientConfig::builder()
+        let crypto = RustlsClientConfig::builder()
             .with_safe_default_cipher_suites()
             .with_safe_default_kx_groups()
             .with_protocol_versions(&[&version::TLS13])
             .unwrap()
-            .with_root_certificates(certs)
-            .with_no_client_auth();
+            .with_root_certificates(certs);
+
+        let mut crypto = match cfg.client_certificate {
+            Some(cert) => crypto.with_single_cert(
+                utils::load_cert_chain(cert.certificate)?,
+                utils::load_priv_key(cert.private_key)?,
+            )?,
+            None => crypto.with_no_client_auth(),
+        };
 
         crypto.alpn_protocols = cfg.alpn.into_iter().map(|alpn| alpn.into_bytes()).collect();
         crypto.enable_early_data = true;
@@ -99,13 +111,16 @@ impl Endpoint {
             password: Arc::from(cfg.password.into_bytes().into_boxed_slice()),
             udp_relay_mode: cfg.udp_relay_mode,
             zero_rtt_handshake: cfg.zero_rtt_handshake,
+            connect_bound: cfg.connect_bound,
+            bind: cfg.bind,
+            udp_session_timeout: cfg.udp_session_timeout,
             heartbeat: cfg.heartbeat,
             gc_interval: cfg.gc_interval,
             gc_lifetime: cfg.gc_lifetime,
         };
 
         ENDPOINT
-            .set(Mutex::new(ep))
+            .set(ep)
             .map_err(|_| "endpoint already initialized")
             .unwrap();
 
@@ -114,18 +129,22 @@ impl Endpoint {
         Ok(())
     }
 
-    async fn connect(&mut self) -> Result<Connection, Error> {
+    async fn connect(&self) -> Result<Connection, Error> {
+        #[allow(clippy::too_many_arguments)]
         async fn connect_to(
-            ep: &mut QuinnEndpoint,
+            ep: &QuinnEndpoint,
             addr: SocketAddr,
             server_name: &str,
             uuid: Uuid,
             password: Arc<[u8]>,
             udp_relay_mode: UdpRelayMode,
             zero_rtt_handshake: bool,
+            connect_bound: bool,
+            bind: bool,
+            udp_session_timeout: Option<Duration>,
         ) -> Result<Connection, Error> {
-            let match_ipv4 = addr.is_ipv4() && ep.local_addr().map_or(false, |addr| addr.is_ipv4());
-            let match_ipv6 = addr.is_ipv6() && ep.local_addr().map_or(false, |addr| addr.is_ipv6());
+            let match_ipv4 = addr.is_ipv4() && ep.local_addr().is_ok_and(|addr| addr.is_ipv4());
+            let match_ipv6 = addr.is_ipv6() && ep.local_addr().is_ok_and(|addr| addr.is_ipv6());
 
             if !match_ipv4 && !match_ipv6 {
                 let bind_addr = if addr.is_ipv4() {
@@ -152,20 +171,31 @@ impl Endpoint {
                 conn.await?
             };
 
-            Ok(Connection::new(conn, udp_relay_mode, uuid, password))
+            Ok(Connection::new(
+                conn,
+                udp_relay_mode,
+                connect_bound,
+                bind,
+                udp_session_timeout,
+                uuid,
+                password,
+            ))
         }
 
         let mut last_err = None;
 
         for addr in self.server.resolve().await? {
             let res = connect_to(
-                &mut self.ep,
+                &self.ep,
                 addr,
                 self.server.server_name(),
                 self.uuid,
                 self.password.clone(),
                 self.udp_relay_mode,
                 self.zero_rtt_handshake,
+                self.connect_bound,
+                self.bind,
+                self.udp_session_timeout,
             )
             .await;
 
@@ -194,6 +224,12 @@ pub struct Connection {
     uuid: Uuid,
     password: Arc<[u8]>,
     udp_relay_mode: UdpRelayMode,
+    connect_bound: bool,
+    bind: bool,
+    udp_session_timeout: Option<Duration>,
+    // the UDP sessions the server was sent the timeout of, per connection as a new one starts
+    // without any
+    associated: Arc<Mutex<HashSet<u16>>>,
     remote_uni_stream_cnt: Counter,
     remote_bi_stream_cnt: Counter,
     max_concurrent_uni_streams: Arc<AtomicUsize>,
@@ -204,6 +240,9 @@ impl Connection {
     fn new(
         conn: QuinnConnection,
         udp_relay_mode: UdpRelayMode,
+        connect_bound: bool,
+        bind: bool,
+        udp_session_timeout: Option<Duration>,
         uuid: Uuid,
         password: Arc<[u8]>,
     ) -> Self {
@@ -213,6 +252,10 @@ impl Connection {
             uuid,
             password,
             udp_relay_mode,
+            connect_bound,
+            bind,
+            udp_session_timeout,
+            associated: Arc::new(Mutex::new(HashSet::new())),
             remote_uni_stream_cnt: Counter::new(),
             remote_bi_stream_cnt: Counter::new(),
             max_concurrent_uni_streams: Arc::new(AtomicUsize::new(DEFAULT_CONCURRENT_STREAMS)),
@@ -221,15 +264,7 @@ impl Connection {
     }
 
     pub async fn get() -> Result<Connection, Error> {
-        let try_init_conn = async {
-            ENDPOINT
-                .get()
-                .unwrap()
-                .lock()
-                .connect()
-                .await
-                .map(AsyncMutex::new)
-        };
+        let try_init_conn = async { ENDPOINT.get().unwrap().connect().await.map(AsyncMutex::new) };
 
         let try_get_conn = async {
             let mut conn = CONNECTION
@@ -239,7 +274,7 @@ impl Connection {
                 .await;
 
             if conn.is_closed() {
-                let new_conn = ENDPOINT.get().unwrap().lock().connect().await?;
+                let new_conn = ENDPOINT.get().unwrap().connect().await?;
                 *conn = new_conn;
             }
 
@@ -253,20 +288,47 @@ impl Connection {
         Ok(conn)
     }
 
-    pub async fn connect(&self, addr: Address) -> Result<Connect, Error> {
-        Ok(self.model.connect(addr).await?)
+    /// Opens a TCP relay, also returning the address the server connected to the target from if
+    /// `connect_bound` is enabled
+    pub async fn connect(&self, addr: Address) -> Result<(Connect, Option<Address>), Error> {
+        if self.connect_bound {
+            let (relay, bound_addr) = self.model.connect_bound(addr).await?;
+            Ok((relay, Some(bound_addr)))
+        } else {
+            Ok((self.model.connect(addr).await?, None))
+        }
+    }
+
+    /// Checks if SOCKS5 `BIND` requests are relayed, which needs no connection to tell
+    pub fn is_bind_enabled() -> bool {
+        ENDPOINT.get().unwrap().bind
+    }
+
+    /// Asks the server to listen for the peer `addr`, returning the relay along with the address
+    /// the server listens on
+    pub async fn bind(&self, addr: Address) -> Result<(Connect, Address), Error> {
+        Ok(self.model.bind(addr).await?)
     }
 
     pub async fn packet(&self, pkt: Bytes, addr: Address, assoc_id: u16) -> Result<(), Error> {
+        if let Some(timeout) = self.udp_session_timeout {
+            // only recorded once sent, so a failed `Associate` is retried with the next packet
+            if !self.associated.lock().contains(&assoc_id) {
+                self.model.associate(assoc_id, Some(timeout)).await?;
+                self.associated.lock().insert(assoc_id);
+            }
+        }
+
         match self.udp_relay_mode {
             UdpRelayMode::Native => self.model.packet_native(pkt, addr, assoc_id)?,
             UdpRelayMode::Quic => self.model.packet_quic(pkt, addr, assoc_id).await?,
-        }
+        };
 
         Ok(())
     }
 
     pub async fn dissociate(&self, assoc_id: u16) -> Result<(), Error> {
+        self.associated.lock().remove(&assoc_id);
         self.model.dissociate(assoc_id).await?;
         Ok(())
     }
@@ -387,9 +449,17 @@ impl Connection {
     }
 
     async fn authenticate(self) {
+        // servers only serve the extension commands once announced, which is done only when
+        // configured to use them
+        let extensions = if self.connect_bound || self.bind || self.udp_session_timeout.is_some() {
+            EXTENSION_COMMANDS
+        } else {
+            0
+        };
+
         match self
             .model
-            .authenticate(self.uuid, self.password.clone())
+            .authenticate_with_extensions(self.uuid, self.password.clone(), extensions)
             .await
         {
             Ok(()) => log::info!("[connection] authentication sent"),
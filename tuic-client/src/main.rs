@@ -5,6 +5,7 @@ use self::{
 };
 use env_logger::Builder as LoggerBuilder;
 use quinn::{ConnectError, ConnectionError};
+use rustls::Error as RustlsError;
 use std::{env, io::Error as IoError, process};
 use thiserror::Error;
 use tuic_quinn::Error as ModelError;
@@ -66,6 +67,8 @@ pub enum Error {
     Model(#[from] ModelError),
     #[error(transparent)]
     Webpki(#[from] WebpkiError),
+    #[error(transparent)]
+    Rustls(#[from] RustlsError),
     #[error("timeout establishing connection")]
     Timeout,
     #[error("cannot resolve the server name")]
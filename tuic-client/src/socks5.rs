@@ -11,7 +11,7 @@ use socks5_server::{
 };
 use std::{
     collections::HashMap,
-    io::{Error as IoError, ErrorKind},
+    io::Error as IoError,
     net::{IpAddr, SocketAddr, TcpListener as StdTcpListener, UdpSocket as StdUdpSocket},
     sync::{
         atomic::{AtomicU16, Ordering},
@@ -53,6 +53,7 @@ impl Server {
             socket.set_reuse_address(true)?;
             socket.bind(&SockAddr::from(cfg.server))?;
             socket.listen(128)?;
+            socket.set_nonblocking(true)?;
             TcpListener::from_std(StdTcpListener::from(socket))?
         };
 
@@ -129,6 +130,7 @@ impl Server {
             }
 
             socket.bind(&SockAddr::from(SERVER.get().unwrap().addr))?;
+            socket.set_nonblocking(true)?;
 
             let socket = AssociatedUdpSocket::from((
                 UdpSocket::from_std(StdUdpSocket::from(socket))?,
@@ -157,12 +159,71 @@ impl Server {
         }
     }
 
-    async fn handle_bind(bind: Bind<bind::NeedFirstReply>, _addr: Address) -> Result<(), Error> {
-        let mut conn = bind
-            .reply(Reply::CommandNotSupported, Address::unspecified())
+    async fn handle_bind(bind: Bind<bind::NeedFirstReply>, addr: Address) -> Result<(), Error> {
+        if !TuicConnection::is_bind_enabled() {
+            let mut conn = bind
+                .reply(Reply::CommandNotSupported, Address::unspecified())
+                .await?;
+            let _ = conn.shutdown().await;
+            return Ok(());
+        }
+
+        let peer_addr = match addr {
+            Address::DomainAddress(domain, port) => TuicAddress::DomainAddress(domain, port),
+            Address::SocketAddress(addr) => TuicAddress::SocketAddress(addr),
+        };
+
+        let relay = match TuicConnection::get().await {
+            Ok(conn) => conn.bind(peer_addr).await,
+            Err(err) => Err(err),
+        };
+
+        let (mut relay, bound_addr) = match relay {
+            Ok(relay) => relay,
+            Err(relay_err) => {
+                log::error!("[connection] {relay_err}");
+                let mut conn = bind
+                    .reply(Reply::GeneralFailure, Address::unspecified())
+                    .await?;
+                let _ = conn.shutdown().await;
+                return Ok(());
+            }
+        };
+
+        // the first reply tells the client where the peer should connect to, the second one who
+        // did once it has
+        let bind = bind
+            .reply(Reply::Succeeded, socks5_addr(bound_addr))
             .await?;
-        let _ = conn.shutdown().await;
-        Ok(())
+
+        let peer_addr = match relay.recv_peer_addr().await {
+            Ok(peer_addr) => peer_addr,
+            Err(relay_err) => {
+                log::error!("[connection] {relay_err}");
+                let mut conn = bind
+                    .reply(Reply::GeneralFailure, Address::unspecified())
+                    .await?;
+                let _ = conn.shutdown().await;
+                return Ok(());
+            }
+        };
+
+        let mut relay = relay.compat();
+
+        match bind.reply(Reply::Succeeded, socks5_addr(peer_addr)).await {
+            Ok(mut conn) => match io::copy_bidirectional(&mut conn, &mut relay).await {
+                Ok(_) => Ok(()),
+                Err(err) => {
+                    let _ = conn.shutdown().await;
+                    let _ = relay.shutdown().await;
+                    Err(Error::from(err))
+                }
+            },
+            Err(err) => {
+                let _ = relay.shutdown().await;
+                Err(Error::from(err))
+            }
+        }
     }
 
     async fn handle_connect(conn: Connect<connect::NeedReply>, addr: Address) -> Result<(), Error> {
@@ -177,10 +238,12 @@ impl Server {
         };
 
         match relay {
-            Ok(relay) => {
+            Ok((relay, bound_addr)) => {
                 let mut relay = relay.compat();
 
-                match conn.reply(Reply::Succeeded, Address::unspecified()).await {
+                let bound_addr = bound_addr.map_or_else(Address::unspecified, socks5_addr);
+
+                match conn.reply(Reply::Succeeded, bound_addr).await {
                     Ok(mut conn) => match io::copy_bidirectional(&mut conn, &mut relay).await {
                         Ok(_) => Ok(()),
                         Err(err) => {
@@ -234,10 +297,9 @@ impl Server {
 
             if let Some(connected) = connected {
                 if connected != &src_addr {
-                    Err(IoError::new(
-                        ErrorKind::Other,
-                        format!("invalid source address: {src_addr}"),
-                    ))?;
+                    Err(IoError::other(format!(
+                        "invalid source address: {src_addr}"
+                    )))?;
                 }
             } else {
                 assoc_socket.connect(src_addr).await?;
@@ -245,10 +307,7 @@ impl Server {
             }
 
             if frag != 0 {
-                Err(IoError::new(
-                    ErrorKind::Other,
-                    "fragmented packet is not supported",
-                ))?;
+                Err(IoError::other("fragmented packet is not supported"))?;
             }
 
             let target_addr = match dst_addr {
@@ -307,3 +366,12 @@ impl Server {
         }
     }
 }
+
+/// Converts an address answering a `ConnectBound` or `Bind` for a SOCKS5 reply
+fn socks5_addr(addr: TuicAddress) -> Address {
+    match addr {
+        TuicAddress::SocketAddress(addr) => Address::SocketAddress(addr),
+        TuicAddress::DomainAddress(domain, port) => Address::DomainAddress(domain, port),
+        TuicAddress::None => Address::unspecified(),
+    }
+}
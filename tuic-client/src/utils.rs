@@ -1,5 +1,5 @@
 use crate::Error;
-use rustls::{Certificate, RootCertStore};
+use rustls::{Certificate, PrivateKey, RootCertStore};
 use rustls_pemfile::Item;
 use std::{
     fs::{self, File},
@@ -38,6 +38,39 @@ pub fn load_certs(paths: Vec<PathBuf>, disable_native: bool) -> Result<RootCertS
     Ok(certs)
 }
 
+pub fn load_cert_chain(path: PathBuf) -> Result<Vec<Certificate>, Error> {
+    let mut file = BufReader::new(File::open(&path)?);
+    let mut certs = Vec::new();
+
+    while let Ok(Some(item)) = rustls_pemfile::read_one(&mut file) {
+        if let Item::X509Certificate(cert) = item {
+            certs.push(Certificate(cert));
+        }
+    }
+
+    if certs.is_empty() {
+        certs = vec![Certificate(fs::read(&path)?)];
+    }
+
+    Ok(certs)
+}
+
+pub fn load_priv_key(path: PathBuf) -> Result<PrivateKey, Error> {
+    let mut file = BufReader::new(File::open(&path)?);
+    let mut priv_key = None;
+
+    while let Ok(Some(item)) = rustls_pemfile::read_one(&mut file) {
+        if let Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) = item {
+            priv_key = Some(key);
+        }
+    }
+
+    Ok(PrivateKey(match priv_key {
+        Some(key) => key,
+        None => fs::read(&path)?,
+    }))
+}
+
 pub struct ServerAddr {
     domain: String,
     port: u16,
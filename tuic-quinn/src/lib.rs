@@ -5,10 +5,12 @@ use bytes::{BufMut, Bytes};
 use futures_util::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use quinn::{
     Connection as QuinnConnection, ConnectionError, RecvStream, SendDatagramError, SendStream,
+    VarInt,
 };
 use std::{
     fmt::{Debug, Formatter, Result as FmtResult},
     io::{Cursor, Error as IoError},
+    net::SocketAddr,
     pin::Pin,
     task::{Context, Poll},
     time::Duration,
@@ -18,10 +20,10 @@ use tuic::{
     model::{
         side::{Rx, Tx},
         AssembleError, Authenticate as AuthenticateModel, Connect as ConnectModel,
-        Connection as ConnectionModel, KeyingMaterialExporter as KeyingMaterialExporterImpl,
-        Packet as PacketModel,
+        Connection as ConnectionModel, FragmentError,
+        KeyingMaterialExporter as KeyingMaterialExporterImpl, Packet as PacketModel,
     },
-    Address, Header, UnmarshalError,
+    Address, Header, StatsResponse, UnmarshalError, MAX_PACKET_SIZE,
 };
 use uuid::Uuid;
 
@@ -52,46 +54,54 @@ pub struct Connection<Side> {
 }
 
 impl<Side> Connection<Side> {
-    /// Sends a `Packet` using UDP relay mode `native`.
+    /// Sends a `Packet` using UDP relay mode `native`, returning the number of fragments it was
+    /// split into.
     pub fn packet_native(
         &self,
         pkt: impl AsRef<[u8]>,
         addr: Address,
         assoc_id: u16,
-    ) -> Result<(), Error> {
+    ) -> Result<usize, Error> {
         let Some(max_pkt_size) = self.conn.max_datagram_size() else {
             return Err(Error::SendDatagram(SendDatagramError::Disabled));
         };
 
         let model = self.model.send_packet(assoc_id, addr, max_pkt_size);
+        let mut frag_cnt = 0;
 
-        for (header, frag) in model.into_fragments(pkt) {
-            let mut buf = vec![0; header.len() + frag.len()];
+        for (header, frag) in model.into_fragments(pkt)? {
+            let mut buf = Vec::with_capacity(header.len() + frag.len());
             header.write(&mut buf);
             buf.put_slice(frag);
             self.conn.send_datagram(Bytes::from(buf))?;
+            frag_cnt += 1;
         }
 
-        Ok(())
+        Ok(frag_cnt)
     }
 
-    /// Sends a `Packet` using UDP relay mode `quic`.
+    /// Sends a `Packet` using UDP relay mode `quic`, returning the number of fragments it was split
+    /// into.
     pub async fn packet_quic(
         &self,
         pkt: impl AsRef<[u8]>,
         addr: Address,
         assoc_id: u16,
-    ) -> Result<(), Error> {
-        let model = self.model.send_packet(assoc_id, addr, u16::MAX as usize);
+    ) -> Result<usize, Error> {
+        let model = self
+            .model
+            .send_packet(assoc_id, addr, MAX_PACKET_SIZE as usize);
+        let mut frag_cnt = 0;
 
-        for (header, frag) in model.into_fragments(pkt) {
+        for (header, frag) in model.into_fragments(pkt)? {
             let mut send = self.conn.open_uni().await?;
             header.async_marshal(&mut send).await?;
             AsyncWriteExt::write_all(&mut send, frag).await?;
             send.close().await?;
+            frag_cnt += 1;
         }
 
-        Ok(())
+        Ok(frag_cnt)
     }
 
     /// Returns the number of `Connect` tasks
@@ -109,6 +119,12 @@ impl<Side> Connection<Side> {
         self.model.collect_garbage(timeout);
     }
 
+    /// Terminates the UDP relaying session linked to a finished relay, see
+    /// [`Connect::linked_assoc_id`]. No command is sent, as the peer sees the relay end too.
+    pub fn dissociate_linked(&self, assoc_id: u16) {
+        self.model.dissociate_linked(assoc_id);
+    }
+
     fn keying_material_exporter(&self) -> KeyingMaterialExporter {
         KeyingMaterialExporter(self.conn.clone())
     }
@@ -126,12 +142,29 @@ impl Connection<side::Client> {
 
     /// Sends an `Authenticate` command.
     pub async fn authenticate(&self, uuid: Uuid, password: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.authenticate_with_extensions(uuid, password, 0).await
+    }
+
+    /// Sends an `Authenticate` command, followed by the extension flags the client wants to use,
+    /// e.g. [`EXTENSION_COMMANDS`](tuic::EXTENSION_COMMANDS). With no flag set, this is the same
+    /// as [`Connection::authenticate`].
+    pub async fn authenticate_with_extensions(
+        &self,
+        uuid: Uuid,
+        password: impl AsRef<[u8]>,
+        extensions: u8,
+    ) -> Result<(), Error> {
         let model = self
             .model
             .send_authenticate(uuid, password, &self.keying_material_exporter());
 
         let mut send = self.conn.open_uni().await?;
         model.header().async_marshal(&mut send).await?;
+
+        if extensions != 0 {
+            AsyncWriteExt::write_all(&mut send, &[extensions]).await?;
+        }
+
         send.close().await?; // stuck here
         Ok(())
     }
@@ -141,7 +174,54 @@ impl Connection<side::Client> {
         let model = self.model.send_connect(addr);
         let (mut send, recv) = self.conn.open_bi().await?;
         model.header().async_marshal(&mut send).await?;
-        Ok(Connect::new(Side::Client(model), send, recv))
+        Ok(Connect::new(Side::Client(model), send, recv, Reply::None))
+    }
+
+    /// Sends a `ConnectBound` command and waits for the server to connect to the target.
+    ///
+    /// Returns the relay along with the local address the server connected to the target from.
+    /// Only servers known to support the command should be asked, as others treat it as invalid.
+    pub async fn connect_bound(&self, addr: Address) -> Result<(Connect, Address), Error> {
+        let model = self.model.send_connect_bound(addr);
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        let bound_addr = Address::async_unmarshal(&mut recv)
+            .await
+            .map_err(Error::UnmarshalBoundAddress)?;
+        Ok((
+            Connect::new(Side::Client(model), send, recv, Reply::None),
+            bound_addr,
+        ))
+    }
+
+    /// Sends a `Bind` command and waits for the server to listen for the peer at `addr`.
+    ///
+    /// Returns the relay along with the address the server listens on, for the peer to connect to.
+    /// Data is only relayed once the peer connected, see [`Connect::recv_peer_addr`]. Only servers
+    /// known to support the command should be asked, as others treat it as invalid.
+    pub async fn bind(&self, addr: Address) -> Result<(Connect, Address), Error> {
+        let model = self.model.send_bind(addr);
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        let bound_addr = Address::async_unmarshal(&mut recv)
+            .await
+            .map_err(Error::UnmarshalBoundAddress)?;
+        Ok((
+            Connect::new(Side::Client(model), send, recv, Reply::Bind),
+            bound_addr,
+        ))
+    }
+
+    /// Sends a `ConnectLinked` command, tying the UDP relaying session `assoc_id` to the relay.
+    ///
+    /// Once the relay ends, the session is terminated on both sides without sending a `Dissociate`,
+    /// see [`Connection::dissociate_linked`]. Only servers known to support the command should be
+    /// asked, as others treat it as invalid.
+    pub async fn connect_linked(&self, assoc_id: u16, addr: Address) -> Result<Connect, Error> {
+        let model = self.model.send_connect_linked(assoc_id, addr);
+        let (mut send, recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        Ok(Connect::new(Side::Client(model), send, recv, Reply::None))
     }
 
     /// Sends a `Dissociate` command.
@@ -153,6 +233,31 @@ impl Connection<side::Client> {
         Ok(())
     }
 
+    /// Sends an `Associate` command, asking the server to terminate the UDP relaying session
+    /// `assoc_id` once it has been idle for `timeout`, or after its own default if `None`.
+    ///
+    /// The server may cap the timeout. Only servers known to support the command should be asked,
+    /// as others treat it as invalid.
+    pub async fn associate(&self, assoc_id: u16, timeout: Option<Duration>) -> Result<(), Error> {
+        let model = self.model.send_associate(assoc_id, timeout);
+        let mut send = self.conn.open_uni().await?;
+        model.header().async_marshal(&mut send).await?;
+        send.close().await?;
+        Ok(())
+    }
+
+    /// Sends a `DissociateAll` command, terminating every UDP relaying session of the
+    /// connection at once.
+    ///
+    /// Only servers known to support the command should be asked, as others treat it as invalid.
+    pub async fn dissociate_all(&self) -> Result<(), Error> {
+        let model = self.model.send_dissociate_all();
+        let mut send = self.conn.open_uni().await?;
+        model.header().async_marshal(&mut send).await?;
+        send.close().await?;
+        Ok(())
+    }
+
     /// Sends a `Heartbeat` command.
     pub async fn heartbeat(&self) -> Result<(), Error> {
         let model = self.model.send_heartbeat();
@@ -162,6 +267,19 @@ impl Connection<side::Client> {
         Ok(())
     }
 
+    /// Sends a `Stats` command and waits for the server's response.
+    ///
+    /// Only servers known to support the command should be queried, as others treat it as invalid.
+    pub async fn stats(&self) -> Result<StatsResponse, Error> {
+        let model = self.model.send_stats();
+        let (mut send, mut recv) = self.conn.open_bi().await?;
+        model.header().async_marshal(&mut send).await?;
+        send.close().await?;
+        StatsResponse::async_unmarshal(&mut recv)
+            .await
+            .map_err(Error::UnmarshalStatsResponse)
+    }
+
     /// Try to parse a `quinn::RecvStream` as a TUIC command.
     ///
     /// The `quinn::RecvStream` should be accepted by `quinn::Connection::accept_uni()` from the same `quinn::Connection`.
@@ -184,6 +302,12 @@ impl Connection<side::Client> {
             }
             Header::Dissociate(_) => Err(Error::BadCommandUniStream("dissociate", recv)),
             Header::Heartbeat(_) => Err(Error::BadCommandUniStream("heartbeat", recv)),
+            Header::Stats(_) => Err(Error::BadCommandUniStream("stats", recv)),
+            Header::ConnectBound(_) => Err(Error::BadCommandUniStream("connect_bound", recv)),
+            Header::DissociateAll(_) => Err(Error::BadCommandUniStream("dissociate_all", recv)),
+            Header::ConnectLinked(_) => Err(Error::BadCommandUniStream("connect_linked", recv)),
+            Header::Associate(_) => Err(Error::BadCommandUniStream("associate", recv)),
+            Header::Bind(_) => Err(Error::BadCommandUniStream("bind", recv)),
             _ => unreachable!(),
         }
     }
@@ -207,6 +331,16 @@ impl Connection<side::Client> {
             Header::Packet(_) => Err(Error::BadCommandBiStream("packet", send, recv)),
             Header::Dissociate(_) => Err(Error::BadCommandBiStream("dissociate", send, recv)),
             Header::Heartbeat(_) => Err(Error::BadCommandBiStream("heartbeat", send, recv)),
+            Header::Stats(_) => Err(Error::BadCommandBiStream("stats", send, recv)),
+            Header::ConnectBound(_) => Err(Error::BadCommandBiStream("connect_bound", send, recv)),
+            Header::DissociateAll(_) => {
+                Err(Error::BadCommandBiStream("dissociate_all", send, recv))
+            }
+            Header::ConnectLinked(_) => {
+                Err(Error::BadCommandBiStream("connect_linked", send, recv))
+            }
+            Header::Associate(_) => Err(Error::BadCommandBiStream("associate", send, recv)),
+            Header::Bind(_) => Err(Error::BadCommandBiStream("bind", send, recv)),
             _ => unreachable!(),
         }
     }
@@ -232,7 +366,7 @@ impl Connection<side::Client> {
                 if let Some(pkt) = self.model.recv_packet(pkt) {
                     let pos = dg.position() as usize;
                     let mut buf = dg.into_inner();
-                    if (pos + pkt.size() as usize) < buf.len() {
+                    if (pos + pkt.size() as usize) <= buf.len() {
                         buf = buf.slice(pos..pos + pkt.size() as usize);
                         Ok(Task::Packet(Packet::new(pkt, PacketSource::Native(buf))))
                     } else {
@@ -244,6 +378,18 @@ impl Connection<side::Client> {
             }
             Header::Dissociate(_) => Err(Error::BadCommandDatagram("dissociate", dg.into_inner())),
             Header::Heartbeat(_) => Err(Error::BadCommandDatagram("heartbeat", dg.into_inner())),
+            Header::Stats(_) => Err(Error::BadCommandDatagram("stats", dg.into_inner())),
+            Header::ConnectBound(_) => {
+                Err(Error::BadCommandDatagram("connect_bound", dg.into_inner()))
+            }
+            Header::DissociateAll(_) => {
+                Err(Error::BadCommandDatagram("dissociate_all", dg.into_inner()))
+            }
+            Header::ConnectLinked(_) => {
+                Err(Error::BadCommandDatagram("connect_linked", dg.into_inner()))
+            }
+            Header::Associate(_) => Err(Error::BadCommandDatagram("associate", dg.into_inner())),
+            Header::Bind(_) => Err(Error::BadCommandDatagram("bind", dg.into_inner())),
             _ => unreachable!(),
         }
     }
@@ -270,9 +416,21 @@ impl Connection<side::Server> {
 
         match header {
             Header::Authenticate(auth) => {
+                // the extension flags are optional, a client without any just finishes the stream
+                let mut extensions = [0];
+                let extensions = match recv.read(&mut extensions).await {
+                    Ok(Some(1)) => extensions[0],
+                    Ok(_) => 0,
+                    Err(err) => {
+                        let err = UnmarshalError::Io(IoError::from(err));
+                        return Err(Error::UnmarshalUniStream(err, recv));
+                    }
+                };
+
                 let model = self.model.recv_authenticate(auth);
                 Ok(Task::Authenticate(Authenticate::new(
                     model,
+                    extensions,
                     self.keying_material_exporter(),
                 )))
             }
@@ -286,6 +444,18 @@ impl Connection<side::Server> {
                 Ok(Task::Dissociate(model.assoc_id()))
             }
             Header::Heartbeat(_) => Err(Error::BadCommandUniStream("heartbeat", recv)),
+            Header::Stats(_) => Err(Error::BadCommandUniStream("stats", recv)),
+            Header::ConnectBound(_) => Err(Error::BadCommandUniStream("connect_bound", recv)),
+            Header::DissociateAll(dissoc_all) => {
+                let _ = self.model.recv_dissociate_all(dissoc_all);
+                Ok(Task::DissociateAll)
+            }
+            Header::ConnectLinked(_) => Err(Error::BadCommandUniStream("connect_linked", recv)),
+            Header::Associate(assoc) => {
+                let model = self.model.recv_associate(assoc);
+                Ok(Task::Associate(model.assoc_id(), model.timeout()))
+            }
+            Header::Bind(_) => Err(Error::BadCommandUniStream("bind", recv)),
             _ => unreachable!(),
         }
     }
@@ -307,11 +477,51 @@ impl Connection<side::Server> {
             Header::Authenticate(_) => Err(Error::BadCommandBiStream("authenticate", send, recv)),
             Header::Connect(conn) => {
                 let model = self.model.recv_connect(conn);
-                Ok(Task::Connect(Connect::new(Side::Server(model), send, recv)))
+                Ok(Task::Connect(Connect::new(
+                    Side::Server(model),
+                    send,
+                    recv,
+                    Reply::None,
+                )))
             }
             Header::Packet(_) => Err(Error::BadCommandBiStream("packet", send, recv)),
             Header::Dissociate(_) => Err(Error::BadCommandBiStream("dissociate", send, recv)),
             Header::Heartbeat(_) => Err(Error::BadCommandBiStream("heartbeat", send, recv)),
+            Header::Stats(stats) => {
+                let _ = self.model.recv_stats(stats);
+                Ok(Task::Stats(Stats::new(send)))
+            }
+            Header::ConnectBound(conn) => {
+                let model = self.model.recv_connect_bound(conn);
+                Ok(Task::Connect(Connect::new(
+                    Side::Server(model),
+                    send,
+                    recv,
+                    Reply::Bound,
+                )))
+            }
+            Header::DissociateAll(_) => {
+                Err(Error::BadCommandBiStream("dissociate_all", send, recv))
+            }
+            Header::ConnectLinked(conn) => {
+                let model = self.model.recv_connect_linked(conn);
+                Ok(Task::Connect(Connect::new(
+                    Side::Server(model),
+                    send,
+                    recv,
+                    Reply::None,
+                )))
+            }
+            Header::Associate(_) => Err(Error::BadCommandBiStream("associate", send, recv)),
+            Header::Bind(bind) => {
+                let model = self.model.recv_bind(bind);
+                Ok(Task::Connect(Connect::new(
+                    Side::Server(model),
+                    send,
+                    recv,
+                    Reply::Bind,
+                )))
+            }
             _ => unreachable!(),
         }
     }
@@ -335,14 +545,31 @@ impl Connection<side::Server> {
             Header::Packet(pkt) => {
                 let model = self.model.recv_packet_unrestricted(pkt);
                 let pos = dg.position() as usize;
-                let buf = dg.into_inner().slice(pos..pos + model.size() as usize);
-                Ok(Task::Packet(Packet::new(model, PacketSource::Native(buf))))
+                let mut buf = dg.into_inner();
+                if (pos + model.size() as usize) <= buf.len() {
+                    buf = buf.slice(pos..pos + model.size() as usize);
+                    Ok(Task::Packet(Packet::new(model, PacketSource::Native(buf))))
+                } else {
+                    Err(Error::PayloadLength(model.size() as usize, buf.len() - pos))
+                }
             }
             Header::Dissociate(_) => Err(Error::BadCommandDatagram("dissociate", dg.into_inner())),
             Header::Heartbeat(hb) => {
                 let _ = self.model.recv_heartbeat(hb);
                 Ok(Task::Heartbeat)
             }
+            Header::Stats(_) => Err(Error::BadCommandDatagram("stats", dg.into_inner())),
+            Header::ConnectBound(_) => {
+                Err(Error::BadCommandDatagram("connect_bound", dg.into_inner()))
+            }
+            Header::DissociateAll(_) => {
+                Err(Error::BadCommandDatagram("dissociate_all", dg.into_inner()))
+            }
+            Header::ConnectLinked(_) => {
+                Err(Error::BadCommandDatagram("connect_linked", dg.into_inner()))
+            }
+            Header::Associate(_) => Err(Error::BadCommandDatagram("associate", dg.into_inner())),
+            Header::Bind(_) => Err(Error::BadCommandDatagram("bind", dg.into_inner())),
             _ => unreachable!(),
         }
     }
@@ -361,12 +588,17 @@ impl<Side> Debug for Connection<Side> {
 #[derive(Debug)]
 pub struct Authenticate {
     model: AuthenticateModel<Rx>,
+    extensions: u8,
     exporter: KeyingMaterialExporter,
 }
 
 impl Authenticate {
-    fn new(model: AuthenticateModel<Rx>, exporter: KeyingMaterialExporter) -> Self {
-        Self { model, exporter }
+    fn new(model: AuthenticateModel<Rx>, extensions: u8, exporter: KeyingMaterialExporter) -> Self {
+        Self {
+            model,
+            extensions,
+            exporter,
+        }
     }
 
     /// The UUID of the client.
@@ -379,6 +611,11 @@ impl Authenticate {
         self.model.token()
     }
 
+    /// The extension flags the client sent, `0` if none.
+    pub fn extensions(&self) -> u8 {
+        self.extensions
+    }
+
     /// Validates if the given password is matching the hashed token.
     pub fn validate(&self, password: impl AsRef<[u8]>) -> bool {
         self.model.is_valid(password, &self.exporter)
@@ -390,6 +627,17 @@ pub struct Connect {
     model: Side<ConnectModel<Tx>, ConnectModel<Rx>>,
     send: SendStream,
     recv: RecvStream,
+    reply: Reply,
+}
+
+/// The addresses the server answers a relay with, before any data
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reply {
+    None,
+    /// The local address of the connection to the target, answering a `ConnectBound`
+    Bound,
+    /// The address listened on, then the one of the peer, answering a `Bind`
+    Bind,
 }
 
 impl Connect {
@@ -397,20 +645,81 @@ impl Connect {
         model: Side<ConnectModel<Tx>, ConnectModel<Rx>>,
         send: SendStream,
         recv: RecvStream,
+        reply: Reply,
     ) -> Self {
-        Self { model, send, recv }
+        Self {
+            model,
+            send,
+            recv,
+            reply,
+        }
     }
 
     /// Returns the `Connect` address
     pub fn addr(&self) -> &Address {
         match &self.model {
-            Side::Client(model) => {
-                let Header::Connect(conn) = model.header() else { unreachable!() };
-                conn.addr()
-            }
+            Side::Client(model) => match model.header() {
+                Header::Connect(conn) => conn.addr(),
+                Header::ConnectBound(conn) => conn.addr(),
+                Header::ConnectLinked(conn) => conn.addr(),
+                Header::Bind(bind) => bind.addr(),
+                _ => unreachable!(),
+            },
             Side::Server(model) => model.addr(),
         }
     }
+
+    /// Returns the UDP relaying session tied to the relay, if requested with `ConnectLinked`.
+    ///
+    /// The session must be terminated with [`Connection::dissociate_linked`] once the relay ends.
+    pub fn linked_assoc_id(&self) -> Option<u16> {
+        match &self.model {
+            Side::Client(model) => match model.header() {
+                Header::ConnectLinked(conn) => Some(conn.assoc_id()),
+                _ => None,
+            },
+            Side::Server(model) => model.assoc_id(),
+        }
+    }
+
+    /// Checks if the relay was requested with `ConnectBound`, in which case the bound address
+    /// must be sent with [`Connect::send_bound_addr`] before relaying any data.
+    pub fn is_bound(&self) -> bool {
+        self.reply == Reply::Bound
+    }
+
+    /// Checks if the relay was requested with `Bind`, in which case [`Connect::addr`] is the peer
+    /// expected to connect. The address listened on, then the one of the peer once connected, must
+    /// be sent with [`Connect::send_bound_addr`] before relaying any data.
+    pub fn is_bind(&self) -> bool {
+        self.reply == Reply::Bind
+    }
+
+    /// Waits for the peer of a relay requested with [`Connection::bind`] to connect, returning its
+    /// address.
+    pub async fn recv_peer_addr(&mut self) -> Result<Address, Error> {
+        Address::async_unmarshal(&mut self.recv)
+            .await
+            .map_err(Error::UnmarshalBoundAddress)
+    }
+
+    /// Sends the local address of the connection to the target, answering a `ConnectBound`, or one
+    /// of the addresses answering a `Bind`.
+    pub async fn send_bound_addr(&mut self, addr: SocketAddr) -> Result<(), Error> {
+        Address::SocketAddress(addr)
+            .async_marshal(&mut self.send)
+            .await?;
+        Ok(())
+    }
+
+    /// Abandons the relay, resetting the send stream and stopping the receive stream with `code`.
+    ///
+    /// The TUIC protocol itself has no response for `Connect`. The error code can be used to tell
+    /// the peer why the relay is abandoned.
+    pub fn reset(mut self, code: VarInt) {
+        let _ = self.send.reset(code);
+        let _ = self.recv.stop(code);
+    }
 }
 
 impl AsyncRead for Connect {
@@ -452,10 +761,35 @@ impl Debug for Connect {
             .field("model", model)
             .field("send", &self.send)
             .field("recv", &self.recv)
+            .field("reply", &self.reply)
             .finish()
     }
 }
 
+/// A received `Stats` command.
+#[derive(Debug)]
+pub struct Stats {
+    send: SendStream,
+}
+
+impl Stats {
+    fn new(send: SendStream) -> Self {
+        Self { send }
+    }
+
+    /// Sends the response and finishes the stream.
+    pub async fn respond(mut self, resp: StatsResponse) -> Result<(), Error> {
+        resp.async_marshal(&mut self.send).await?;
+        self.send.close().await?;
+        Ok(())
+    }
+
+    /// Refuses to answer, resetting the stream with `code`.
+    pub fn reset(mut self, code: VarInt) {
+        let _ = self.send.reset(code);
+    }
+}
+
 /// A received `Packet` command.
 #[derive(Debug)]
 pub struct Packet {
@@ -513,6 +847,14 @@ impl Packet {
             .map(|pkt| pkt.assemble(&mut asm))
             .map(|(addr, assoc_id)| (Bytes::from(asm), addr, assoc_id)))
     }
+
+    /// Refuses the packet, stopping the stream it would be read from with `code`. A packet
+    /// received as a datagram is just dropped.
+    pub fn reset(self, code: VarInt) {
+        if let PacketSource::Quic(mut recv) = self.src {
+            let _ = recv.stop(code);
+        }
+    }
 }
 
 /// Type of tasks that can be received.
@@ -523,7 +865,10 @@ pub enum Task {
     Connect(Connect),
     Packet(Packet),
     Dissociate(u16),
+    DissociateAll,
+    Associate(u16, Option<Duration>),
     Heartbeat,
+    Stats(Stats),
 }
 
 #[derive(Debug)]
@@ -554,16 +899,22 @@ pub enum Error {
     InvalidUdpSession(u16),
     #[error(transparent)]
     Assemble(#[from] AssembleError),
+    #[error(transparent)]
+    Fragment(#[from] FragmentError),
     #[error("error unmarshaling uni_stream: {0}")]
     UnmarshalUniStream(UnmarshalError, RecvStream),
     #[error("error unmarshaling bi_stream: {0}")]
     UnmarshalBiStream(UnmarshalError, SendStream, RecvStream),
     #[error("error unmarshaling datagram: {0}")]
     UnmarshalDatagram(UnmarshalError, Bytes),
-    #[error("bad command `{0}` from uni_stream")]
+    #[error("command `{0}` not allowed on uni_stream")]
     BadCommandUniStream(&'static str, RecvStream),
-    #[error("bad command `{0}` from bi_stream")]
+    #[error("command `{0}` not allowed on bi_stream")]
     BadCommandBiStream(&'static str, SendStream, RecvStream),
-    #[error("bad command `{0}` from datagram")]
+    #[error("command `{0}` not allowed in datagram")]
     BadCommandDatagram(&'static str, Bytes),
+    #[error("error unmarshaling bound address: {0}")]
+    UnmarshalBoundAddress(UnmarshalError),
+    #[error("error unmarshaling stats response: {0}")]
+    UnmarshalStatsResponse(UnmarshalError),
 }
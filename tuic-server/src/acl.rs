@@ -0,0 +1,305 @@
+use crate::{
+    config::{Acl as AclConfig, Config, SourceFilter as SourceFilterConfig},
+    Error,
+};
+use log::Level;
+use parking_lot::RwLock;
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+use tuic::Address;
+use uuid::Uuid;
+
+/// The destination policy applied to relay requests
+pub struct Acl {
+    deny_ips: Vec<Cidr>,
+    allow_ports: Vec<u16>,
+    deny_domains: Vec<DomainPattern>,
+    log_level: Option<Level>,
+    log_allowed: bool,
+}
+
+impl Acl {
+    pub fn new(cfg: AclConfig) -> Self {
+        Self {
+            deny_ips: cfg.deny_ips,
+            allow_ports: cfg.allow_ports,
+            deny_domains: cfg
+                .deny_domains
+                .iter()
+                .map(|pattern| DomainPattern::new(pattern))
+                .collect(),
+            log_level: cfg.log_level.to_level(),
+            log_allowed: cfg.log_allowed,
+        }
+    }
+
+    /// Checks the requested address before resolving it, returning the rule that denies it
+    pub fn check_addr(&self, addr: &Address) -> Result<(), Rule> {
+        match addr {
+            Address::None => Err(Rule::EmptyAddress),
+            Address::DomainAddress(domain, port) => {
+                self.check_port(*port)?;
+
+                match self.deny_domains.iter().find(|p| p.matches(domain)) {
+                    Some(pattern) => Err(Rule::DenyDomains(pattern.pattern.clone())),
+                    None => Ok(()),
+                }
+            }
+            Address::SocketAddress(addr) => {
+                self.check_port(addr.port())?;
+                self.check_ip(addr.ip())
+            }
+        }
+    }
+
+    /// Checks an address that a domain resolved to, returning the rule that denies it
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), Rule> {
+        let ip = canonicalize(ip);
+
+        match self.deny_ips.iter().find(|cidr| cidr.contains(ip)) {
+            Some(cidr) => Err(Rule::DenyIps(*cidr)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_port(&self, port: u16) -> Result<(), Rule> {
+        if self.allow_ports.is_empty() || self.allow_ports.contains(&port) {
+            Ok(())
+        } else {
+            Err(Rule::AllowPorts)
+        }
+    }
+
+    /// Logs a relay request of `user`, from the connection `remote`, that `rule` denied
+    pub fn log_denied(
+        &self,
+        remote: impl Display,
+        user: Option<Uuid>,
+        cmd: &str,
+        addr: &Address,
+        rule: &Rule,
+    ) {
+        if let Some(level) = self.log_level {
+            log::log!(
+                level,
+                "{remote} event=acl user={user} cmd={cmd} target={addr} rule={rule} denied",
+                user = user.map_or_else(|| "-".to_owned(), |user| user.to_string()),
+            );
+        }
+    }
+
+    /// Logs a relay request that passed the ACL, if enabled
+    pub fn log_allowed(&self, remote: impl Display, user: Option<Uuid>, cmd: &str, addr: &Address) {
+        if let Some(level) = self.log_level.filter(|_| self.log_allowed) {
+            log::log!(
+                level,
+                "{remote} event=acl user={user} cmd={cmd} target={addr} allowed",
+                user = user.map_or_else(|| "-".to_owned(), |user| user.to_string()),
+            );
+        }
+    }
+}
+
+/// The ACL rule that denied a destination
+pub enum Rule {
+    EmptyAddress,
+    AllowPorts,
+    DenyDomains(String),
+    DenyIps(Cidr),
+    /// Not a configurable rule, see `is_ipv6_link_local` in the server
+    Ipv6LinkLocal,
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::EmptyAddress => write!(f, "empty_address"),
+            Self::AllowPorts => write!(f, "allow_ports"),
+            Self::DenyDomains(pattern) => write!(f, "deny_domains:{pattern}"),
+            Self::DenyIps(cidr) => write!(f, "deny_ips:{cidr}"),
+            Self::Ipv6LinkLocal => write!(f, "ipv6_link_local"),
+        }
+    }
+}
+
+/// The source address policy applied to incoming connections, before they are handed to a
+/// connection handler
+pub struct SourceFilter {
+    lists: RwLock<SourceFilterConfig>,
+}
+
+impl SourceFilter {
+    pub fn new(cfg: Option<SourceFilterConfig>) -> Arc<Self> {
+        Arc::new(Self {
+            lists: RwLock::new(cfg.unwrap_or_default()),
+        })
+    }
+
+    /// Checks the address of a connecting client. An address in a denied network is never
+    /// allowed, and if any allowed network is configured, the address must be in one of them.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = canonicalize(ip);
+        let lists = self.lists.read();
+
+        !lists.deny.iter().any(|cidr| cidr.contains(ip))
+            && (lists.allow.is_empty() || lists.allow.iter().any(|cidr| cidr.contains(ip)))
+    }
+
+    /// Replaces the lists. Connections already accepted are not affected.
+    pub fn update(&self, cfg: Option<SourceFilterConfig>) {
+        *self.lists.write() = cfg.unwrap_or_default();
+    }
+}
+
+/// Reloads the source filter from the config file every time the process receives SIGHUP
+///
+/// Only `source_filter` is taken from the reloaded file. If the file can't be read, the current
+/// lists are kept.
+#[cfg(unix)]
+pub fn reload_on_sighup(filter: Arc<SourceFilter>, config_path: PathBuf) -> Result<(), Error> {
+    use tokio::signal::unix::{self, SignalKind};
+
+    let mut sighup = unix::signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            match Config::read(&config_path) {
+                Ok(cfg) => {
+                    filter.update(cfg.source_filter);
+                    log::warn!("source filter reloaded");
+                }
+                Err(err) => log::error!("failed to reload source filter: {err}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Treats IPv4-mapped IPv6 addresses as the IPv4 addresses they map to, so `::ffff:127.0.0.1`
+/// can't bypass a deny rule for `127.0.0.0/8`
+pub fn canonicalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8`. A bare IP address is a single-host network.
+#[derive(Clone, Copy)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let addr = IpAddr::from_str(addr).map_err(|_| "invalid CIDR address")?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or("invalid CIDR prefix length")?,
+            None => max_prefix_len,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// A domain name, or `*.` followed by a domain name to match all of its subdomains
+struct DomainPattern {
+    pattern: String,
+    domain: String,
+    subdomains: bool,
+}
+
+impl DomainPattern {
+    fn new(pattern: &str) -> Self {
+        let normalized = pattern.trim_end_matches('.').to_ascii_lowercase();
+
+        match normalized.strip_prefix("*.") {
+            Some(domain) => Self {
+                pattern: pattern.to_owned(),
+                domain: format!(".{domain}"),
+                subdomains: true,
+            },
+            None => Self {
+                pattern: pattern.to_owned(),
+                domain: normalized,
+                subdomains: false,
+            },
+        }
+    }
+
+    fn matches(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+
+        if self.subdomains {
+            domain.ends_with(&self.domain)
+        } else {
+            domain == self.domain
+        }
+    }
+}
+
+/// Loopback, private, link-local, unspecified and shared (CGNAT) address ranges
+pub fn default_deny_ips() -> Vec<Cidr> {
+    [
+        "0.0.0.0/8",
+        "10.0.0.0/8",
+        "100.64.0.0/10",
+        "127.0.0.0/8",
+        "169.254.0.0/16",
+        "172.16.0.0/12",
+        "192.168.0.0/16",
+        "::/128",
+        "::1/128",
+        "fc00::/7",
+        "fe80::/10",
+    ]
+    .into_iter()
+    .map(|cidr| Cidr::from_str(cidr).unwrap())
+    .collect()
+}
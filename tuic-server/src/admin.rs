@@ -0,0 +1,191 @@
+use crate::server::Registry;
+use std::{
+    io::{Error as IoError, ErrorKind},
+    sync::Arc,
+    time::Duration,
+};
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// The largest request head accepted, request line and headers included
+const MAX_REQUEST_HEAD_LEN: usize = 8192;
+
+/// How long a client has to send the request head
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the admin API over HTTP
+///
+/// - `GET /connections` lists the established connections as a JSON array, with their ID, remote
+///   address, authenticated user, active TCP relays and UDP sessions, and bytes relayed.
+/// - `POST /connections/<id>/close` closes a connection with the `closed_by_admin` error code.
+///
+/// Every request must carry `Authorization: Bearer <token>`, or it is answered with
+/// `401 Unauthorized`.
+pub async fn serve(listener: TcpListener, token: String, registry: Arc<Registry>) {
+    log::warn!(
+        "admin server started, listening on {}",
+        listener.local_addr().unwrap()
+    );
+
+    let token = Arc::<str>::from(token);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let token = token.clone();
+                let registry = registry.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) = handle_request(stream, &token, &registry).await {
+                        log::debug!("event=admin addr={addr} {err}");
+                    }
+                });
+            }
+            Err(err) => log::warn!("event=admin {err}"),
+        }
+    }
+}
+
+async fn handle_request(
+    mut stream: TcpStream,
+    token: &str,
+    registry: &Registry,
+) -> Result<(), IoError> {
+    let head = time::timeout(REQUEST_TIMEOUT, read_request_head(&mut stream))
+        .await
+        .map_err(|_| IoError::new(ErrorKind::TimedOut, "request timed out"))??;
+    let req = String::from_utf8_lossy(&head);
+
+    let mut lines = req.lines();
+    let mut req_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = req_line.next().unwrap_or_default();
+    let path = req_line.next().unwrap_or_default();
+
+    let is_authorized = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("authorization")
+                && value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .is_some_and(|value| bool::from(value.as_bytes().ct_eq(token.as_bytes())))
+        });
+
+    let (status, body) = if !is_authorized {
+        ("401 Unauthorized", String::new())
+    } else {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+        match (method, segments.as_slice()) {
+            ("GET", ["connections"]) => {
+                let conns = registry.list().await;
+                ("200 OK", serde_json::to_string(&conns).unwrap())
+            }
+            ("POST", ["connections", id, "close"]) => match id.parse() {
+                Ok(id) if registry.close(id) => ("204 No Content", String::new()),
+                _ => ("404 Not Found", String::new()),
+            },
+            _ => ("404 Not Found", String::new()),
+        }
+    };
+
+    let resp = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(resp.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads the request line and headers, which may arrive over several TCP segments, up to the
+/// blank line ending them. The requests served carry no body.
+async fn read_request_head(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, IoError> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+
+    loop {
+        let n = stream.read(&mut buf).await?;
+
+        if n == 0 {
+            return Err(IoError::new(
+                ErrorKind::UnexpectedEof,
+                "connection closed before the end of the request head",
+            ));
+        }
+
+        // the terminator may straddle two reads
+        let searched = head.len().saturating_sub(3);
+        head.extend_from_slice(&buf[..n]);
+
+        if let Some(pos) = head[searched..].windows(4).position(|w| w == b"\r\n\r\n") {
+            head.truncate(searched + pos + 4);
+            return Ok(head);
+        }
+
+        if head.len() > MAX_REQUEST_HEAD_LEN {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{self, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn request_head_split_across_writes() {
+        let (mut client, mut server) = io::duplex(64);
+
+        tokio::spawn(async move {
+            for part in [
+                "GET /connections HTTP/1.1\r\nAuthor",
+                "ization: Bearer x\r",
+                "\n\r\n",
+            ] {
+                client.write_all(part.as_bytes()).await.unwrap();
+                client.flush().await.unwrap();
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        let head = read_request_head(&mut server).await.unwrap();
+        assert_eq!(
+            head,
+            b"GET /connections HTTP/1.1\r\nAuthorization: Bearer x\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn request_head_too_large() {
+        let (mut client, mut server) = io::duplex(1024);
+
+        tokio::spawn(async move {
+            let line = format!("X-Padding: {}\r\n", "a".repeat(100));
+
+            while client.write_all(line.as_bytes()).await.is_ok() {}
+        });
+
+        let err = read_request_head(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn request_head_truncated() {
+        let (mut client, mut server) = io::duplex(64);
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        drop(client);
+
+        let err = read_request_head(&mut server).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    config::{self, User},
+    Error,
+};
+use parking_lot::RwLock;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    future::Future,
+    io::BufReader,
+    mem,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::mpsc, time};
+use uuid::Uuid;
+
+/// How often the users file is checked for changes
+const USERS_FILE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub type UserFuture<'a> = Pin<Box<dyn Future<Output = Option<Arc<User>>> + Send + 'a>>;
+
+/// Where users are looked up when a client authenticates, e.g. a file, a database or an HTTP
+/// service
+///
+/// The token sent by a client is derived from its password and the TLS session, so it can only be
+/// checked by the server holding the connection. A backend hands out the user, along with its
+/// password, and the server validates the token against it.
+pub trait Authenticator: Send + Sync {
+    /// Looks up the user with the given UUID, returning `None` if there is none
+    fn user(&self, uuid: Uuid) -> UserFuture<'_>;
+}
+
+/// The users listed in the config file, or in the users file
+pub struct StaticUsers {
+    users: RwLock<HashMap<Uuid, Arc<User>>>,
+}
+
+impl StaticUsers {
+    pub fn new(users: HashMap<Uuid, User>) -> Arc<Self> {
+        Arc::new(Self {
+            users: RwLock::new(Self::wrap(users)),
+        })
+    }
+
+    /// Reads the users from a JSON file, in the same format as `users` in the config file
+    pub fn read_file(path: &Path) -> Result<HashMap<Uuid, User>, Error> {
+        let file = BufReader::new(File::open(path)?);
+        let mut de = serde_json::Deserializer::from_reader(file);
+        let users = config::deserialize_users(&mut de).map_err(Error::InvalidUsersFile)?;
+        de.end().map_err(Error::InvalidUsersFile)?;
+        Ok(users)
+    }
+
+    /// Replaces the users at once. Connections already authenticated are not affected, even if
+    /// their user was removed.
+    pub fn update(&self, users: HashMap<Uuid, User>) {
+        *self.users.write() = Self::wrap(users);
+    }
+
+    fn wrap(users: HashMap<Uuid, User>) -> HashMap<Uuid, Arc<User>> {
+        users
+            .into_iter()
+            .map(|(uuid, user)| (uuid, Arc::new(user)))
+            .collect()
+    }
+}
+
+impl Authenticator for StaticUsers {
+    fn user(&self, uuid: Uuid) -> UserFuture<'_> {
+        let user = self.users.read().get(&uuid).cloned();
+        Box::pin(async move { user })
+    }
+}
+
+/// Reloads the users from the users file whenever it changes, and every time the process receives
+/// SIGHUP
+///
+/// If the file can't be read or holds an invalid entry, the current users are kept.
+pub fn reload_users_file(users: Arc<StaticUsers>, path: PathBuf) -> Result<(), Error> {
+    let (tx, mut rx) = mpsc::channel(1);
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+
+        let mut sighup = unix::signal(SignalKind::hangup())?;
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            while sighup.recv().await.is_some() {
+                // a reload already pending covers this one
+                let _ = tx.try_send(());
+            }
+        });
+    }
+
+    let watched = path.clone();
+
+    tokio::spawn(async move {
+        let mut modified = modified_at(&watched);
+
+        loop {
+            time::sleep(USERS_FILE_POLL_INTERVAL).await;
+            let prev = mem::replace(&mut modified, modified_at(&watched));
+
+            if modified != prev && tx.send(()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            match StaticUsers::read_file(&path) {
+                Ok(new_users) => {
+                    log::warn!("users reloaded, {} users", new_users.len());
+                    users.update(new_users);
+                }
+                Err(err) => log::error!("failed to reload users: {err}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
@@ -0,0 +1,129 @@
+use crate::{utils, Error};
+use parking_lot::RwLock;
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey, SigningKey},
+    Certificate, Error as RustlsError, RootCertStore, SignatureScheme,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use webpki::{
+    EndEntityCert, ECDSA_P256_SHA256, ECDSA_P384_SHA384, ED25519, RSA_PKCS1_2048_8192_SHA256,
+};
+
+/// Serves the certificate loaded from disk to every handshake, and swaps it for a fresh copy on
+/// `reload()`
+///
+/// Handshakes in progress keep the certificate they started with, established connections are not
+/// affected at all.
+pub struct CertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    cert: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> Result<Arc<Self>, Error> {
+        let cert = Self::load(&cert_path, &key_path)?;
+
+        Ok(Arc::new(Self {
+            cert_path,
+            key_path,
+            cert: RwLock::new(Arc::new(cert)),
+        }))
+    }
+
+    /// Reloads the certificate and private key. On error, the current certificate is kept.
+    pub fn reload(&self) -> Result<(), Error> {
+        let cert = Self::load(&self.cert_path, &self.key_path)?;
+        *self.cert.write() = Arc::new(cert);
+        Ok(())
+    }
+
+    fn load(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, Error> {
+        let certs = utils::load_certs(cert_path.to_path_buf())?;
+        let priv_key = utils::load_priv_key(key_path.to_path_buf())?;
+
+        let key = sign::any_supported_type(&priv_key)
+            .map_err(|_| RustlsError::General("invalid private key".into()))?;
+
+        let Some(end_entity) = certs.first() else {
+            return Err(RustlsError::General("no certificate found".into()).into());
+        };
+
+        check_key_matches(end_entity, key.as_ref())?;
+
+        Ok(CertifiedKey::new(certs, key))
+    }
+}
+
+/// Checks that the private key belongs to the certificate, by signing a message with the key and
+/// verifying the signature with the certificate's public key
+fn check_key_matches(cert: &Certificate, key: &dyn SigningKey) -> Result<(), RustlsError> {
+    const MSG: &[u8] = b"tuic-server certificate key check";
+
+    let schemes = [
+        (SignatureScheme::ECDSA_NISTP256_SHA256, &ECDSA_P256_SHA256),
+        (SignatureScheme::ECDSA_NISTP384_SHA384, &ECDSA_P384_SHA384),
+        (SignatureScheme::ED25519, &ED25519),
+        (
+            SignatureScheme::RSA_PKCS1_SHA256,
+            &RSA_PKCS1_2048_8192_SHA256,
+        ),
+    ];
+
+    let signer = key
+        .choose_scheme(&schemes.map(|(scheme, _)| scheme))
+        .ok_or_else(|| RustlsError::General("unsupported private key type".into()))?;
+
+    let (_, alg) = schemes
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+        .unwrap();
+
+    let sig = signer.sign(MSG)?;
+
+    EndEntityCert::try_from(cert.0.as_slice())
+        .and_then(|cert| cert.verify_signature(alg, MSG, &sig))
+        .map_err(|_| RustlsError::General("private key does not match the certificate".into()))
+}
+
+/// Loads the CA certificates client certificates are verified against
+pub fn load_client_ca(path: &Path) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in utils::load_certs(path.to_path_buf())? {
+        roots
+            .add(&cert)
+            .map_err(|err| RustlsError::General(format!("invalid client CA certificate: {err}")))?;
+    }
+
+    Ok(roots)
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.cert.read().clone())
+    }
+}
+
+/// Reloads the certificate every time the process receives SIGHUP
+#[cfg(unix)]
+pub fn reload_on_sighup(resolver: Arc<CertResolver>) -> Result<(), Error> {
+    use tokio::signal::unix::{self, SignalKind};
+
+    let mut sighup = unix::signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            match resolver.reload() {
+                Ok(()) => log::warn!("certificate reloaded"),
+                Err(err) => log::error!("failed to reload certificate: {err}"),
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -1,11 +1,23 @@
-use crate::utils::CongestionControl;
+use crate::{
+    acl::Cidr,
+    utils::{
+        CongestionControl, OutboundBind, OutboundFamily, UdpNatMode, UdpRelayMode, UpstreamProtocol,
+    },
+};
 use lexopt::{Arg, Error as ArgumentError, Parser};
 use log::LevelFilter;
 use serde::{de::Error as DeError, Deserialize, Deserializer};
-use serde_json::Error as SerdeError;
+use serde_json::{error::Category, Error as SerdeError};
 use std::{
-    collections::HashMap, env::ArgsOs, fmt::Display, fs::File, io::Error as IoError,
-    net::SocketAddr, path::PathBuf, str::FromStr, time::Duration,
+    collections::HashMap,
+    env::ArgsOs,
+    fmt::Display,
+    fs,
+    io::Error as IoError,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
 use thiserror::Error;
 use uuid::Uuid;
@@ -15,6 +27,8 @@ Usage tuic-server [arguments]
 
 Arguments:
     -c, --config <path>     Path to the config file (required)
+        --check             Validate the config, loading the certificate and binding the
+                            listen addresses, then exit without serving
     -v, --version           Print the version
     -h, --help              Print this help message
 "#;
@@ -22,11 +36,27 @@ Arguments:
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
-    pub server: SocketAddr,
-    #[serde(deserialize_with = "deserialize_users")]
-    pub users: HashMap<Uuid, String>,
+    #[serde(deserialize_with = "deserialize_listen_addrs")]
+    pub server: Vec<SocketAddr>,
+    #[serde(default, deserialize_with = "deserialize_users")]
+    pub users: HashMap<Uuid, User>,
+    /// A JSON file holding the users instead of `users`, in the same format. It is reloaded when
+    /// it changes and on SIGHUP, without restarting the server.
+    pub users_file: Option<PathBuf>,
+    /// Treats every connection as authenticated, making the server an open relay. Only meant for
+    /// testing and benchmarking. The extension commands are then served without being negotiated.
+    #[serde(default = "default::no_auth")]
+    pub no_auth: bool,
+    /// Closes the whole connection on a malformed or unexpected command, instead of only resetting
+    /// the stream that carried it
+    #[serde(default = "default::strict_protocol")]
+    pub strict_protocol: bool,
     pub certificate: PathBuf,
     pub private_key: PathBuf,
+    /// The CA certificates, in PEM, client certificates are verified against. If set, clients must
+    /// present a certificate issued by one of them, on top of authenticating with their UUID and
+    /// password.
+    pub client_ca: Option<PathBuf>,
     #[serde(
         default = "default::congestion_control",
         deserialize_with = "deserialize_from_str"
@@ -34,39 +64,306 @@ pub struct Config {
     pub congestion_control: CongestionControl,
     #[serde(default = "default::alpn")]
     pub alpn: Vec<String>,
+    #[serde(default = "default::udp_relay")]
+    pub udp_relay: bool,
     #[serde(default = "default::udp_relay_ipv6")]
     pub udp_relay_ipv6: bool,
+    #[serde(
+        default = "default::udp_nat_mode",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub udp_nat_mode: UdpNatMode,
     #[serde(default = "default::zero_rtt_handshake")]
     pub zero_rtt_handshake: bool,
+    /// The level the outcome of each TLS handshake is logged at: the negotiated ALPN protocol, the
+    /// server name sent by the client, whether 0-RTT was used and the number of client
+    /// certificates. `off` disables the log.
+    #[serde(default = "default::handshake_log_level")]
+    pub handshake_log_level: LevelFilter,
+    #[serde(default = "default::migration")]
+    pub migration: bool,
     pub dual_stack: Option<bool>,
     #[serde(default = "default::auth_timeout")]
     pub auth_timeout: Duration,
+    /// How long to hold a connection that failed to authenticate before closing it, doubled for
+    /// every recent failure from the same address if `escalate_auth_failure_delay` is enabled
+    #[serde(default = "default::auth_failure_delay")]
+    pub auth_failure_delay: Duration,
+    #[serde(default = "default::escalate_auth_failure_delay")]
+    pub escalate_auth_failure_delay: bool,
+    pub max_accept_rate: Option<u64>,
+    pub max_accept_burst: Option<u64>,
+    pub max_unauthenticated_per_ip: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub max_connections_per_user: Option<usize>,
+    #[serde(default = "default::connect_timeout")]
+    pub connect_timeout: Duration,
+    #[serde(default = "default::command_timeout")]
+    pub command_timeout: Duration,
+    /// How long the payload of a `Packet` sent over a unidirectional stream may take to arrive once
+    /// its header did. A stream missing the deadline is aborted.
+    #[serde(default = "default::packet_timeout")]
+    pub packet_timeout: Duration,
+    #[serde(default = "default::dns_cache_size")]
+    pub dns_cache_size: usize,
+    #[serde(default = "default::dns_cache_ttl")]
+    pub dns_cache_ttl: Duration,
+    pub bandwidth_limit: Option<u64>,
+    #[serde(default = "default::acl")]
+    pub acl: Acl,
     #[serde(default = "default::max_idle_time")]
     pub max_idle_time: Duration,
+    #[serde(default = "default::stream_receive_window")]
+    pub stream_receive_window: u64,
+    #[serde(default = "default::receive_window")]
+    pub receive_window: u64,
+    #[serde(default = "default::send_window")]
+    pub send_window: u64,
+    pub keep_alive_interval: Option<Duration>,
     #[serde(default = "default::max_external_packet_size")]
     pub max_external_packet_size: usize,
     #[serde(default = "default::gc_interval")]
     pub gc_interval: Duration,
     #[serde(default = "default::gc_lifetime")]
     pub gc_lifetime: Duration,
+    #[serde(default = "default::udp_session_timeout")]
+    pub udp_session_timeout: Duration,
+    /// The longest idle timeout a client may ask for a UDP session with `Associate`. Longer ones
+    /// are cut down to this.
+    #[serde(default = "default::max_udp_session_timeout")]
+    pub max_udp_session_timeout: Duration,
+    #[serde(default = "default::max_udp_sessions")]
+    pub max_udp_sessions: usize,
+    #[serde(default = "default::max_tcp_relays")]
+    pub max_tcp_relays: usize,
+    /// The most unidirectional streams a client may have open at once. Every command but
+    /// `Connect` and `Heartbeat` takes a stream of its own, including each `Packet` fragment in UDP
+    /// relay mode `quic`. The server starts lower and raises the limit up to this as streams fill
+    /// up. Packets beyond `max_udp_packets_in_flight` are dropped even while streams are left, but a
+    /// client out of streams waits instead, stalling its UDP relaying.
+    #[serde(default = "default::max_concurrent_uni_streams")]
+    pub max_concurrent_uni_streams: u32,
+    /// The most bidirectional streams, each carrying a TCP relay, a client may have open at once,
+    /// raised up to like `max_concurrent_uni_streams`. Set above `max_tcp_relays`, relays beyond
+    /// that are refused; set below, a client opening more waits for others to close instead.
+    #[serde(default = "default::max_concurrent_bi_streams")]
+    pub max_concurrent_bi_streams: u32,
+    /// The most UDP packets from the client a connection forwards at once
+    #[serde(default = "default::max_udp_packets_in_flight")]
+    pub max_udp_packets_in_flight: usize,
+    /// The most UDP packets to the client a connection forwards at once, bounded apart from
+    /// `max_udp_packets_in_flight`
+    #[serde(default = "default::max_udp_download_packets_in_flight")]
+    pub max_udp_download_packets_in_flight: usize,
+    #[serde(default = "default::relay_buffer_size")]
+    pub relay_buffer_size: usize,
+    #[serde(default = "default::tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Option<TcpKeepalive>,
+    pub max_relay_lifetime: Option<Duration>,
+    /// How long a `Bind` waits for its peer to connect. `Bind` is refused unless set, and always
+    /// with `upstream_proxy`, as the peer would reach the server itself.
+    pub bind_timeout: Option<Duration>,
+    pub path_stats_interval: Option<Duration>,
+    #[serde(
+        default = "default::outbound_bind",
+        deserialize_with = "deserialize_outbound_bind"
+    )]
+    pub outbound_bind: OutboundBind,
+    /// The fwmark (`SO_MARK`) set on relayed TCP connections and UDP sockets, for policy routing.
+    /// Only supported on Linux, where it requires `CAP_NET_ADMIN`.
+    pub outbound_mark: Option<u32>,
+    /// Restricts relays to one address family, `v4` or `v6`, or makes them try one first,
+    /// `v4-first` or `v6-first`, when a destination resolves to both. `auto` keeps the resolver's
+    /// order.
+    #[serde(
+        default = "default::outbound_family",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub outbound_family: OutboundFamily,
+    pub metrics_server: Option<SocketAddr>,
+    pub admin: Option<Admin>,
+    pub proxy_protocol: Option<ProxyProtocol>,
+    pub upstream_proxy: Option<UpstreamProxy>,
+    pub connection_pool: Option<ConnectionPool>,
+    pub connect_retry: Option<ConnectRetry>,
+    pub fingerprint_resistance: Option<FingerprintResistance>,
+    pub source_filter: Option<SourceFilter>,
+    pub quota_file: Option<PathBuf>,
+    #[serde(default = "default::quota_save_interval")]
+    pub quota_save_interval: Duration,
+    #[serde(default = "default::shutdown_timeout")]
+    pub shutdown_timeout: Duration,
     #[serde(default = "default::log_level")]
     pub log_level: LevelFilter,
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub check: bool,
+}
+
+/// A user, configured either as just its password or as an object with per-user settings
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct User {
+    pub password: String,
+    pub quota: Option<u64>,
+    #[serde(
+        default = "default::outbound_bind",
+        deserialize_with = "deserialize_outbound_bind"
+    )]
+    pub outbound_bind: OutboundBind,
+    pub outbound_mark: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_option_from_str")]
+    pub udp_nat_mode: Option<UdpNatMode>,
+    /// Restricts the user to one UDP relay mode. Packets the client sends the other way are
+    /// rejected, so the client must be configured to match.
+    #[serde(default, deserialize_with = "deserialize_option_from_str")]
+    pub udp_relay_mode: Option<UdpRelayMode>,
+    /// Overrides `max_connections_per_user` for this user
+    pub max_connections: Option<usize>,
+}
+
+/// The admin API listener. Requests must carry `Authorization: Bearer <token>`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Admin {
+    pub server: SocketAddr,
+    pub token: String,
+}
+
+/// Sending a PROXY protocol v2 header on relayed TCP connections. An empty `destinations` list
+/// means every destination.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyProtocol {
+    #[serde(
+        default = "default::proxy_protocol::destinations",
+        deserialize_with = "deserialize_vec_from_str"
+    )]
+    pub destinations: Vec<Cidr>,
+}
+
+/// A proxy relayed TCP connections go through instead of connecting directly
+///
+/// Domains are passed to the proxy unresolved, so the ACL can only check them by name and port.
+/// No PROXY protocol header is sent through it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamProxy {
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub protocol: UpstreamProtocol,
+    pub server: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Keeping idle TCP connections to `destinations`, given as `host:port` exactly as requested by
+/// clients, established in advance so relays to them skip the handshake
+///
+/// This changes connection semantics: the destination sees connections opened before, and
+/// independently of, any client asking for them, which may stay idle for up to `idle_timeout`.
+/// Only enable it for backends that tolerate this, i.e. that neither time out idle connections
+/// early nor expect anything of a connection before the client's first bytes.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionPool {
+    pub destinations: Vec<String>,
+    #[serde(default = "default::connection_pool::size")]
+    pub size: usize,
+    #[serde(default = "default::connection_pool::idle_timeout")]
+    pub idle_timeout: Duration,
+}
+
+/// TCP keep-alive on relayed connections, sending the first probe after the connection has been
+/// idle for `time` and the next ones every `interval`, on platforms that support setting it
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpKeepalive {
+    #[serde(default = "default::tcp_keepalive::time")]
+    pub time: Duration,
+    pub interval: Option<Duration>,
+}
+
+/// Retrying failed connection attempts to relay targets
+///
+/// Each resolved address is tried up to `attempts` times before it counts as unreachable, waiting
+/// between tries for a backoff that starts at `initial_backoff`, doubles every time up to
+/// `max_backoff`, and is randomly shortened by up to half. All attempts share `connect_timeout`,
+/// so an unresponsive target can't stall a relay for longer.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectRetry {
+    #[serde(default = "default::connect_retry::attempts")]
+    pub attempts: u32,
+    #[serde(default = "default::connect_retry::initial_backoff")]
+    pub initial_backoff: Duration,
+    #[serde(default = "default::connect_retry::max_backoff")]
+    pub max_backoff: Duration,
+}
+
+/// Making the server harder to identify by active probing, at a small cost
+///
+/// A client that fails to authenticate, or breaks the protocol, is closed after a random delay of
+/// up to `close_jitter` on top of `auth_failure_delay`, holding the connection a little longer. With
+/// `conceal_close_reason`, it is closed with error code `0` and no reason phrase instead of the
+/// specific code, so legitimate clients can no longer tell why they were closed either.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FingerprintResistance {
+    #[serde(default = "default::fingerprint_resistance::close_jitter")]
+    pub close_jitter: Duration,
+    #[serde(default = "default::fingerprint_resistance::conceal_close_reason")]
+    pub conceal_close_reason: bool,
+}
+
+/// The client addresses allowed to connect, checked before the connection is handled
+///
+/// Clients in a `deny` network are dropped. If `allow` is not empty, so are clients in none of its
+/// networks. Both lists are reloaded from the config file on SIGHUP.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceFilter {
+    #[serde(default, deserialize_with = "deserialize_vec_from_str")]
+    pub allow: Vec<Cidr>,
+    #[serde(default, deserialize_with = "deserialize_vec_from_str")]
+    pub deny: Vec<Cidr>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Acl {
+    #[serde(
+        default = "default::acl::deny_ips",
+        deserialize_with = "deserialize_vec_from_str"
+    )]
+    pub deny_ips: Vec<Cidr>,
+    #[serde(default = "default::acl::allow_ports")]
+    pub allow_ports: Vec<u16>,
+    #[serde(default = "default::acl::deny_domains")]
+    pub deny_domains: Vec<String>,
+    /// The level denied destinations are logged at, with the user and the rule that matched.
+    /// `off` disables the log.
+    #[serde(default = "default::acl::log_level")]
+    pub log_level: LevelFilter,
+    /// Also logs the TCP relays that passed the ACL, at the same level
+    #[serde(default = "default::acl::log_allowed")]
+    pub log_allowed: bool,
 }
 
 impl Config {
     pub fn parse(args: ArgsOs) -> Result<Self, ConfigError> {
         let mut parser = Parser::from_iter(args);
         let mut path = None;
+        let mut check = false;
 
         while let Some(arg) = parser.next()? {
             match arg {
-                Arg::Short('c') | Arg::Long("config") => {
-                    if path.is_none() {
-                        path = Some(parser.value()?);
-                    } else {
-                        return Err(ConfigError::Argument(arg.unexpected()));
-                    }
+                Arg::Short('c') | Arg::Long("config") if path.is_none() => {
+                    path = Some(parser.value()?);
                 }
+                Arg::Long("check") => check = true,
                 Arg::Short('v') | Arg::Long("version") => {
                     return Err(ConfigError::Version(env!("CARGO_PKG_VERSION")))
                 }
@@ -75,20 +372,133 @@ impl Config {
             }
         }
 
-        if path.is_none() {
+        let Some(path) = path else {
             return Err(ConfigError::NoConfig);
+        };
+
+        let mut cfg = Self::read(Path::new(&path))?;
+        cfg.check = check;
+        Ok(cfg)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let json = fs::read_to_string(path)?;
+        let mut cfg: Self = serde_json::from_str(&json).map_err(|err| match err.classify() {
+            Category::Data => match field_at(&json, err.line(), err.column()) {
+                field if field.is_empty() => ConfigError::Serde(err),
+                field => ConfigError::Field(field, err),
+            },
+            _ => ConfigError::Serde(err),
+        })?;
+
+        if cfg.users_file.is_some() && !cfg.users.is_empty() {
+            return Err(ConfigError::UsersAndUsersFile);
         }
 
-        let file = File::open(path.unwrap())?;
-        Ok(serde_json::from_reader(file)?)
+        if cfg.users.is_empty() && cfg.users_file.is_none() && !cfg.no_auth {
+            return Err(ConfigError::NoUsers);
+        }
+
+        cfg.path = path.to_path_buf();
+        Ok(cfg)
     }
 }
 
 mod default {
-    use crate::utils::CongestionControl;
+    use super::Acl;
+    use crate::utils::{CongestionControl, OutboundBind, OutboundFamily, UdpNatMode};
     use log::LevelFilter;
     use std::time::Duration;
 
+    pub mod acl {
+        use crate::acl::{self, Cidr};
+        use log::LevelFilter;
+
+        pub fn deny_ips() -> Vec<Cidr> {
+            acl::default_deny_ips()
+        }
+
+        pub fn allow_ports() -> Vec<u16> {
+            Vec::new()
+        }
+
+        pub fn deny_domains() -> Vec<String> {
+            Vec::new()
+        }
+
+        pub fn log_level() -> LevelFilter {
+            LevelFilter::Info
+        }
+
+        pub fn log_allowed() -> bool {
+            false
+        }
+    }
+
+    pub mod proxy_protocol {
+        use crate::acl::Cidr;
+
+        pub fn destinations() -> Vec<Cidr> {
+            Vec::new()
+        }
+    }
+
+    pub mod connection_pool {
+        use std::time::Duration;
+
+        pub fn size() -> usize {
+            4
+        }
+
+        pub fn idle_timeout() -> Duration {
+            Duration::from_secs(30)
+        }
+    }
+
+    pub mod tcp_keepalive {
+        use std::time::Duration;
+
+        pub fn time() -> Duration {
+            Duration::from_secs(60)
+        }
+    }
+
+    pub mod fingerprint_resistance {
+        use std::time::Duration;
+
+        pub fn close_jitter() -> Duration {
+            Duration::from_secs(1)
+        }
+
+        pub fn conceal_close_reason() -> bool {
+            true
+        }
+    }
+
+    pub mod connect_retry {
+        use std::time::Duration;
+
+        pub fn attempts() -> u32 {
+            3
+        }
+
+        pub fn initial_backoff() -> Duration {
+            Duration::from_millis(100)
+        }
+
+        pub fn max_backoff() -> Duration {
+            Duration::from_secs(1)
+        }
+    }
+
+    pub fn outbound_bind() -> OutboundBind {
+        OutboundBind::default()
+    }
+
+    pub fn outbound_family() -> OutboundFamily {
+        OutboundFamily::Auto
+    }
+
     pub fn congestion_control() -> CongestionControl {
         CongestionControl::Cubic
     }
@@ -97,22 +507,98 @@ mod default {
         Vec::new()
     }
 
+    pub fn udp_relay() -> bool {
+        true
+    }
+
     pub fn udp_relay_ipv6() -> bool {
         true
     }
 
+    pub fn udp_nat_mode() -> UdpNatMode {
+        UdpNatMode::FullCone
+    }
+
     pub fn zero_rtt_handshake() -> bool {
         false
     }
 
+    pub fn handshake_log_level() -> LevelFilter {
+        LevelFilter::Debug
+    }
+
+    pub fn no_auth() -> bool {
+        false
+    }
+
+    pub fn strict_protocol() -> bool {
+        false
+    }
+
+    pub fn migration() -> bool {
+        true
+    }
+
+    pub fn auth_failure_delay() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    pub fn escalate_auth_failure_delay() -> bool {
+        false
+    }
+
     pub fn auth_timeout() -> Duration {
         Duration::from_secs(10)
     }
 
+    pub fn connect_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    pub fn command_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    pub fn packet_timeout() -> Duration {
+        Duration::from_secs(3)
+    }
+
+    pub fn dns_cache_size() -> usize {
+        1024
+    }
+
+    pub fn dns_cache_ttl() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub fn acl() -> Acl {
+        Acl {
+            deny_ips: acl::deny_ips(),
+            allow_ports: acl::allow_ports(),
+            deny_domains: acl::deny_domains(),
+            log_level: acl::log_level(),
+            log_allowed: acl::log_allowed(),
+        }
+    }
+
     pub fn max_idle_time() -> Duration {
         Duration::from_secs(15)
     }
 
+    // the flow-control windows match quinn's defaults, sized for 100 Mbps at 100 ms RTT
+
+    pub fn stream_receive_window() -> u64 {
+        1_250_000
+    }
+
+    pub fn receive_window() -> u64 {
+        (1 << 62) - 1
+    }
+
+    pub fn send_window() -> u64 {
+        8 * 1_250_000
+    }
+
     pub fn max_external_packet_size() -> usize {
         1500
     }
@@ -125,6 +611,54 @@ mod default {
         Duration::from_secs(15)
     }
 
+    pub fn udp_session_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub fn max_udp_session_timeout() -> Duration {
+        Duration::from_secs(600)
+    }
+
+    pub fn max_udp_sessions() -> usize {
+        256
+    }
+
+    pub fn max_tcp_relays() -> usize {
+        512
+    }
+
+    pub fn max_concurrent_uni_streams() -> u32 {
+        1024
+    }
+
+    pub fn max_concurrent_bi_streams() -> u32 {
+        1024
+    }
+
+    pub fn max_udp_packets_in_flight() -> usize {
+        1024
+    }
+
+    pub fn max_udp_download_packets_in_flight() -> usize {
+        1024
+    }
+
+    pub fn tcp_nodelay() -> bool {
+        true
+    }
+
+    pub fn relay_buffer_size() -> usize {
+        16 * 1024
+    }
+
+    pub fn quota_save_interval() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub fn shutdown_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
     pub fn log_level() -> LevelFilter {
         LevelFilter::Warn
     }
@@ -140,17 +674,105 @@ where
     T::from_str(&s).map_err(DeError::custom)
 }
 
-pub fn deserialize_users<'de, D>(deserializer: D) -> Result<HashMap<Uuid, String>, D::Error>
+pub fn deserialize_option_from_str<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
 where
+    T: FromStr,
+    <T as FromStr>::Err: Display,
     D: Deserializer<'de>,
 {
-    let map = HashMap::<Uuid, String>::deserialize(deserializer)?;
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| T::from_str(&s).map_err(DeError::custom))
+        .transpose()
+}
+
+pub fn deserialize_vec_from_str<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    T: FromStr,
+    <T as FromStr>::Err: Display,
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| T::from_str(s).map_err(DeError::custom))
+        .collect()
+}
+
+pub fn deserialize_outbound_bind<'de, D>(deserializer: D) -> Result<OutboundBind, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut bind = OutboundBind::default();
+
+    for addr in Vec::<IpAddr>::deserialize(deserializer)? {
+        match addr {
+            IpAddr::V4(addr) if bind.v4.is_none() => bind.v4 = Some(addr),
+            IpAddr::V6(addr) if bind.v6.is_none() => bind.v6 = Some(addr),
+            _ => {
+                return Err(DeError::custom(
+                    "at most one outbound bind address per address family",
+                ))
+            }
+        }
+    }
+
+    Ok(bind)
+}
+
+/// Accepts either a single listen address or a non-empty list of them
+pub fn deserialize_listen_addrs<'de, D>(deserializer: D) -> Result<Vec<SocketAddr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ListenAddrs {
+        One(SocketAddr),
+        Many(Vec<SocketAddr>),
+    }
+
+    let addrs = match ListenAddrs::deserialize(deserializer)? {
+        ListenAddrs::One(addr) => vec![addr],
+        ListenAddrs::Many(addrs) => addrs,
+    };
+
+    if addrs.is_empty() {
+        return Err(DeError::custom("server cannot be empty"));
+    }
+
+    Ok(addrs)
+}
 
-    if map.is_empty() {
-        return Err(DeError::custom("users cannot be empty"));
+pub fn deserialize_users<'de, D>(deserializer: D) -> Result<HashMap<Uuid, User>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum UserEntry {
+        Password(String),
+        User(User),
     }
 
-    Ok(map)
+    let map = HashMap::<Uuid, UserEntry>::deserialize(deserializer)?;
+
+    Ok(map
+        .into_iter()
+        .map(|(uuid, entry)| match entry {
+            UserEntry::Password(password) => (
+                uuid,
+                User {
+                    password,
+                    quota: None,
+                    outbound_bind: OutboundBind::default(),
+                    outbound_mark: None,
+                    udp_nat_mode: None,
+                    udp_relay_mode: None,
+                    max_connections: None,
+                },
+            ),
+            UserEntry::User(user) => (uuid, user),
+        })
+        .collect())
 }
 
 #[derive(Debug, Error)]
@@ -159,6 +781,10 @@ pub enum ConfigError {
     Argument(#[from] ArgumentError),
     #[error("no config file specified")]
     NoConfig,
+    #[error("users cannot be empty unless no_auth is enabled")]
+    NoUsers,
+    #[error("users and users_file cannot both be set")]
+    UsersAndUsersFile,
     #[error("{0}")]
     Version(&'static str),
     #[error("{0}")]
@@ -167,4 +793,82 @@ pub enum ConfigError {
     Io(#[from] IoError),
     #[error(transparent)]
     Serde(#[from] SerdeError),
+    #[error("invalid `{0}`: {1}")]
+    Field(String, SerdeError),
+}
+
+/// Returns the path, e.g. `acl.deny_ips[1]`, of the value being parsed at `line` and `column` of
+/// the JSON, where serde_json reports an invalid value, or an empty string at the top level
+fn field_at(json: &str, line: usize, column: usize) -> String {
+    enum Scope {
+        Object { key: Option<String>, in_value: bool },
+        Array(usize),
+    }
+
+    let offset = json
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>()
+        + column;
+
+    let mut bytes = json.as_bytes()[..offset.min(json.len())].iter();
+    let mut scopes = Vec::new();
+
+    while let Some(b) = bytes.next() {
+        match b {
+            b'{' => scopes.push(Scope::Object {
+                key: None,
+                in_value: false,
+            }),
+            b'[' => scopes.push(Scope::Array(0)),
+            b'}' | b']' => {
+                scopes.pop();
+            }
+            b':' => {
+                if let Some(Scope::Object { in_value, .. }) = scopes.last_mut() {
+                    *in_value = true;
+                }
+            }
+            b',' => match scopes.last_mut() {
+                Some(Scope::Object { in_value, .. }) => *in_value = false,
+                Some(Scope::Array(idx)) => *idx += 1,
+                None => {}
+            },
+            b'"' => {
+                let mut s = Vec::new();
+
+                while let Some(&b) = bytes.next() {
+                    match b {
+                        b'"' => break,
+                        b'\\' => s.extend(bytes.next()),
+                        b => s.push(b),
+                    }
+                }
+
+                if let Some(Scope::Object { key, in_value }) = scopes.last_mut() {
+                    if !*in_value {
+                        *key = Some(String::from_utf8_lossy(&s).into_owned());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut path = String::new();
+
+    for scope in scopes {
+        match scope {
+            Scope::Object { key: Some(key), .. } if path.is_empty() => path.push_str(&key),
+            Scope::Object { key: Some(key), .. } => {
+                path.push('.');
+                path.push_str(&key);
+            }
+            Scope::Object { key: None, .. } => {}
+            Scope::Array(idx) => path.push_str(&format!("[{idx}]")),
+        }
+    }
+
+    path
 }
@@ -0,0 +1,98 @@
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    time::Duration,
+    vec::IntoIter,
+};
+use tokio::{net, time::Instant};
+use tuic::Address;
+
+/// An in-memory cache of resolved domain names, shared by all connections
+///
+/// Entries expire after a fixed TTL, as `lookup_host` does not expose the TTL of DNS records. When
+/// the cache is full, the least recently used entry is evicted.
+pub struct DnsCache {
+    cache: Option<Mutex<LruCache<String, CacheEntry>>>,
+    ttl: Duration,
+}
+
+struct CacheEntry {
+    ips: Vec<IpAddr>,
+    expire_at: Instant,
+}
+
+impl DnsCache {
+    /// Creates a new cache. A `size` or `ttl` of zero disables caching.
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        let cache = NonZeroUsize::new(size)
+            .filter(|_| !ttl.is_zero())
+            .map(|size| Mutex::new(LruCache::new(size)));
+
+        Self { cache, ttl }
+    }
+
+    pub async fn resolve(&self, addr: &Address) -> Result<IntoIter<SocketAddr>, IoError> {
+        match addr {
+            Address::None => Err(IoError::new(ErrorKind::InvalidInput, "empty address")),
+            Address::DomainAddress(domain, port) => Ok(self
+                .lookup(domain)
+                .await?
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, *port))
+                .collect::<Vec<_>>()
+                .into_iter()),
+            Address::SocketAddress(addr) => Ok(vec![*addr].into_iter()),
+        }
+    }
+
+    async fn lookup(&self, domain: &str) -> Result<Vec<IpAddr>, IoError> {
+        if let Some(ip) = parse_ip_literal(domain) {
+            return Ok(vec![ip]);
+        }
+
+        let Some(cache) = &self.cache else {
+            return lookup_host(domain).await;
+        };
+
+        let domain = domain.to_ascii_lowercase();
+
+        if let Some(entry) = cache.lock().get(&domain) {
+            if entry.expire_at > Instant::now() {
+                return Ok(entry.ips.clone());
+            }
+        }
+
+        let ips = lookup_host(&domain).await?;
+
+        if !ips.is_empty() {
+            let entry = CacheEntry {
+                ips: ips.clone(),
+                expire_at: Instant::now() + self.ttl,
+            };
+
+            cache.lock().put(domain, entry);
+        }
+
+        Ok(ips)
+    }
+}
+
+/// Parses an IP address sent in place of a domain name. IPv6 addresses may be enclosed in brackets.
+pub fn parse_ip_literal(domain: &str) -> Option<IpAddr> {
+    let domain = domain
+        .strip_prefix('[')
+        .and_then(|domain| domain.strip_suffix(']'))
+        .unwrap_or(domain);
+
+    domain.parse().ok()
+}
+
+async fn lookup_host(domain: &str) -> Result<Vec<IpAddr>, IoError> {
+    Ok(net::lookup_host((domain, 0))
+        .await?
+        .map(|addr| addr.ip())
+        .collect())
+}
@@ -0,0 +1,106 @@
+//! A TUIC server, as used by the `tuic-server` binary, for embedding into other programs
+//!
+//! ```no_run
+//! # async fn run(cfg: tuic_server::config::Config) -> Result<(), tuic_server::Error> {
+//! let server = tuic_server::Server::builder(cfg).build()?;
+//! server.run().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use self::utils::{OutboundFamily, UdpRelayMode};
+use quinn::ConnectionError;
+use rustls::Error as RustlsError;
+use serde_json::Error as SerdeError;
+use std::{io::Error as IoError, net::SocketAddr, time::Duration};
+use thiserror::Error;
+use tuic::Address;
+use tuic_quinn::Error as ModelError;
+use uuid::Uuid;
+
+pub use self::server::{Builder, Server};
+
+mod acl;
+mod admin;
+pub mod auth;
+mod cert;
+pub mod config;
+mod dns;
+mod limiter;
+mod metrics;
+mod pool;
+mod proxy_protocol;
+mod quota;
+mod server;
+mod upstream;
+mod utils;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] IoError),
+    #[error(transparent)]
+    Rustls(#[from] RustlsError),
+    #[error("invalid max idle time")]
+    InvalidMaxIdleTime,
+    #[error("invalid flow control window: {0}")]
+    InvalidWindow(&'static str),
+    #[error("invalid stream limit: {0}")]
+    InvalidStreamLimit(&'static str),
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error(transparent)]
+    Model(#[from] ModelError),
+    #[error("duplicated authentication")]
+    DuplicatedAuth,
+    #[error("authentication already failed")]
+    AuthAlreadyFailed,
+    #[error("token length too short")]
+    ExportKeyingMaterial,
+    #[error("authentication failed: {0}")]
+    AuthFailed(Uuid),
+    #[error("received packet from unexpected source")]
+    UnexpectedPacketSource,
+    #[error("UDP relay disabled")]
+    UdpRelayDisabled,
+    #[error("extension command `{0}` not negotiated")]
+    ExtensionNotNegotiated(&'static str),
+    #[error("UDP relay mode {0} not allowed for this user")]
+    UdpRelayModeNotAllowed(UdpRelayMode),
+    #[error("{0} resolved to {1} but IPv6 UDP relay disabled")]
+    UdpRelayIpv6Disabled(Address, SocketAddr),
+    #[error("{0} is blocked by ACL")]
+    Forbidden(Address),
+    #[error("{0} has no address in the family of the outbound bind addresses")]
+    OutboundBindMismatch(Address),
+    #[error("{0} has no address allowed by outbound family {1}")]
+    OutboundFamilyMismatch(Address, OutboundFamily),
+    #[error("failed to send UDP packet to {0}: {1}")]
+    UdpSend(SocketAddr, IoError),
+    #[error("too many UDP sessions, limit is {0}")]
+    TooManyUdpSessions(usize),
+    #[error("too many UDP destinations in a symmetric NAT session, limit is {0}")]
+    TooManyUdpMappings(usize),
+    #[error("too many TCP relays, limit is {0}")]
+    TooManyTcpRelays(usize),
+    #[error("no command received on stream within {0:?}")]
+    CommandTimeout(Duration),
+    #[error("packet payload not received within {0:?}")]
+    PacketTimeout(Duration),
+    #[error("bound address not taken by the client within {0:?}")]
+    ResponseTimeout(Duration),
+    #[error("relay closed after reaching its maximum lifetime of {0:?}")]
+    RelayLifetimeExceeded(Duration),
+    #[error("`Bind` disabled")]
+    BindDisabled,
+    #[error("no peer connected within {0:?}")]
+    BindTimeout(Duration),
+    #[error("invalid quota file: {0}")]
+    InvalidQuotaFile(SerdeError),
+    #[error("invalid users file: {0}")]
+    InvalidUsersFile(SerdeError),
+    #[error("traffic quota exceeded: {0}")]
+    QuotaExceeded(Uuid),
+    #[error("too many connections for user {0}, limit is {1}")]
+    TooManyUserConnections(Uuid, usize),
+}
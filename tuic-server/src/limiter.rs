@@ -0,0 +1,314 @@
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::Error as IoError,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{self, Instant, Sleep},
+};
+use uuid::Uuid;
+
+/// A token bucket holding up to one second worth of bytes by default
+///
+/// Consuming more tokens than available puts the bucket into debt, and the caller should wait for
+/// the returned duration before consuming again. This lets whole reads and packets pass through
+/// without splitting them, while keeping the average rate at the limit.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<State>,
+}
+
+struct State {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `rate` bytes per second
+    pub fn new(rate: u64) -> Arc<Self> {
+        Self::with_burst(rate, rate)
+    }
+
+    /// Creates a limiter allowing `rate` tokens per second, holding up to `burst` tokens
+    pub fn with_burst(rate: u64, burst: u64) -> Arc<Self> {
+        Arc::new(Self {
+            rate: rate as f64,
+            burst: burst as f64,
+            state: Mutex::new(State {
+                tokens: burst as f64,
+                last_refill: Instant::now(),
+            }),
+        })
+    }
+
+    /// Consumes `n` bytes, returning how long to wait if the bucket is in debt
+    pub fn consume(&self, n: usize) -> Option<Duration> {
+        let mut state = self.state.lock();
+        self.refill(&mut state);
+
+        state.tokens -= n as f64;
+
+        if state.tokens < 0.0 {
+            Some(Duration::from_secs_f64(-state.tokens / self.rate))
+        } else {
+            None
+        }
+    }
+
+    /// Consumes `n` tokens only if that many are available, without going into debt
+    pub fn try_consume(&self, n: usize) -> bool {
+        let mut state = self.state.lock();
+        self.refill(&mut state);
+
+        if state.tokens >= n as f64 {
+            state.tokens -= n as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes `n` bytes, waiting until the bucket is out of debt
+    pub async fn acquire(&self, n: usize) {
+        if let Some(delay) = self.consume(n) {
+            time::sleep(delay).await;
+        }
+    }
+
+    fn refill(&self, state: &mut State) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.burst);
+        state.last_refill = now;
+    }
+}
+
+/// Caps the number of simultaneous connections from each source IP address
+pub struct IpLimiter {
+    max: usize,
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl IpLimiter {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Registers a connection from `ip`, returning `None` if the address is already at the limit.
+    /// The connection is counted until the returned guard is dropped.
+    pub fn register(self: &Arc<Self>, ip: IpAddr) -> Option<IpLimiterGuard> {
+        let mut counts = self.counts.lock();
+
+        if counts.get(&ip).is_some_and(|count| *count >= self.max) || self.max == 0 {
+            return None;
+        }
+
+        *counts.entry(ip).or_insert(0) += 1;
+
+        Some(IpLimiterGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+}
+
+pub struct IpLimiterGuard {
+    limiter: Arc<IpLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for IpLimiterGuard {
+    fn drop(&mut self) {
+        let mut counts = self.limiter.counts.lock();
+
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Caps the number of simultaneous connections of each user
+///
+/// A user may have a limit of its own, so the limit is given with every connection, falling back
+/// to the default one.
+pub struct UserLimiter {
+    default_max: Option<usize>,
+    counts: Mutex<HashMap<Uuid, usize>>,
+}
+
+impl UserLimiter {
+    pub fn new(default_max: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            default_max,
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Counts a connection of the user until the returned guard is dropped. Returns `Ok(None)` if
+    /// the user has no limit, and the limit if the user already holds that many connections.
+    pub fn register(
+        self: &Arc<Self>,
+        uuid: Uuid,
+        max: Option<usize>,
+    ) -> Result<Option<UserLimiterGuard>, usize> {
+        let Some(max) = max.or(self.default_max) else {
+            return Ok(None);
+        };
+
+        let mut counts = self.counts.lock();
+        let count = counts.get(&uuid).copied().unwrap_or(0);
+
+        if count >= max {
+            return Err(max);
+        }
+
+        counts.insert(uuid, count + 1);
+
+        Ok(Some(UserLimiterGuard {
+            limiter: self.clone(),
+            uuid,
+        }))
+    }
+}
+
+pub struct UserLimiterGuard {
+    limiter: Arc<UserLimiter>,
+    uuid: Uuid,
+}
+
+impl Drop for UserLimiterGuard {
+    fn drop(&mut self) {
+        let mut counts = self.limiter.counts.lock();
+
+        if let Some(count) = counts.get_mut(&self.uuid) {
+            *count -= 1;
+
+            if *count == 0 {
+                counts.remove(&self.uuid);
+            }
+        }
+    }
+}
+
+/// The longest a connection that failed to authenticate is held open, also how long failures are
+/// remembered for escalation
+const MAX_AUTH_FAILURE_DELAY: Duration = Duration::from_secs(60);
+
+/// Decides how long to hold a connection that failed to authenticate before closing it, so
+/// credentials can't be guessed as fast as handshakes complete
+///
+/// With escalation, the delay doubles with every failure from the same source IP address, up to
+/// `MAX_AUTH_FAILURE_DELAY`. An address is forgotten once it has not failed for that long.
+pub struct AuthFailureDelay {
+    base: Duration,
+    escalate: bool,
+    failures: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl AuthFailureDelay {
+    pub fn new(base: Duration, escalate: bool) -> Arc<Self> {
+        Arc::new(Self {
+            base,
+            escalate,
+            failures: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Records a failure from `ip`, returning how long to wait before closing the connection
+    pub fn record(&self, ip: IpAddr) -> Duration {
+        if !self.escalate {
+            return self.base;
+        }
+
+        let now = Instant::now();
+        let mut failures = self.failures.lock();
+        failures.retain(|_, (_, last)| now.duration_since(*last) < MAX_AUTH_FAILURE_DELAY);
+
+        let (count, last) = failures.entry(ip).or_insert((0, now));
+        *count = count.saturating_add(1);
+        *last = now;
+
+        self.base
+            .saturating_mul(2u32.saturating_pow(*count - 1))
+            .min(MAX_AUTH_FAILURE_DELAY)
+    }
+}
+
+/// Throttles reads from the wrapped stream. Writes are passed through.
+pub struct Limited<T> {
+    inner: T,
+    limiter: Option<Arc<RateLimiter>>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> Limited<T> {
+    pub fn new(inner: T, limiter: Option<Arc<RateLimiter>>) -> Self {
+        Self {
+            inner,
+            limiter,
+            delay: None,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Limited<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        if let Some(delay) = &mut self.delay {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = buf.filled().len() - filled;
+
+        if let Some(limiter) = &self.limiter {
+            if let Some(delay) = limiter.consume(n) {
+                self.delay = Some(Box::pin(time::sleep(delay)));
+            }
+        }
+
+        res
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Limited<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
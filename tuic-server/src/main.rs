@@ -1,19 +1,10 @@
-use self::{
+use env_logger::Builder as LoggerBuilder;
+use std::{env, future, io::Error as IoError, process};
+use tokio::signal;
+use tuic_server::{
     config::{Config, ConfigError},
-    server::Server,
+    Server,
 };
-use env_logger::Builder as LoggerBuilder;
-use quinn::ConnectionError;
-use rustls::Error as RustlsError;
-use std::{env, io::Error as IoError, net::SocketAddr, process};
-use thiserror::Error;
-use tuic::Address;
-use tuic_quinn::Error as ModelError;
-use uuid::Uuid;
-
-mod config;
-mod server;
-mod utils;
 
 #[tokio::main]
 async fn main() {
@@ -31,39 +22,47 @@ async fn main() {
 
     LoggerBuilder::new()
         .filter_level(cfg.log_level)
+        .parse_default_env()
         .format_module_path(false)
         .format_target(false)
         .init();
 
-    match Server::init(cfg) {
-        Ok(server) => server.start().await,
+    let check = cfg.check;
+
+    match Server::builder(cfg).build() {
+        Ok(_) if check => println!("config is valid"),
+        Ok(server) => {
+            tokio::select! {
+                () = server.run() => {}
+                () = async {
+                    if let Err(err) = shutdown_signal().await {
+                        log::error!("failed to listen for shutdown signal: {err}");
+                        future::pending().await
+                    }
+                } => server.shutdown().await,
+            }
+        }
         Err(err) => {
-            eprintln!("{err}");
+            log::error!("{err}");
             process::exit(1);
         }
     }
 }
 
-#[derive(Debug, Error)]
-pub enum Error {
-    #[error(transparent)]
-    Io(#[from] IoError),
-    #[error(transparent)]
-    Rustls(#[from] RustlsError),
-    #[error("invalid max idle time")]
-    InvalidMaxIdleTime,
-    #[error(transparent)]
-    Connection(#[from] ConnectionError),
-    #[error(transparent)]
-    Model(#[from] ModelError),
-    #[error("duplicated authentication")]
-    DuplicatedAuth,
-    #[error("token length too short")]
-    ExportKeyingMaterial,
-    #[error("authentication failed: {0}")]
-    AuthFailed(Uuid),
-    #[error("received packet from unexpected source")]
-    UnexpectedPacketSource,
-    #[error("{0} resolved to {1} but IPv6 UDP relay disabled")]
-    UdpRelayIpv6Disabled(Address, SocketAddr),
+/// Resolves on SIGINT, or on SIGTERM on Unix
+async fn shutdown_signal() -> Result<(), IoError> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{self, SignalKind};
+
+        let mut sigterm = unix::signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            res = signal::ctrl_c() => res,
+            _ = sigterm.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    signal::ctrl_c().await
 }
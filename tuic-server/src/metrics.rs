@@ -0,0 +1,465 @@
+use crate::quota::Usage;
+use std::{
+    fmt::Write as _,
+    io::Error as IoError,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpListener, TcpStream},
+    time::Instant,
+};
+
+pub static CONNECTIONS: Gauge = Gauge::new();
+pub static TCP_RELAYS: Gauge = Gauge::new();
+pub static UDP_SESSIONS: Gauge = Gauge::new();
+pub static UDP_PACKETS_IN_FLIGHT_UPLOAD: Gauge = Gauge::new();
+pub static UDP_PACKETS_IN_FLIGHT_DOWNLOAD: Gauge = Gauge::new();
+pub static TCP_BYTES_UPLOAD: Counter = Counter::new();
+pub static TCP_BYTES_DOWNLOAD: Counter = Counter::new();
+pub static UDP_BYTES_UPLOAD: Counter = Counter::new();
+pub static UDP_BYTES_DOWNLOAD: Counter = Counter::new();
+pub static UDP_PACKETS_DROPPED_UPLOAD: Counter = Counter::new();
+pub static UDP_PACKETS_DROPPED_DOWNLOAD: Counter = Counter::new();
+pub static UDP_PACKETS_UNRESOLVED: Counter = Counter::new();
+pub static UDP_PACKETS_OVERSIZED: Counter = Counter::new();
+pub static UDP_DATAGRAM_PACKETS_UPLOAD: Counter = Counter::new();
+pub static UDP_DATAGRAM_PACKETS_DOWNLOAD: Counter = Counter::new();
+pub static UDP_STREAM_PACKETS_UPLOAD: Counter = Counter::new();
+pub static UDP_STREAM_PACKETS_DOWNLOAD: Counter = Counter::new();
+pub static UDP_PACKETS_FRAGMENTED_UPLOAD: Counter = Counter::new();
+pub static UDP_PACKETS_FRAGMENTED_DOWNLOAD: Counter = Counter::new();
+pub static AUTH_SUCCESSES: Counter = Counter::new();
+pub static AUTH_FAILURES: Counter = Counter::new();
+pub static AUTH_TIMEOUTS: Counter = Counter::new();
+
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Marks the server as shutting down, failing the health check from now on
+pub fn set_draining() {
+    DRAINING.store(true, Ordering::Relaxed);
+}
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Increments the gauge, returning a guard that decrements it when dropped
+    pub fn track(&'static self) -> GaugeGuard {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        GaugeGuard(self)
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct GaugeGuard(&'static Gauge);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The bytes relayed for one connection, also counted in the user's usage once authenticated
+pub struct Traffic {
+    upload: AtomicU64,
+    download: AtomicU64,
+    usage: OnceLock<Arc<Usage>>,
+}
+
+impl Traffic {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            upload: AtomicU64::new(0),
+            download: AtomicU64::new(0),
+            usage: OnceLock::new(),
+        })
+    }
+
+    pub fn set_usage(&self, usage: Arc<Usage>) {
+        let _ = self.usage.set(usage);
+    }
+
+    pub fn usage(&self) -> Option<&Usage> {
+        self.usage.get().map(Arc::as_ref)
+    }
+
+    pub fn add_upload(&self, n: u64) {
+        self.upload.fetch_add(n, Ordering::Relaxed);
+
+        if let Some(usage) = self.usage() {
+            usage.add(n);
+        }
+    }
+
+    pub fn add_download(&self, n: u64) {
+        self.download.fetch_add(n, Ordering::Relaxed);
+
+        if let Some(usage) = self.usage() {
+            usage.add(n);
+        }
+    }
+
+    pub fn upload(&self) -> u64 {
+        self.upload.load(Ordering::Relaxed)
+    }
+
+    pub fn download(&self) -> u64 {
+        self.download.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the bytes read from and written to the wrapped stream, both locally and in the given
+/// counters, and optionally in a connection's traffic, where reads count as download and writes
+/// as upload
+pub struct Metered<T> {
+    inner: T,
+    read: u64,
+    written: u64,
+    read_counter: &'static Counter,
+    written_counter: &'static Counter,
+    traffic: Option<Arc<Traffic>>,
+}
+
+impl<T> Metered<T> {
+    pub fn new(
+        inner: T,
+        read_counter: &'static Counter,
+        written_counter: &'static Counter,
+    ) -> Self {
+        Self {
+            inner,
+            read: 0,
+            written: 0,
+            read_counter,
+            written_counter,
+            traffic: None,
+        }
+    }
+
+    pub fn with_traffic(mut self, traffic: Arc<Traffic>) -> Self {
+        self.traffic = Some(traffic);
+        self
+    }
+
+    /// Returns the number of bytes read from the stream
+    pub fn read(&self) -> u64 {
+        self.read
+    }
+
+    /// Returns the number of bytes written to the stream
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Metered<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = (buf.filled().len() - filled) as u64;
+        self.read += n;
+        self.read_counter.add(n);
+
+        if let Some(traffic) = &self.traffic {
+            traffic.add_download(n);
+        }
+
+        res
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Metered<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(n)) = res {
+            self.written += n as u64;
+            self.written_counter.add(n as u64);
+
+            if let Some(traffic) = &self.traffic {
+                traffic.add_upload(n as u64);
+            }
+        }
+
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Serves the metrics in the Prometheus text format at `/metrics`, and the health check at
+/// `/health` over HTTP
+///
+/// The health check responds `200 OK` while the server accepts connections and
+/// `503 Service Unavailable` once it is shutting down, with a small JSON status body.
+pub async fn serve(listener: TcpListener) {
+    STARTED_AT.get_or_init(Instant::now);
+
+    log::warn!(
+        "metrics server started, listening on {}",
+        listener.local_addr().unwrap()
+    );
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::spawn(async move {
+                    if let Err(err) = handle_request(stream).await {
+                        log::debug!("event=metrics addr={addr} {err}");
+                    }
+                });
+            }
+            Err(err) => log::warn!("event=metrics {err}"),
+        }
+    }
+}
+
+async fn handle_request(mut stream: TcpStream) -> Result<(), IoError> {
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await?;
+    let req = String::from_utf8_lossy(&buf[..n]);
+
+    let resp = match req.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let body = render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        Some("/health") => {
+            let draining = DRAINING.load(Ordering::Relaxed);
+            let status = if draining {
+                "503 Service Unavailable"
+            } else {
+                "200 OK"
+            };
+            let body = format!(
+                r#"{{"status":"{}","uptime_secs":{},"connections":{}}}"#,
+                if draining { "draining" } else { "ok" },
+                STARTED_AT.get().map_or(0, |t| t.elapsed().as_secs()),
+                CONNECTIONS.get(),
+            );
+            format!(
+                "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+    };
+
+    stream.write_all(resp.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn render() -> String {
+    let mut buf = String::new();
+
+    write_metric(
+        &mut buf,
+        "tuic_connections",
+        "gauge",
+        "Active QUIC connections",
+        &[("", CONNECTIONS.get())],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_tcp_relays",
+        "gauge",
+        "Active TCP relays",
+        &[("", TCP_RELAYS.get())],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_udp_sessions",
+        "gauge",
+        "Active UDP sessions",
+        &[("", UDP_SESSIONS.get())],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_udp_packets_in_flight",
+        "gauge",
+        "UDP packets being forwarded, counted against max_udp_packets_in_flight for uploads and max_udp_download_packets_in_flight for downloads",
+        &[
+            (
+                r#"{direction="upload"}"#,
+                UDP_PACKETS_IN_FLIGHT_UPLOAD.get(),
+            ),
+            (
+                r#"{direction="download"}"#,
+                UDP_PACKETS_IN_FLIGHT_DOWNLOAD.get(),
+            ),
+        ],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_relay_bytes_total",
+        "counter",
+        "Bytes relayed",
+        &[
+            (
+                r#"{protocol="tcp",direction="upload"}"#,
+                TCP_BYTES_UPLOAD.get(),
+            ),
+            (
+                r#"{protocol="tcp",direction="download"}"#,
+                TCP_BYTES_DOWNLOAD.get(),
+            ),
+            (
+                r#"{protocol="udp",direction="upload"}"#,
+                UDP_BYTES_UPLOAD.get(),
+            ),
+            (
+                r#"{protocol="udp",direction="download"}"#,
+                UDP_BYTES_DOWNLOAD.get(),
+            ),
+        ],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_udp_packets_dropped_total",
+        "counter",
+        "UDP packets dropped because too many were being forwarded",
+        &[
+            (r#"{direction="upload"}"#, UDP_PACKETS_DROPPED_UPLOAD.get()),
+            (
+                r#"{direction="download"}"#,
+                UDP_PACKETS_DROPPED_DOWNLOAD.get(),
+            ),
+        ],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_udp_packets_unresolved_total",
+        "counter",
+        "UDP packets dropped because their destination failed to resolve",
+        &[("", UDP_PACKETS_UNRESOLVED.get())],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_udp_packets_oversized_total",
+        "counter",
+        "UDP packets dropped because they were too large to be sent to the client",
+        &[("", UDP_PACKETS_OVERSIZED.get())],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_udp_packets_total",
+        "counter",
+        "UDP packets relayed, by how they were carried over QUIC",
+        &[
+            (
+                r#"{transport="datagram",direction="upload"}"#,
+                UDP_DATAGRAM_PACKETS_UPLOAD.get(),
+            ),
+            (
+                r#"{transport="datagram",direction="download"}"#,
+                UDP_DATAGRAM_PACKETS_DOWNLOAD.get(),
+            ),
+            (
+                r#"{transport="stream",direction="upload"}"#,
+                UDP_STREAM_PACKETS_UPLOAD.get(),
+            ),
+            (
+                r#"{transport="stream",direction="download"}"#,
+                UDP_STREAM_PACKETS_DOWNLOAD.get(),
+            ),
+        ],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_udp_packets_fragmented_total",
+        "counter",
+        "UDP packets split into several fragments, as they did not fit in one",
+        &[
+            (
+                r#"{direction="upload"}"#,
+                UDP_PACKETS_FRAGMENTED_UPLOAD.get(),
+            ),
+            (
+                r#"{direction="download"}"#,
+                UDP_PACKETS_FRAGMENTED_DOWNLOAD.get(),
+            ),
+        ],
+    );
+
+    write_metric(
+        &mut buf,
+        "tuic_auth_total",
+        "counter",
+        "Authentication attempts",
+        &[
+            (r#"{result="success"}"#, AUTH_SUCCESSES.get()),
+            (r#"{result="failure"}"#, AUTH_FAILURES.get()),
+            (r#"{result="timeout"}"#, AUTH_TIMEOUTS.get()),
+        ],
+    );
+
+    buf
+}
+
+fn write_metric(buf: &mut String, name: &str, kind: &str, help: &str, values: &[(&str, u64)]) {
+    let _ = writeln!(buf, "# HELP {name} {help}");
+    let _ = writeln!(buf, "# TYPE {name} {kind}");
+
+    for (labels, value) in values {
+        let _ = writeln!(buf, "{name}{labels} {value}");
+    }
+}
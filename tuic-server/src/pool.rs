@@ -0,0 +1,115 @@
+use crate::{config::ConnectionPool as ConnectionPoolConfig, utils::OutboundBind};
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::ErrorKind,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::TcpStream,
+    time::{self, Instant},
+};
+use tuic::Address;
+
+/// Idle TCP connections to configured destinations, established ahead of the relays using them
+///
+/// Every pooled connection serves a single relay. Taking one starts replenishing the pool, so the
+/// next relay to the same destination skips the handshake too. Connections are kept per
+/// destination, outbound bind addresses and fwmark, and are dropped once idle for longer than
+/// `idle_timeout`.
+pub struct ConnectionPool {
+    destinations: HashSet<String>,
+    size: usize,
+    idle_timeout: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+/// A destination, with the outbound bind addresses and fwmark connections to it are made with
+type Key = (String, OutboundBind, Option<u32>);
+
+#[derive(Default)]
+struct Entry {
+    idle: VecDeque<(TcpStream, Instant)>,
+    pending: usize,
+}
+
+impl ConnectionPool {
+    pub fn new(cfg: ConnectionPoolConfig) -> Self {
+        Self {
+            destinations: cfg.destinations.into_iter().collect(),
+            size: cfg.size,
+            idle_timeout: cfg.idle_timeout,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks if connections to `addr` are pooled
+    pub fn applies_to(&self, addr: &Address) -> bool {
+        self.destinations.contains(&addr.to_string())
+    }
+
+    /// Takes an idle connection to `addr`, skipping the ones that expired or were closed by the
+    /// destination in the meantime
+    pub fn take(&self, addr: &Address, bind: OutboundBind, mark: Option<u32>) -> Option<TcpStream> {
+        let mut entries = self.entries.lock();
+        let entry = entries.get_mut(&(addr.to_string(), bind, mark))?;
+
+        while let Some((stream, since)) = entry.idle.pop_front() {
+            if since.elapsed() >= self.idle_timeout {
+                continue;
+            }
+
+            // an unused connection has nothing to read, unless it was closed
+            match stream.try_read(&mut [0; 1]) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Some(stream),
+                _ => continue,
+            }
+        }
+
+        None
+    }
+
+    /// Returns how many connections to `addr` should be established to fill the pool, counting
+    /// them as pending until handed over with [`ConnectionPool::put`]
+    pub fn reserve(&self, addr: &Address, bind: OutboundBind, mark: Option<u32>) -> usize {
+        let mut entries = self.entries.lock();
+        let entry = entries.entry((addr.to_string(), bind, mark)).or_default();
+        let n = self.size.saturating_sub(entry.idle.len() + entry.pending);
+        entry.pending += n;
+        n
+    }
+
+    /// Hands over a reserved connection to `addr`, or `None` if establishing it failed
+    pub fn put(
+        &self,
+        addr: &Address,
+        bind: OutboundBind,
+        mark: Option<u32>,
+        stream: Option<TcpStream>,
+    ) {
+        let mut entries = self.entries.lock();
+        let entry = entries.entry((addr.to_string(), bind, mark)).or_default();
+        entry.pending = entry.pending.saturating_sub(1);
+
+        if let Some(stream) = stream {
+            entry.idle.push_back((stream, Instant::now()));
+        }
+    }
+
+    fn evict_idle(&self) {
+        self.entries.lock().retain(|_, entry| {
+            entry
+                .idle
+                .retain(|(_, since)| since.elapsed() < self.idle_timeout);
+            !entry.idle.is_empty() || entry.pending > 0
+        });
+    }
+
+    pub async fn evict_idle_periodically(self: Arc<Self>) {
+        loop {
+            time::sleep(self.idle_timeout).await;
+            self.evict_idle();
+        }
+    }
+}
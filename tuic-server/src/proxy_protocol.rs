@@ -0,0 +1,66 @@
+use crate::{
+    acl::{self, Cidr},
+    config::ProxyProtocol as ProxyProtocolConfig,
+};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const VERSION_COMMAND_PROXY: u8 = 0x21;
+const FAMILY_TCP_V4: u8 = 0x11;
+const FAMILY_TCP_V6: u8 = 0x21;
+
+/// Decides which relayed TCP connections are prefixed with a PROXY protocol v2 header, so the
+/// destination can see the real client address
+pub struct ProxyProtocol {
+    destinations: Vec<Cidr>,
+}
+
+impl ProxyProtocol {
+    pub fn new(cfg: ProxyProtocolConfig) -> Self {
+        Self {
+            destinations: cfg.destinations,
+        }
+    }
+
+    /// Checks if a header should be sent to the resolved destination address
+    pub fn applies_to(&self, dst: SocketAddr) -> bool {
+        let ip = acl::canonicalize(dst.ip());
+        self.destinations.is_empty() || self.destinations.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// Builds a PROXY protocol v2 header for a TCP connection from `src` to `dst`
+///
+/// If only one of the addresses is IPv4, it is sent as an IPv4-mapped IPv6 address, since the
+/// header holds a single address family.
+pub fn header(src: SocketAddr, dst: SocketAddr) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16 + 36);
+    buf.extend_from_slice(&SIGNATURE);
+    buf.push(VERSION_COMMAND_PROXY);
+
+    match (acl::canonicalize(src.ip()), acl::canonicalize(dst.ip())) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            buf.push(FAMILY_TCP_V4);
+            buf.extend_from_slice(&12u16.to_be_bytes());
+            buf.extend_from_slice(&src_ip.octets());
+            buf.extend_from_slice(&dst_ip.octets());
+        }
+        (src_ip, dst_ip) => {
+            buf.push(FAMILY_TCP_V6);
+            buf.extend_from_slice(&36u16.to_be_bytes());
+            buf.extend_from_slice(&to_ipv6(src_ip).octets());
+            buf.extend_from_slice(&to_ipv6(dst_ip).octets());
+        }
+    }
+
+    buf.extend_from_slice(&src.port().to_be_bytes());
+    buf.extend_from_slice(&dst.port().to_be_bytes());
+    buf
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
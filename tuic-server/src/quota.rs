@@ -0,0 +1,125 @@
+use crate::Error;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time;
+use uuid::Uuid;
+
+/// The bytes relayed for a user, shared by all of its connections
+pub struct Usage {
+    bytes: AtomicU64,
+    quota: AtomicU64,
+}
+
+impl Usage {
+    fn new(bytes: u64) -> Self {
+        Self {
+            bytes: AtomicU64::new(bytes),
+            quota: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    pub fn add(&self, n: u64) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Checks if the user has used up its quota. A user without a quota never does.
+    pub fn is_exceeded(&self) -> bool {
+        self.bytes() >= self.quota.load(Ordering::Relaxed)
+    }
+}
+
+/// Tracks the cumulative traffic of every user, both directions of TCP and UDP relays included
+///
+/// If a file is configured, the counters are loaded from it at startup and written back
+/// periodically and on shutdown, so quotas survive restarts. The file is a JSON object mapping
+/// UUIDs to byte counts.
+pub struct QuotaStore {
+    path: Option<PathBuf>,
+    users: Mutex<HashMap<Uuid, Arc<Usage>>>,
+}
+
+impl QuotaStore {
+    pub fn load(path: Option<PathBuf>) -> Result<Arc<Self>, Error> {
+        let users = match &path {
+            Some(path) => match fs::read(path) {
+                Ok(data) => serde_json::from_slice::<HashMap<Uuid, u64>>(&data)
+                    .map_err(Error::InvalidQuotaFile)?
+                    .into_iter()
+                    .map(|(uuid, bytes)| (uuid, Arc::new(Usage::new(bytes))))
+                    .collect(),
+                Err(err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
+                Err(err) => return Err(Error::from(err)),
+            },
+            None => HashMap::new(),
+        };
+
+        Ok(Arc::new(Self {
+            path,
+            users: Mutex::new(users),
+        }))
+    }
+
+    /// Returns the usage of `user`, applying its current quota
+    pub fn usage(&self, user: Uuid, quota: Option<u64>) -> Arc<Usage> {
+        let usage = self
+            .users
+            .lock()
+            .entry(user)
+            .or_insert_with(|| Arc::new(Usage::new(0)))
+            .clone();
+
+        usage
+            .quota
+            .store(quota.unwrap_or(u64::MAX), Ordering::Relaxed);
+
+        usage
+    }
+
+    /// Writes the counters to the file, replacing it atomically
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let users = self
+            .users
+            .lock()
+            .iter()
+            .map(|(uuid, usage)| (*uuid, usage.bytes()))
+            .collect::<HashMap<_, _>>();
+
+        let data = serde_json::to_vec(&users).map_err(Error::InvalidQuotaFile)?;
+
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub async fn save_periodically(self: Arc<Self>, interval: Duration) {
+        loop {
+            time::sleep(interval).await;
+
+            if let Err(err) = self.save() {
+                log::warn!("failed to save traffic usage: {err}");
+            }
+        }
+    }
+}
@@ -1,85 +1,210 @@
 use crate::{
-    config::Config,
-    utils::{self, CongestionControl, UdpRelayMode},
+    acl::{self, Acl, Rule, SourceFilter},
+    admin,
+    auth::{self, Authenticator, StaticUsers},
+    cert::{self, CertResolver},
+    config::{Config, ConnectRetry, FingerprintResistance, TcpKeepalive, User},
+    dns::DnsCache,
+    limiter::{
+        AuthFailureDelay, IpLimiter, IpLimiterGuard, Limited, RateLimiter, UserLimiter,
+        UserLimiterGuard,
+    },
+    metrics::{self, GaugeGuard, Metered, Traffic},
+    pool::ConnectionPool,
+    proxy_protocol::{self, ProxyProtocol},
+    quota::QuotaStore,
+    upstream::UpstreamProxy,
+    utils::{CongestionControl, Faulted, OutboundBind, OutboundFamily, UdpNatMode, UdpRelayMode},
     Error,
 };
 use bytes::Bytes;
 use crossbeam_utils::atomic::AtomicCell;
+use log::Level;
 use parking_lot::Mutex;
 use quinn::{
     congestion::{BbrConfig, CubicConfig, NewRenoConfig},
+    crypto::rustls::HandshakeData,
     Connecting, Connection as QuinnConnection, ConnectionError, Endpoint, EndpointConfig,
-    IdleTimeout, RecvStream, SendStream, ServerConfig, TokioRuntime, TransportConfig, VarInt,
+    IdleTimeout, RecvStream, SendDatagramError, SendStream, ServerConfig, TokioRuntime,
+    TransportConfig, VarInt, ZeroRttAccepted,
 };
+use rand::Rng;
 use register_count::{Counter, Register};
-use rustls::{version, ServerConfig as RustlsServerConfig};
-use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use rustls::{
+    server::AllowAnyAuthenticatedClient, version, Certificate, ServerConfig as RustlsServerConfig,
+};
+use serde::Serialize;
+use socket2::{Domain, Protocol, SockAddr, SockRef, Socket, TcpKeepalive as SockKeepalive, Type};
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::{Display, Formatter, Result as FmtResult},
     future::Future,
     io::{Error as IoError, ErrorKind},
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket as StdUdpSocket},
+    net::{
+        IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener as StdTcpListener,
+        UdpSocket as StdUdpSocket,
+    },
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, OnceLock,
     },
     task::{Context, Poll, Waker},
     time::Duration,
 };
 use tokio::{
     io::{self, AsyncWriteExt},
-    net::{self, TcpStream, UdpSocket},
-    sync::{
-        oneshot::{self, Receiver, Sender},
-        Mutex as AsyncMutex,
-    },
-    time,
+    net::{self, TcpListener, TcpSocket, TcpStream, UdpSocket},
+    sync::{mpsc, Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+    time::{self, Instant},
 };
 use tokio_util::compat::FuturesAsyncReadCompatExt;
-use tuic::Address;
-use tuic_quinn::{side, Connect, Connection as Model, Packet, Task};
+use tuic::{Address, StatsResponse, UnmarshalError, EXTENSION_COMMANDS};
+use tuic_quinn::{side, Connect, Connection as Model, Error as ModelError, Packet, Task};
 use uuid::Uuid;
 
 const DEFAULT_CONCURRENT_STREAMS: usize = 32;
+const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
 
 pub struct Server {
-    ep: Endpoint,
-    users: Arc<HashMap<Uuid, Vec<u8>>>,
-    udp_relay_ipv6: bool,
-    zero_rtt_handshake: bool,
-    auth_timeout: Duration,
-    max_external_pkt_size: usize,
-    gc_interval: Duration,
-    gc_lifetime: Duration,
+    eps: Vec<Endpoint>,
+    source_filter: Arc<SourceFilter>,
+    accept_limiter: Option<Arc<RateLimiter>>,
+    unauthed_limiter: Option<Arc<IpLimiter>>,
+    conn_limiter: Option<Arc<Semaphore>>,
+    ip_conn_limiter: Option<Arc<IpLimiter>>,
+    settings: Arc<Settings>,
+    shutdown_timeout: Duration,
+}
+
+/// Sets up a [`Server`] from a config
+pub struct Builder {
+    cfg: Config,
+    authenticator: Option<Arc<dyn Authenticator>>,
+}
+
+impl Builder {
+    /// Looks users up with `authenticator`, instead of in `users` or `users_file` of the config
+    pub fn authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Loads the certificate, binds the listen addresses and starts the background tasks the
+    /// config asks for, such as the metrics server. Must be called within a Tokio runtime.
+    ///
+    /// No connection is accepted before [`Server::run`].
+    pub fn build(self) -> Result<Server, Error> {
+        Server::init(self.cfg, self.authenticator)
+    }
 }
 
 impl Server {
-    pub fn init(cfg: Config) -> Result<Self, Error> {
-        let certs = utils::load_certs(cfg.certificate)?;
-        let priv_key = utils::load_priv_key(cfg.private_key)?;
+    pub fn builder(cfg: Config) -> Builder {
+        Builder {
+            cfg,
+            authenticator: None,
+        }
+    }
+
+    fn init(cfg: Config, authenticator: Option<Arc<dyn Authenticator>>) -> Result<Self, Error> {
+        if cfg.no_auth {
+            log::warn!("authentication is disabled, anyone can use this server as an open relay");
+        }
+
+        if !cfg.udp_relay {
+            log::warn!("UDP relay is disabled, only TCP is relayed");
+        }
+
+        let cert_resolver = CertResolver::new(cfg.certificate, cfg.private_key)?;
+
+        #[cfg(unix)]
+        cert::reload_on_sighup(cert_resolver.clone())?;
+
+        let source_filter = SourceFilter::new(cfg.source_filter);
+
+        // a config not read from a file has nothing to reload the source filter from
+        #[cfg(unix)]
+        if !cfg.path.as_os_str().is_empty() {
+            acl::reload_on_sighup(source_filter.clone(), cfg.path)?;
+        }
+
+        let authenticator: Arc<dyn Authenticator> = match (authenticator, cfg.users_file) {
+            (Some(authenticator), _) => authenticator,
+            (None, Some(path)) => {
+                let users = StaticUsers::new(StaticUsers::read_file(&path)?);
+                auth::reload_users_file(users.clone(), path)?;
+                users
+            }
+            (None, None) => StaticUsers::new(cfg.users),
+        };
 
-        let mut crypto = RustlsServerConfig::builder()
+        let crypto = RustlsServerConfig::builder()
             .with_safe_default_cipher_suites()
             .with_safe_default_kx_groups()
             .with_protocol_versions(&[&version::TLS13])
-            .unwrap()
-            .with_no_client_auth()
-            .with_single_cert(certs, priv_key)?;
+            .unwrap();
+
+        // a client without a valid certificate fails the handshake, before it can authenticate
+        let crypto = match cfg.client_ca {
+            Some(path) => crypto.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(
+                cert::load_client_ca(&path)?,
+            )),
+            None => crypto.with_no_client_auth(),
+        };
+
+        let mut crypto = crypto.with_cert_resolver(cert_resolver);
 
         crypto.alpn_protocols = cfg.alpn.into_iter().map(|alpn| alpn.into_bytes()).collect();
-        crypto.max_early_data_size = u32::MAX;
+        crypto.max_early_data_size = if cfg.zero_rtt_handshake { u32::MAX } else { 0 };
         crypto.send_half_rtt_data = cfg.zero_rtt_handshake;
 
         let mut config = ServerConfig::with_crypto(Arc::new(crypto));
+
+        // clients moving to another network keep their connection, and with it their relays and
+        // UDP sessions, once quinn has validated the new path
+        config.migration(cfg.migration);
+
         let mut tp_cfg = TransportConfig::default();
 
+        if cfg.stream_receive_window == 0 || cfg.receive_window == 0 || cfg.send_window == 0 {
+            return Err(Error::InvalidWindow("window size must not be zero"));
+        }
+
+        if cfg.stream_receive_window > cfg.receive_window {
+            return Err(Error::InvalidWindow(
+                "stream receive window larger than connection receive window",
+            ));
+        }
+
+        let stream_receive_window = VarInt::from_u64(cfg.stream_receive_window)
+            .map_err(|_| Error::InvalidWindow("stream receive window too large"))?;
+        let receive_window = VarInt::from_u64(cfg.receive_window)
+            .map_err(|_| Error::InvalidWindow("receive window too large"))?;
+
+        if cfg.max_concurrent_uni_streams == 0 || cfg.max_concurrent_bi_streams == 0 {
+            return Err(Error::InvalidStreamLimit("stream limit must not be zero"));
+        }
+
+        // connections start with fewer streams, raised up to the limits as they are used
+        let max_concurrent_bi_streams = cfg
+            .max_concurrent_bi_streams
+            .min(DEFAULT_CONCURRENT_STREAMS as u32);
+        let max_concurrent_uni_streams = cfg
+            .max_concurrent_uni_streams
+            .min(DEFAULT_CONCURRENT_STREAMS as u32);
+
         tp_cfg
-            .max_concurrent_bidi_streams(VarInt::from(DEFAULT_CONCURRENT_STREAMS as u32))
-            .max_concurrent_uni_streams(VarInt::from(DEFAULT_CONCURRENT_STREAMS as u32))
+            .max_concurrent_bidi_streams(VarInt::from(max_concurrent_bi_streams))
+            .max_concurrent_uni_streams(VarInt::from(max_concurrent_uni_streams))
             .max_idle_timeout(Some(
                 IdleTimeout::try_from(cfg.max_idle_time).map_err(|_| Error::InvalidMaxIdleTime)?,
-            ));
+            ))
+            .keep_alive_interval(cfg.keep_alive_interval)
+            .stream_receive_window(stream_receive_window)
+            .receive_window(receive_window)
+            .send_window(cfg.send_window);
 
         match cfg.congestion_control {
             CongestionControl::Cubic => {
@@ -95,115 +220,394 @@ impl Server {
 
         config.transport_config(Arc::new(tp_cfg));
 
-        let domain = match cfg.server.ip() {
-            IpAddr::V4(_) => Domain::IPV4,
-            IpAddr::V6(_) => Domain::IPV6,
-        };
+        let eps = cfg
+            .server
+            .iter()
+            .map(|addr| {
+                let domain = match addr.ip() {
+                    IpAddr::V4(_) => Domain::IPV4,
+                    IpAddr::V6(_) => Domain::IPV6,
+                };
+
+                let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
 
-        let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
+                if let Some(dual_stack) = cfg.dual_stack {
+                    socket.set_only_v6(!dual_stack)?;
+                }
 
-        if let Some(dual_stack) = cfg.dual_stack {
-            socket.set_only_v6(!dual_stack)?;
+                socket.bind(&SockAddr::from(*addr))?;
+                let socket = StdUdpSocket::from(socket);
+
+                Ok(Endpoint::new(
+                    EndpointConfig::default(),
+                    Some(config.clone()),
+                    socket,
+                    TokioRuntime,
+                )?)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some(addr) = cfg.metrics_server {
+            let listener = StdTcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            tokio::spawn(metrics::serve(TcpListener::from_std(listener)?));
         }
 
-        socket.bind(&SockAddr::from(cfg.server))?;
-        let socket = StdUdpSocket::from(socket);
+        let quotas = QuotaStore::load(cfg.quota_file)?;
+        tokio::spawn(quotas.clone().save_periodically(cfg.quota_save_interval));
 
-        let ep = Endpoint::new(
-            EndpointConfig::default(),
-            Some(config),
-            socket,
-            TokioRuntime,
-        )?;
+        let connection_pool = cfg
+            .connection_pool
+            .map(|cfg| Arc::new(ConnectionPool::new(cfg)));
 
-        let users = cfg
-            .users
-            .into_iter()
-            .map(|(uuid, password)| (uuid, password.into_bytes()))
-            .collect();
+        if let Some(pool) = &connection_pool {
+            tokio::spawn(pool.clone().evict_idle_periodically());
+        }
 
-        Ok(Self {
-            ep,
-            users: Arc::new(users),
+        let registry = Registry::new();
+
+        if let Some(admin) = cfg.admin {
+            let listener = StdTcpListener::bind(admin.server)?;
+            listener.set_nonblocking(true)?;
+            tokio::spawn(admin::serve(
+                TcpListener::from_std(listener)?,
+                admin.token,
+                registry.clone(),
+            ));
+        }
+
+        let settings = Arc::new(Settings {
+            authenticator,
+            quotas,
+            registry,
+            udp_relay: cfg.udp_relay,
             udp_relay_ipv6: cfg.udp_relay_ipv6,
+            udp_nat_mode: cfg.udp_nat_mode,
             zero_rtt_handshake: cfg.zero_rtt_handshake,
+            handshake_log_level: cfg.handshake_log_level.to_level(),
+            no_auth: cfg.no_auth,
+            strict_protocol: cfg.strict_protocol,
             auth_timeout: cfg.auth_timeout,
+            auth_failure_delay: AuthFailureDelay::new(
+                cfg.auth_failure_delay,
+                cfg.escalate_auth_failure_delay,
+            ),
+            connect_timeout: cfg.connect_timeout,
+            connect_retry: cfg.connect_retry,
+            fingerprint_resistance: cfg.fingerprint_resistance,
+            user_conn_limiter: UserLimiter::new(cfg.max_connections_per_user),
+            command_timeout: cfg.command_timeout,
+            packet_timeout: cfg.packet_timeout,
+            dns_cache: Arc::new(DnsCache::new(cfg.dns_cache_size, cfg.dns_cache_ttl)),
+            acl: Arc::new(Acl::new(cfg.acl)),
+            proxy_protocol: cfg
+                .proxy_protocol
+                .map(|cfg| Arc::new(ProxyProtocol::new(cfg))),
+            upstream_proxy: cfg
+                .upstream_proxy
+                .map(|cfg| Arc::new(UpstreamProxy::new(cfg))),
+            connection_pool,
+            outbound_bind: cfg.outbound_bind,
+            outbound_mark: cfg.outbound_mark,
+            outbound_family: cfg.outbound_family,
+            bandwidth_limit: cfg.bandwidth_limit,
             max_external_pkt_size: cfg.max_external_packet_size,
+            max_udp_sessions: cfg.max_udp_sessions,
+            max_tcp_relays: cfg.max_tcp_relays,
+            max_concurrent_uni_streams: cfg.max_concurrent_uni_streams as usize,
+            max_concurrent_bi_streams: cfg.max_concurrent_bi_streams as usize,
+            max_udp_packets_in_flight: cfg.max_udp_packets_in_flight,
+            max_udp_download_packets_in_flight: cfg.max_udp_download_packets_in_flight,
+            relay_buffer_size: cfg.relay_buffer_size,
+            tcp_nodelay: cfg.tcp_nodelay,
+            tcp_keepalive: cfg.tcp_keepalive,
+            max_relay_lifetime: cfg.max_relay_lifetime,
+            bind_timeout: cfg.bind_timeout,
             gc_interval: cfg.gc_interval,
             gc_lifetime: cfg.gc_lifetime,
+            udp_session_timeout: cfg.udp_session_timeout,
+            max_udp_session_timeout: cfg.max_udp_session_timeout,
+            path_stats_interval: cfg.path_stats_interval,
+        });
+
+        Ok(Self {
+            eps,
+            source_filter,
+            accept_limiter: cfg
+                .max_accept_rate
+                .map(|rate| RateLimiter::with_burst(rate, cfg.max_accept_burst.unwrap_or(rate))),
+            unauthed_limiter: cfg.max_unauthenticated_per_ip.map(IpLimiter::new),
+            conn_limiter: cfg.max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            ip_conn_limiter: cfg.max_connections_per_ip.map(IpLimiter::new),
+            settings,
+            shutdown_timeout: cfg.shutdown_timeout,
         })
     }
 
-    pub async fn start(&self) {
-        log::warn!(
-            "server started, listening on {}",
-            self.ep.local_addr().unwrap()
-        );
+    /// Returns the addresses the server listens on, with the ports picked by the system for
+    /// listen addresses with port `0`
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.eps
+            .iter()
+            .filter_map(|ep| ep.local_addr().ok())
+            .collect()
+    }
 
-        loop {
-            let Some(conn) = self.ep.accept().await else {
-                return;
+    /// Accepts connections on all endpoints, returning once every one of them is closed
+    pub async fn run(&self) {
+        let (tx, mut rx) = mpsc::channel(1);
+
+        for ep in &self.eps {
+            log::warn!("server started, listening on {}", ep.local_addr().unwrap());
+
+            let ep = ep.clone();
+            let tx = tx.clone();
+
+            tokio::spawn(async move {
+                while let Some(conn) = ep.accept().await {
+                    if tx.send(conn).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        drop(tx);
+
+        while let Some(conn) = rx.recv().await {
+            let addr = conn.remote_address();
+
+            // dropping the handle before the handshake completes closes the connection
+            if !self.source_filter.is_allowed(addr.ip()) {
+                log::debug!("addr={addr} connection dropped, source address not allowed");
+                continue;
+            }
+
+            if let Some(limiter) = &self.accept_limiter {
+                if !limiter.try_consume(1) {
+                    log::debug!("addr={addr} connection dropped, accept rate limit exceeded");
+                    continue;
+                }
+            }
+
+            // held until the connection handler exits
+            let conn_permit = match &self.conn_limiter {
+                Some(limiter) => match limiter.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        log::debug!("addr={addr} connection dropped, too many connections");
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let ip_conn_guard = match &self.ip_conn_limiter {
+                Some(limiter) => match limiter.register(addr.ip()) {
+                    Some(guard) => Some(guard),
+                    None => {
+                        log::debug!(
+                            "addr={addr} connection dropped, too many connections from this address"
+                        );
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            let unauthed_guard = match &self.unauthed_limiter {
+                Some(limiter) => match limiter.register(addr.ip()) {
+                    Some(guard) => Some(guard),
+                    None => {
+                        log::debug!(
+                            "addr={addr} connection dropped, too many unauthenticated connections"
+                        );
+                        continue;
+                    }
+                },
+                None => None,
             };
 
             tokio::spawn(Connection::handle(
                 conn,
-                self.users.clone(),
-                self.udp_relay_ipv6,
-                self.zero_rtt_handshake,
-                self.auth_timeout,
-                self.max_external_pkt_size,
-                self.gc_interval,
-                self.gc_lifetime,
+                (conn_permit, ip_conn_guard),
+                unauthed_guard,
+                self.settings.clone(),
             ));
         }
     }
+
+    /// Stops accepting new connections, then waits up to `shutdown_timeout` for existing
+    /// connections to finish before closing them
+    pub async fn shutdown(&self) {
+        log::warn!("shutting down, waiting for active connections to finish");
+        metrics::set_draining();
+
+        for ep in &self.eps {
+            ep.set_server_config(None);
+        }
+
+        if time::timeout(self.shutdown_timeout, self.wait_idle())
+            .await
+            .is_err()
+        {
+            log::warn!("shutdown timeout, closing active connections");
+        }
+
+        for ep in &self.eps {
+            ep.close(
+                CloseReason::ShuttingDown.code(),
+                CloseReason::ShuttingDown.as_str().as_bytes(),
+            );
+        }
+
+        self.wait_idle().await;
+
+        if let Err(err) = self.settings.quotas.save() {
+            log::warn!("failed to save traffic usage: {err}");
+        }
+    }
+
+    async fn wait_idle(&self) {
+        for ep in &self.eps {
+            ep.wait_idle().await;
+        }
+    }
+}
+
+/// What connections are served with, built once from the config and shared by all of them
+struct Settings {
+    authenticator: Arc<dyn Authenticator>,
+    quotas: Arc<QuotaStore>,
+    registry: Arc<Registry>,
+    udp_relay: bool,
+    udp_relay_ipv6: bool,
+    udp_nat_mode: UdpNatMode,
+    zero_rtt_handshake: bool,
+    handshake_log_level: Option<Level>,
+    no_auth: bool,
+    strict_protocol: bool,
+    auth_timeout: Duration,
+    auth_failure_delay: Arc<AuthFailureDelay>,
+    connect_timeout: Duration,
+    connect_retry: Option<ConnectRetry>,
+    fingerprint_resistance: Option<FingerprintResistance>,
+    user_conn_limiter: Arc<UserLimiter>,
+    command_timeout: Duration,
+    packet_timeout: Duration,
+    dns_cache: Arc<DnsCache>,
+    acl: Arc<Acl>,
+    proxy_protocol: Option<Arc<ProxyProtocol>>,
+    upstream_proxy: Option<Arc<UpstreamProxy>>,
+    connection_pool: Option<Arc<ConnectionPool>>,
+    outbound_bind: OutboundBind,
+    outbound_mark: Option<u32>,
+    outbound_family: OutboundFamily,
+    bandwidth_limit: Option<u64>,
+    max_external_pkt_size: usize,
+    max_udp_sessions: usize,
+    max_tcp_relays: usize,
+    max_concurrent_uni_streams: usize,
+    max_concurrent_bi_streams: usize,
+    max_udp_packets_in_flight: usize,
+    max_udp_download_packets_in_flight: usize,
+    relay_buffer_size: usize,
+    tcp_nodelay: bool,
+    tcp_keepalive: Option<TcpKeepalive>,
+    max_relay_lifetime: Option<Duration>,
+    bind_timeout: Option<Duration>,
+    gc_interval: Duration,
+    gc_lifetime: Duration,
+    udp_session_timeout: Duration,
+    max_udp_session_timeout: Duration,
+    path_stats_interval: Option<Duration>,
 }
 
 #[derive(Clone)]
 struct Connection {
     inner: QuinnConnection,
+    id: u64,
+    remote_addr: Arc<AtomicCell<SocketAddr>>,
     model: Model<side::Server>,
-    users: Arc<HashMap<Uuid, Vec<u8>>>,
-    udp_relay_ipv6: bool,
+    settings: Arc<Settings>,
+    traffic: Arc<Traffic>,
+    established_at: Instant,
+    close_reason: Arc<AtomicCell<Option<CloseReason>>>,
+    streams: Arc<AtomicU64>,
+    associations: Arc<AtomicU64>,
+    auth_failed: Arc<AtomicBool>,
+    // held while an `Authenticate` is checked, so attempts on concurrent streams can't race the
+    // failure of another
+    auth_lock: Arc<AsyncMutex<()>>,
     is_authed: IsAuthed,
+    // the extension flags sent with `Authenticate`, set before the connection is authenticated
+    extensions: Arc<AtomicU8>,
+    user_settings: Arc<OnceLock<Arc<User>>>,
+    zero_rtt_accepted: Arc<AsyncMutex<Option<ZeroRttAccepted>>>,
     udp_sessions: Arc<AsyncMutex<HashMap<u16, UdpSession>>>,
+    // the idle timeouts clients asked for with `Associate`, kept apart from the sessions as the
+    // command may arrive before the first packet
+    udp_session_timeouts: Arc<Mutex<HashMap<u16, Duration>>>,
     udp_relay_mode: Arc<AtomicCell<Option<UdpRelayMode>>>,
-    max_external_pkt_size: usize,
+    // released once the connection handler exits, as the connection's clones may outlive it
+    user_conn_guard: Arc<Mutex<Option<UserLimiterGuard>>>,
+    upload_limiter: Option<Arc<RateLimiter>>,
+    download_limiter: Option<Arc<RateLimiter>>,
+    tcp_relays: Arc<Semaphore>,
+    // uploads and downloads are bounded apart, so a flood from the targets can't crowd out what
+    // the client sends, or the other way round
+    udp_upload_packets: Arc<Semaphore>,
+    udp_download_packets: Arc<Semaphore>,
     remote_uni_stream_cnt: Counter,
     remote_bi_stream_cnt: Counter,
     max_concurrent_uni_streams: Arc<AtomicUsize>,
     max_concurrent_bi_streams: Arc<AtomicUsize>,
 }
 
-#[allow(clippy::too_many_arguments)]
 impl Connection {
     async fn handle(
         conn: Connecting,
-        users: Arc<HashMap<Uuid, Vec<u8>>>,
-        udp_relay_ipv6: bool,
-        zero_rtt_handshake: bool,
-        auth_timeout: Duration,
-        max_external_pkt_size: usize,
-        gc_interval: Duration,
-        gc_lifetime: Duration,
+        _limit_guards: (Option<OwnedSemaphorePermit>, Option<IpLimiterGuard>),
+        unauthed_guard: Option<IpLimiterGuard>,
+        settings: Arc<Settings>,
     ) {
-        let addr = conn.remote_address();
-
-        let conn = Self::init(
-            conn,
-            users,
-            udp_relay_ipv6,
-            zero_rtt_handshake,
-            max_external_pkt_size,
-        )
-        .await;
+        let id = settings.registry.next_id();
+        let addr = Peer {
+            id,
+            addr: conn.remote_address(),
+        };
+
+        let conn = Self::init(conn, id, settings.clone()).await;
 
         match conn {
             Ok(conn) => {
-                log::info!("[{addr}] connection established");
+                log::info!("{addr} connection established");
+
+                if let Some(level) = settings.handshake_log_level {
+                    conn.log_handshake(level).await;
+                }
+
+                let _guard = metrics::CONNECTIONS.track();
+                let _registered = settings.registry.register(conn.clone());
 
-                tokio::spawn(conn.clone().handle_auth_timeout(auth_timeout));
-                tokio::spawn(conn.clone().collect_garbage(gc_interval, gc_lifetime));
+                if settings.no_auth {
+                    tokio::spawn(conn.clone().authenticate_anonymously());
+                } else {
+                    tokio::spawn(
+                        conn.clone()
+                            .handle_auth_timeout(settings.auth_timeout, unauthed_guard),
+                    );
+                }
+                tokio::spawn(conn.clone().collect_garbage(
+                    settings.gc_interval,
+                    settings.gc_lifetime,
+                    settings.udp_session_timeout,
+                    settings.max_udp_session_timeout,
+                ));
+
+                if let Some(interval) = settings.path_stats_interval {
+                    tokio::spawn(conn.clone().log_path_stats(interval));
+                }
 
                 loop {
                     if conn.is_closed() {
@@ -214,54 +618,93 @@ impl Connection {
                         Ok(()) => {}
                         Err(err) if err.is_locally_closed() => {}
                         Err(err) if err.is_timeout_closed() => {
-                            log::debug!("[{addr}] connection timeout")
+                            log::debug!("{addr} connection timeout")
                         }
-                        Err(err) => log::warn!("[{addr}] {err}"),
+                        Err(err) => log::warn!("{addr} {err}"),
                     }
                 }
+
+                conn.dissociate_all().await;
+                conn.user_conn_guard.lock().take();
+                conn.log_close();
             }
             Err(err) if err.is_locally_closed() => unreachable!(),
-            Err(err) if err.is_timeout_closed() => log::debug!("[{addr}] connection timeout"),
-            Err(err) => log::warn!("[{addr}] {err}"),
+            Err(err) if err.is_timeout_closed() => log::debug!("{addr} connection timeout"),
+            Err(err) => log::warn!("{addr} {err}"),
         }
     }
 
-    async fn init(
-        conn: Connecting,
-        users: Arc<HashMap<Uuid, Vec<u8>>>,
-        udp_relay_ipv6: bool,
-        zero_rtt_handshake: bool,
-        max_external_pkt_size: usize,
-    ) -> Result<Self, Error> {
-        let conn = if zero_rtt_handshake {
+    async fn init(conn: Connecting, id: u64, settings: Arc<Settings>) -> Result<Self, Error> {
+        let (conn, zero_rtt_accepted) = if settings.zero_rtt_handshake {
             match conn.into_0rtt() {
-                Ok((conn, _)) => conn,
+                Ok((conn, accepted)) => (conn, Some(accepted)),
                 Err(conn) => {
-                    log::info!("0-RTT handshake failed, fallback to 1-RTT handshake");
-                    conn.await?
+                    log::info!(
+                        "addr={} 0-RTT handshake failed, fallback to 1-RTT handshake",
+                        conn.remote_address()
+                    );
+                    (conn.await?, None)
                 }
             }
         } else {
-            conn.await?
+            (conn.await?, None)
         };
 
         Ok(Self {
             inner: conn.clone(),
+            id,
+            remote_addr: Arc::new(AtomicCell::new(conn.remote_address())),
             model: Model::<side::Server>::new(conn),
-            users,
-            udp_relay_ipv6,
+            settings: settings.clone(),
+            traffic: Traffic::new(),
+            established_at: Instant::now(),
+            close_reason: Arc::new(AtomicCell::new(None)),
+            streams: Arc::new(AtomicU64::new(0)),
+            associations: Arc::new(AtomicU64::new(0)),
+            auth_failed: Arc::new(AtomicBool::new(false)),
+            auth_lock: Arc::new(AsyncMutex::new(())),
             is_authed: IsAuthed::new(),
+            extensions: Arc::new(AtomicU8::new(0)),
+            user_settings: Arc::new(OnceLock::new()),
+            zero_rtt_accepted: Arc::new(AsyncMutex::new(zero_rtt_accepted)),
             udp_sessions: Arc::new(AsyncMutex::new(HashMap::new())),
+            udp_session_timeouts: Arc::new(Mutex::new(HashMap::new())),
             udp_relay_mode: Arc::new(AtomicCell::new(None)),
-            max_external_pkt_size,
+            user_conn_guard: Arc::new(Mutex::new(None)),
+            upload_limiter: settings.bandwidth_limit.map(RateLimiter::new),
+            download_limiter: settings.bandwidth_limit.map(RateLimiter::new),
+            tcp_relays: Arc::new(Semaphore::new(settings.max_tcp_relays)),
+            udp_upload_packets: Arc::new(Semaphore::new(settings.max_udp_packets_in_flight)),
+            udp_download_packets: Arc::new(Semaphore::new(
+                settings.max_udp_download_packets_in_flight,
+            )),
             remote_uni_stream_cnt: Counter::new(),
             remote_bi_stream_cnt: Counter::new(),
-            max_concurrent_uni_streams: Arc::new(AtomicUsize::new(DEFAULT_CONCURRENT_STREAMS)),
-            max_concurrent_bi_streams: Arc::new(AtomicUsize::new(DEFAULT_CONCURRENT_STREAMS)),
+            max_concurrent_uni_streams: Arc::new(AtomicUsize::new(
+                DEFAULT_CONCURRENT_STREAMS.min(settings.max_concurrent_uni_streams),
+            )),
+            max_concurrent_bi_streams: Arc::new(AtomicUsize::new(
+                DEFAULT_CONCURRENT_STREAMS.min(settings.max_concurrent_bi_streams),
+            )),
         })
     }
 
+    /// Returns how the connection is referred to in logs
+    fn peer(&self) -> Peer {
+        Peer {
+            id: self.id,
+            addr: self.inner.remote_address(),
+        }
+    }
+
     async fn accept(&self) -> Result<(), Error> {
+        // streams already opened are left unread until the failed authentication closes the
+        // connection
+        if self.is_auth_failed() {
+            self.inner.closed().await;
+            return Ok(());
+        }
+
         tokio::select! {
             res = self.inner.accept_uni() =>
                 tokio::spawn(self.clone().handle_uni_stream(res?, self.remote_uni_stream_cnt.reg())),
@@ -271,44 +714,111 @@ impl Connection {
                 tokio::spawn(self.clone().handle_datagram(res?)),
         };
 
+        self.check_migration();
+
         Ok(())
     }
 
-    async fn handle_uni_stream(self, recv: RecvStream, _reg: Register) {
+    /// Logs the new address of the client if the connection migrated since the last check
+    fn check_migration(&self) {
         let addr = self.inner.remote_address();
-        log::debug!("[{addr}] incoming unidirectional stream");
+        let prev = self.remote_addr.swap(addr);
+
+        if prev != addr {
+            log::info!("{} from={prev} connection migrated", self.peer());
+        }
+    }
+
+    async fn handle_uni_stream(self, recv: RecvStream, _reg: Register) {
+        let addr = self.peer();
+        log::debug!("{addr} stream=uni incoming stream");
+        self.streams.fetch_add(1, Ordering::Relaxed);
 
         let max = self.max_concurrent_uni_streams.load(Ordering::Relaxed);
 
-        if self.remote_uni_stream_cnt.count() == max {
+        if self.remote_uni_stream_cnt.count() == max
+            && max < self.settings.max_concurrent_uni_streams
+        {
+            let max = (max * 2).min(self.settings.max_concurrent_uni_streams);
             self.max_concurrent_uni_streams
-                .store(max * 2, Ordering::Relaxed);
+                .store(max, Ordering::Relaxed);
 
             self.inner
-                .set_max_concurrent_uni_streams(VarInt::from((max * 2) as u32));
+                .set_max_concurrent_uni_streams(VarInt::from(max as u32));
         }
 
         async fn pre_process(conn: &Connection, recv: RecvStream) -> Result<Task, Error> {
-            let task = conn.model.accept_uni_stream(recv).await?;
+            let task = time::timeout(
+                conn.settings.command_timeout,
+                conn.model.accept_uni_stream(recv),
+            )
+            .await
+            .map_err(|_| Error::CommandTimeout(conn.settings.command_timeout))??;
+
+            // nothing is served while a failed authentication waits for its delay, or credentials
+            // could be tried again on new streams
+            if conn.is_auth_failed() {
+                return Err(Error::AuthAlreadyFailed);
+            }
+
+            // with `no_auth`, the connection is authenticated anyway and credentials are ignored
+            if let (Task::Authenticate(auth), false) = (&task, conn.settings.no_auth) {
+                let _auth_lock = conn.auth_lock.lock().await;
 
-            if let Task::Authenticate(auth) = &task {
-                if conn.is_authed() {
+                if conn.is_auth_failed() {
+                    return Err(Error::AuthAlreadyFailed);
+                } else if conn.is_authed() {
                     return Err(Error::DuplicatedAuth);
-                } else if conn
-                    .users
-                    .get(&auth.uuid())
-                    .map_or(false, |password| auth.validate(password))
+                } else if let Some(user) = conn
+                    .settings
+                    .authenticator
+                    .user(auth.uuid())
+                    .await
+                    .filter(|user| auth.validate(&user.password))
                 {
-                    conn.set_authed();
+                    conn.handshake_confirmed().await?;
+
+                    // counted only once the handshake is confirmed, so a replayed 0-RTT flight
+                    // can't take the user's connections
+                    let guard = conn
+                        .settings
+                        .user_conn_limiter
+                        .register(auth.uuid(), user.max_connections)
+                        .map_err(|max| Error::TooManyUserConnections(auth.uuid(), max))?;
+                    *conn.user_conn_guard.lock() = guard;
+
+                    conn.traffic
+                        .set_usage(conn.settings.quotas.usage(auth.uuid(), user.quota));
+                    conn.warn_unsupported_udp_relay_mode(&user);
+                    let _ = conn.user_settings.set(user);
+                    conn.extensions.store(auth.extensions(), Ordering::Relaxed);
+                    conn.set_authed(auth.uuid());
+                    metrics::AUTH_SUCCESSES.inc();
                 } else {
+                    conn.auth_failed.store(true, Ordering::Relaxed);
+                    metrics::AUTH_FAILURES.inc();
                     return Err(Error::AuthFailed(auth.uuid()));
                 }
             }
 
-            tokio::select! {
-                () = conn.authed() => {}
-                err = conn.inner.closed() => Err(err)?,
-            };
+            conn.wait_authed().await?;
+
+            match task {
+                Task::DissociateAll => conn.check_extension("dissociate_all")?,
+                Task::Associate(..) => conn.check_extension("associate")?,
+                _ => {}
+            }
+
+            // dissociating is harmless, there is just no session to drop
+            if matches!(task, Task::Packet(_) | Task::Associate(..)) {
+                if let Err(err) = conn.check_udp_relay() {
+                    if let Task::Packet(pkt) = task {
+                        pkt.reset(CloseReason::from_error(&err).code());
+                    }
+
+                    return Err(err);
+                }
+            }
 
             let same_pkt_src = matches!(task, Task::Packet(_))
                 && matches!(conn.get_udp_relay_mode(), Some(UdpRelayMode::Native));
@@ -316,56 +826,136 @@ impl Connection {
                 return Err(Error::UnexpectedPacketSource);
             }
 
+            if matches!(task, Task::Packet(_)) {
+                conn.check_udp_relay_mode(UdpRelayMode::Quic)?;
+                conn.check_quota()?;
+            }
+
             Ok(task)
         }
 
         match pre_process(&self, recv).await {
-            Ok(Task::Authenticate(auth)) => log::info!("[{addr}] authenticated as {}", auth.uuid()),
+            Ok(Task::Authenticate(auth)) if self.settings.no_auth => log::debug!(
+                "{addr} cmd=authenticate user={} ignored, authentication is disabled",
+                auth.uuid()
+            ),
+            Ok(Task::Authenticate(auth)) => {
+                log::info!("{addr} cmd=authenticate user={} authenticated", auth.uuid())
+            }
             Ok(Task::Packet(pkt)) => {
                 let assoc_id = pkt.assoc_id();
                 let pkt_id = pkt.pkt_id();
                 let frag_id = pkt.frag_id();
                 let frag_total = pkt.frag_total();
                 log::info!(
-                    "[{addr}] [packet-from-quic] [{assoc_id}] [{pkt_id}] [{frag_id}:{frag_total}]"
+                    "{addr} cmd=packet dir=upload mode=quic assoc_id={assoc_id} pkt_id={pkt_id} frag_id={frag_id} frag_total={frag_total}"
                 );
 
                 self.set_udp_relay_mode(UdpRelayMode::Quic);
+
+                // every packet has exactly one first fragment
+                if frag_id == 0 {
+                    metrics::UDP_STREAM_PACKETS_UPLOAD.inc();
+
+                    if frag_total > 1 {
+                        metrics::UDP_PACKETS_FRAGMENTED_UPLOAD.inc();
+                    }
+                }
+
+                let Ok(_permit) = self.udp_upload_packets.try_acquire() else {
+                    log::debug!(
+                        "{addr} cmd=packet dir=upload mode=quic assoc_id={assoc_id} pkt_id={pkt_id} frag_id={frag_id} frag_total={frag_total} dropped, too many packets in flight"
+                    );
+                    metrics::UDP_PACKETS_DROPPED_UPLOAD.inc();
+                    return;
+                };
+                let _in_flight = metrics::UDP_PACKETS_IN_FLIGHT_UPLOAD.track();
+
                 match self.handle_packet(pkt).await {
                     Ok(()) => {}
                     Err(err) => log::warn!(
-                        "[{addr}] [packet-from-quic] [{assoc_id}] [{pkt_id}] [{frag_id}:{frag_total}] {err}"
+                        "{addr} cmd=packet dir=upload mode=quic assoc_id={assoc_id} pkt_id={pkt_id} frag_id={frag_id} frag_total={frag_total} {err}"
                     ),
                 }
             }
             Ok(Task::Dissociate(assoc_id)) => {
-                log::info!("[{addr}] [dissociate] [{assoc_id}]");
+                log::info!("{addr} cmd=dissociate assoc_id={assoc_id}");
 
                 match self.handle_dissociate(assoc_id).await {
                     Ok(()) => {}
-                    Err(err) => log::warn!("[{addr}] [dissociate] [{assoc_id}] {err}"),
+                    Err(err) => log::warn!("{addr} cmd=dissociate assoc_id={assoc_id} {err}"),
+                }
+            }
+            Ok(Task::Associate(assoc_id, timeout)) => {
+                log::info!(
+                    "{addr} cmd=associate assoc_id={assoc_id} timeout_ms={}",
+                    timeout.map_or(0, |timeout| timeout.as_millis())
+                );
+
+                match self.handle_associate(assoc_id, timeout) {
+                    Ok(()) => {}
+                    Err(err) => log::warn!("{addr} cmd=associate assoc_id={assoc_id} {err}"),
+                }
+            }
+            Ok(Task::DissociateAll) => {
+                log::info!("{addr} cmd=dissociate_all");
+
+                match self.handle_dissociate_all().await {
+                    Ok(()) => {}
+                    Err(err) => log::warn!("{addr} cmd=dissociate_all {err}"),
                 }
             }
             Ok(_) => unreachable!(),
+            // the stream is dropped, but the connection itself did nothing wrong
+            Err(err @ Error::CommandTimeout(_)) => log::debug!("{addr} stream=uni {err}"),
+            // the client finished or gave up on the stream, as it does when tearing down
+            Err(err) if err.is_stream_closed() => log::debug!("{addr} stream=uni {err}"),
+            // the failed authentication closes the connection, this stream is just dropped
+            Err(err @ Error::AuthAlreadyFailed) => log::debug!("{addr} stream=uni {err}"),
+            // the TCP relays of the connection go on
+            Err(err @ Error::UdpRelayDisabled) => log::debug!("{addr} stream=uni {err}"),
+            Err(err) if err.is_bad_command() && !self.settings.strict_protocol => {
+                log::warn!("{addr} stream=uni {err}");
+                let reason = CloseReason::from_error(&err);
+                err.reset_stream(reason.code());
+            }
+            Err(err @ Error::AuthFailed(_)) => {
+                log::warn!("{addr} stream=uni {err}");
+
+                // the client gets no more streams while the delay runs
+                self.inner
+                    .set_max_concurrent_uni_streams(VarInt::from_u32(0));
+                self.inner
+                    .set_max_concurrent_bi_streams(VarInt::from_u32(0));
+
+                let delay = self
+                    .settings
+                    .auth_failure_delay
+                    .record(self.inner.remote_address().ip());
+                time::sleep(delay).await;
+                self.close_jittered(CloseReason::AuthFailed).await;
+            }
             Err(err) => {
-                log::warn!("[{addr}] handle unidirection stream error: {err}");
-                self.close();
+                log::warn!("{addr} stream=uni {err}");
+                self.close_jittered(CloseReason::from_error(&err)).await;
             }
         }
     }
 
     async fn handle_bi_stream(self, (send, recv): (SendStream, RecvStream), _reg: Register) {
-        let addr = self.inner.remote_address();
-        log::debug!("[{addr}] incoming bidirectional stream");
+        let addr = self.peer();
+        log::debug!("{addr} stream=bi incoming stream");
+        self.streams.fetch_add(1, Ordering::Relaxed);
 
         let max = self.max_concurrent_bi_streams.load(Ordering::Relaxed);
 
-        if self.remote_bi_stream_cnt.count() == max {
-            self.max_concurrent_bi_streams
-                .store(max * 2, Ordering::Relaxed);
+        if self.remote_bi_stream_cnt.count() == max && max < self.settings.max_concurrent_bi_streams
+        {
+            let max = (max * 2).min(self.settings.max_concurrent_bi_streams);
+            self.max_concurrent_bi_streams.store(max, Ordering::Relaxed);
 
             self.inner
-                .set_max_concurrent_bi_streams(VarInt::from((max * 2) as u32));
+                .set_max_concurrent_bi_streams(VarInt::from(max as u32));
         }
 
         async fn pre_process(
@@ -373,45 +963,111 @@ impl Connection {
             send: SendStream,
             recv: RecvStream,
         ) -> Result<Task, Error> {
-            let task = conn.model.accept_bi_stream(send, recv).await?;
-
-            tokio::select! {
-                () = conn.authed() => {}
-                err = conn.inner.closed() => Err(err)?,
+            let task = time::timeout(
+                conn.settings.command_timeout,
+                conn.model.accept_bi_stream(send, recv),
+            )
+            .await
+            .map_err(|_| Error::CommandTimeout(conn.settings.command_timeout))??;
+
+            conn.wait_authed().await?;
+
+            let extension = match &task {
+                Task::Connect(conn) if conn.is_bound() => Some("connect_bound"),
+                Task::Connect(conn) if conn.linked_assoc_id().is_some() => Some("connect_linked"),
+                Task::Connect(conn) if conn.is_bind() => Some("bind"),
+                Task::Stats(_) => Some("stats"),
+                _ => None,
             };
 
+            if let Some(Err(err)) = extension.map(|name| conn.check_extension(name)) {
+                let code = CloseReason::from_error(&err).code();
+
+                match task {
+                    Task::Connect(conn) => conn.reset(code),
+                    Task::Stats(stats) => stats.reset(code),
+                    _ => unreachable!(),
+                }
+
+                return Err(err);
+            }
+
+            conn.check_quota()?;
+
             Ok(task)
         }
 
         match pre_process(&self, send, recv).await {
             Ok(Task::Connect(conn)) => {
                 let target_addr = conn.addr().to_string();
-                log::info!("[{addr}] [connect] [{target_addr}]");
+                let linked_assoc_id = conn.linked_assoc_id();
+                let cmd = if conn.is_bind() { "bind" } else { "connect" };
+                let user = self.user().unwrap();
+                log::info!("{addr} user={user} cmd={cmd} target={target_addr}");
 
                 match self.handle_connect(conn).await {
                     Ok(()) => {}
-                    Err(err) => log::warn!("[{addr}] [connect] [{target_addr}] {err}"),
+                    Err(err) => {
+                        log::warn!("{addr} user={user} cmd={cmd} target={target_addr} {err}")
+                    }
+                }
+
+                // the UDP session tied to the relay goes with it, however the relay ended
+                if let Some(assoc_id) = linked_assoc_id {
+                    log::info!("{addr} assoc_id={assoc_id} session dropped, linked relay closed");
+                    self.model.dissociate_linked(assoc_id);
+
+                    match self.handle_dissociate(assoc_id).await {
+                        Ok(()) => {}
+                        Err(err) => log::warn!("{addr} cmd=dissociate assoc_id={assoc_id} {err}"),
+                    }
+                }
+            }
+            Ok(Task::Stats(stats)) => {
+                let user = self.user().unwrap();
+                log::debug!("{addr} user={user} cmd=stats");
+
+                let associations = self.udp_sessions.lock().await.len();
+                let resp = StatsResponse::new(
+                    self.traffic.upload(),
+                    self.traffic.download(),
+                    u16::try_from(associations).unwrap_or(u16::MAX),
+                    self.established_at.elapsed().as_secs(),
+                );
+
+                if let Err(err) = stats.respond(resp).await {
+                    log::warn!("{addr} user={user} cmd=stats {err}");
                 }
             }
             Ok(_) => unreachable!(),
+            // the stream is dropped, but the connection itself did nothing wrong
+            Err(err @ Error::CommandTimeout(_)) => log::debug!("{addr} stream=bi {err}"),
+            // the client finished or gave up on the stream, as it does when tearing down
+            Err(err) if err.is_stream_closed() => log::debug!("{addr} stream=bi {err}"),
+            Err(err) if err.is_bad_command() && !self.settings.strict_protocol => {
+                log::warn!("{addr} stream=bi {err}");
+                let reason = CloseReason::from_error(&err);
+                err.reset_stream(reason.code());
+            }
             Err(err) => {
-                log::warn!("[{addr}] handle bidirection stream error: {err}");
-                self.close();
+                log::warn!("{addr} stream=bi {err}");
+                self.close_jittered(CloseReason::from_error(&err)).await;
             }
         }
     }
 
     async fn handle_datagram(self, dg: Bytes) {
-        let addr = self.inner.remote_address();
-        log::debug!("[{addr}] incoming datagram");
+        let addr = self.peer();
+        log::debug!("{addr} stream=datagram incoming datagram");
 
         async fn pre_process(conn: &Connection, dg: Bytes) -> Result<Task, Error> {
             let task = conn.model.accept_datagram(dg)?;
 
-            tokio::select! {
-                () = conn.authed() => {}
-                err = conn.inner.closed() => Err(err)?,
-            };
+            conn.wait_authed().await?;
+
+            if matches!(task, Task::Packet(_)) {
+                conn.check_udp_relay()?;
+            }
 
             let same_pkt_src = matches!(task, Task::Packet(_))
                 && matches!(conn.get_udp_relay_mode(), Some(UdpRelayMode::Quic));
@@ -419,6 +1075,11 @@ impl Connection {
                 return Err(Error::UnexpectedPacketSource);
             }
 
+            if matches!(task, Task::Packet(_)) {
+                conn.check_udp_relay_mode(UdpRelayMode::Native)?;
+                conn.check_quota()?;
+            }
+
             Ok(task)
         }
 
@@ -429,131 +1090,960 @@ impl Connection {
                 let frag_id = pkt.frag_id();
                 let frag_total = pkt.frag_total();
                 log::info!(
-                    "[{addr}] [packet-from-native] [{assoc_id}] [{pkt_id}] [{frag_id}:{frag_total}]"
+                    "{addr} cmd=packet dir=upload mode=native assoc_id={assoc_id} pkt_id={pkt_id} frag_id={frag_id} frag_total={frag_total}"
                 );
 
                 self.set_udp_relay_mode(UdpRelayMode::Native);
+
+                // every packet has exactly one first fragment
+                if frag_id == 0 {
+                    metrics::UDP_DATAGRAM_PACKETS_UPLOAD.inc();
+
+                    if frag_total > 1 {
+                        metrics::UDP_PACKETS_FRAGMENTED_UPLOAD.inc();
+                    }
+                }
+
+                let Ok(_permit) = self.udp_upload_packets.try_acquire() else {
+                    log::debug!(
+                        "{addr} cmd=packet dir=upload mode=native assoc_id={assoc_id} pkt_id={pkt_id} frag_id={frag_id} frag_total={frag_total} dropped, too many packets in flight"
+                    );
+                    metrics::UDP_PACKETS_DROPPED_UPLOAD.inc();
+                    return;
+                };
+                let _in_flight = metrics::UDP_PACKETS_IN_FLIGHT_UPLOAD.track();
+
                 match self.handle_packet(pkt).await {
                     Ok(()) => {}
                     Err(err) => log::warn!(
-                        "[{addr}] [packet-from-native] [{assoc_id}] [{pkt_id}] [{frag_id}:{frag_total}] {err}"
+                        "{addr} cmd=packet dir=upload mode=native assoc_id={assoc_id} pkt_id={pkt_id} frag_id={frag_id} frag_total={frag_total} {err}"
                     ),
                 }
             }
-            Ok(Task::Heartbeat) => log::info!("[{addr}] [heartbeat]"),
+            Ok(Task::Heartbeat) => {
+                log::info!("{addr} cmd=heartbeat");
+                self.refresh_udp_sessions().await;
+            }
             Ok(_) => unreachable!(),
+            // the packet is dropped, the TCP relays of the connection go on
+            Err(err @ Error::UdpRelayDisabled) => log::debug!("{addr} stream=datagram {err}"),
+            Err(err) if err.is_bad_command() && !self.settings.strict_protocol => {
+                log::warn!("{addr} stream=datagram {err}");
+                let reason = CloseReason::from_error(&err);
+                err.reset_stream(reason.code());
+            }
             Err(err) => {
-                log::warn!("[{addr}] handle datagram error: {err}");
-                self.close();
+                log::warn!("{addr} stream=datagram {err}");
+                self.close_jittered(CloseReason::from_error(&err)).await;
             }
         }
     }
 
-    async fn handle_connect(&self, conn: Connect) -> Result<(), Error> {
-        let mut stream = None;
-        let mut last_err = None;
+    async fn handle_connect(&self, mut conn: Connect) -> Result<(), Error> {
+        let target_addr = conn.addr().to_string();
+        let cmd = if conn.is_bind() { "bind" } else { "connect" };
+        let start = Instant::now();
 
-        match resolve_dns(conn.addr()).await {
-            Ok(addrs) => {
-                for addr in addrs {
-                    match TcpStream::connect(addr).await {
-                        Ok(s) => {
-                            stream = Some(s);
-                            break;
-                        }
-                        Err(err) => last_err = Some(err),
-                    }
-                }
-            }
-            Err(err) => last_err = Some(err),
-        }
+        // the permit is held until the relay finishes, or while waiting for the peer of a `Bind`
+        let permit = self.tcp_relays.try_acquire();
 
-        if let Some(mut stream) = stream {
-            let mut conn = conn.compat();
-            let res = io::copy_bidirectional(&mut conn, &mut stream).await;
-            let _ = conn.shutdown().await;
-            let _ = stream.shutdown().await;
-            res?;
-            Ok(())
+        let stream = if permit.is_ok() && conn.is_bind() {
+            self.accept_peer(&mut conn).await
+        } else if permit.is_ok() {
+            self.connect_target(conn.addr()).await
         } else {
-            let _ = conn.compat().shutdown().await;
-            Err(last_err
-                .unwrap_or_else(|| IoError::new(ErrorKind::NotFound, "no address resolved")))?
-        }
-    }
-
-    async fn handle_packet(&self, pkt: Packet) -> Result<(), Error> {
-        let Some((pkt, addr, assoc_id)) = pkt.accept().await? else {
-            return Ok(());
+            Err((
+                ConnectFailure::TooManyRelays,
+                Error::TooManyTcpRelays(self.settings.max_tcp_relays),
+            ))
         };
 
-        let (socket_v4, socket_v6) = match self.udp_sessions.lock().await.entry(assoc_id) {
-            Entry::Occupied(mut entry) => {
-                let session = entry.get_mut();
-                (session.socket_v4.clone(), session.socket_v6.clone())
-            }
-            Entry::Vacant(entry) => {
-                let session = entry
-                    .insert(UdpSession::new(assoc_id, self.clone(), self.udp_relay_ipv6).await?);
-                (session.socket_v4.clone(), session.socket_v6.clone())
+        let stream = match stream {
+            Ok(mut stream) if conn.is_bound() => {
+                match self.send_bound_addr(&mut conn, &stream).await {
+                    Ok(()) => Ok(stream),
+                    Err(err) => {
+                        // the client may have gone between the command and the answer, the target
+                        // must not be left connected with nothing to relay it
+                        let _ = stream.shutdown().await;
+                        Err((ConnectFailure::ResponseFailed, err))
+                    }
+                }
             }
+            stream => stream,
         };
 
-        let Some(socket_addr) = resolve_dns(&addr).await?.next() else {
-            Err(IoError::new(ErrorKind::NotFound, "no address resolved"))?
-        };
+        let (res, status, upload, download) = match stream {
+            Ok(stream) => {
+                let _guard = metrics::TCP_RELAYS.track();
+                // an error may come from either side, and only a reset of the target should be
+                // passed on to the client as one
+                let mut stream = Faulted::new(
+                    Metered::new(
+                        Limited::new(stream, self.download_limiter.clone()),
+                        &metrics::TCP_BYTES_DOWNLOAD,
+                        &metrics::TCP_BYTES_UPLOAD,
+                    )
+                    .with_traffic(self.traffic.clone()),
+                );
+                let mut conn = Limited::new(conn.compat(), self.upload_limiter.clone());
+
+                // `copy_bidirectional` may have already finished the QUIC send stream, and
+                // finishing it twice panics in quinn. Dropping it finishes it if needed.
+                let relay = io::copy_bidirectional_with_sizes(
+                    &mut conn,
+                    &mut stream,
+                    self.settings.relay_buffer_size,
+                    self.settings.relay_buffer_size,
+                );
 
-        let socket = match socket_addr {
-            SocketAddr::V4(_) => socket_v4,
-            SocketAddr::V6(_) => {
-                socket_v6.ok_or_else(|| Error::UdpRelayIpv6Disabled(addr, socket_addr))?
+                let res = match self.settings.max_relay_lifetime {
+                    Some(lifetime) => time::timeout(lifetime, relay)
+                        .await
+                        .map_err(|_| Error::RelayLifetimeExceeded(lifetime)),
+                    None => Ok(relay.await),
+                };
+
+                let (res, status) = match res {
+                    Ok(Ok(_)) => (Ok(()), "ok"),
+                    // finishing the stream would tell the client the target closed the
+                    // connection cleanly
+                    Ok(Err(err)) if is_reset(&err) && stream.is_faulted() => {
+                        let failure = ConnectFailure::Reset;
+                        conn.into_inner().into_inner().reset(failure.code());
+                        (Err(Error::from(err)), failure.as_str())
+                    }
+                    Ok(Err(err)) if is_reset(&err) => {
+                        let _ = stream.shutdown().await;
+                        (Err(Error::from(err)), "client_reset")
+                    }
+                    Ok(Err(err)) => {
+                        let _ = stream.shutdown().await;
+                        (Err(Error::from(err)), "relay_error")
+                    }
+                    Err(err) => {
+                        let _ = stream.shutdown().await;
+                        (Err(err), "lifetime_exceeded")
+                    }
+                };
+
+                (
+                    res,
+                    status,
+                    stream.get_ref().written(),
+                    stream.get_ref().read(),
+                )
+            }
+            Err((failure, err)) => {
+                conn.reset(failure.code());
+                (Err(err), failure.as_str(), 0, 0)
             }
         };
 
-        socket.send_to(&pkt, socket_addr).await?;
+        let addr = self.peer();
+        let user = self.user().unwrap();
+        let duration = start.elapsed().as_millis();
+        log::info!(
+            target: "access",
+            "{addr} event=access user={user} cmd={cmd} target={target_addr} status={status} upload={upload} download={download} duration_ms={duration}"
+        );
 
-        Ok(())
+        res
     }
 
-    async fn handle_dissociate(&self, assoc_id: u16) -> Result<(), Error> {
-        self.udp_sessions.lock().await.remove(&assoc_id);
-        Ok(())
+    /// Answers a `ConnectBound` with the local address of the connection to the target, or the
+    /// one to the upstream proxy if there is one
+    ///
+    /// A client that stopped reading could stall this, so it has `command_timeout` to take it.
+    async fn send_bound_addr(&self, conn: &mut Connect, stream: &TcpStream) -> Result<(), Error> {
+        self.send_addr(conn, stream.local_addr()?).await
     }
 
-    async fn handle_auth_timeout(self, timeout: Duration) {
-        time::sleep(timeout).await;
+    /// Sends `addr` on the stream of `conn`, taking up to `command_timeout` as the client could
+    /// have stopped reading
+    async fn send_addr(&self, conn: &mut Connect, addr: SocketAddr) -> Result<(), Error> {
+        time::timeout(self.settings.command_timeout, conn.send_bound_addr(addr))
+            .await
+            .map_err(|_| Error::ResponseTimeout(self.settings.command_timeout))??;
 
-        if !self.is_authed() {
-            let addr = self.inner.remote_address();
-            log::warn!("[{addr}] authentication timeout");
-            self.close();
-        }
+        Ok(())
     }
 
-    async fn collect_garbage(self, gc_interval: Duration, gc_lifetime: Duration) {
-        loop {
-            time::sleep(gc_interval).await;
+    /// Listens for the peer of a `Bind`, answering it with the address listened on, then with the
+    /// address of the peer once connected
+    ///
+    /// If the `Bind` names the peer, only a connection from one of its IP addresses the ACL allows is
+    /// taken, with any port. A client not knowing the peer names an unspecified address to take the
+    /// first connection. Either way, connections from addresses the ACL denies are dropped.
+    async fn accept_peer(&self, conn: &mut Connect) -> Result<TcpStream, (ConnectFailure, Error)> {
+        let timeout = match self.settings.bind_timeout {
+            Some(timeout) if self.settings.upstream_proxy.is_none() => timeout,
+            _ => return Err((ConnectFailure::Forbidden, Error::BindDisabled)),
+        };
 
-            if self.is_closed() {
-                break;
+        let peer_ips = match conn.addr() {
+            Address::SocketAddress(addr) if addr.ip().is_unspecified() => Vec::new(),
+            addr => {
+                let resolved = self
+                    .settings
+                    .dns_cache
+                    .resolve(addr)
+                    .await
+                    .map_err(|err| (ConnectFailure::DnsFailure, Error::from(err)))?;
+
+                self.filter_resolved("bind", addr, resolved)
+                    .map_err(|err| (ConnectFailure::Forbidden, err))?
+                    .into_iter()
+                    .map(|addr| acl::canonicalize(addr.ip()))
+                    .collect()
             }
+        };
 
-            self.model.collect_garbage(gc_lifetime);
+        let (listener, bound_addr) = self
+            .bind_listener(peer_ips.first().copied())
+            .map_err(|err| (ConnectFailure::from_io_error(&err), Error::from(err)))?;
+
+        self.send_addr(conn, bound_addr)
+            .await
+            .map_err(|err| (ConnectFailure::ResponseFailed, err))?;
+
+        let accept = async {
+            loop {
+                let (stream, peer_addr) = listener.accept().await?;
+                let peer_ip = acl::canonicalize(peer_addr.ip());
+
+                if !peer_ips.is_empty() && !peer_ips.contains(&peer_ip) {
+                    log::debug!(
+                        "{addr} cmd=bind target={target} dropped connection from {peer_addr}",
+                        addr = self.peer(),
+                        target = conn.addr(),
+                    );
+                } else if let Err(rule) = self.settings.acl.check_ip(peer_ip) {
+                    self.log_acl_denied("bind", &Address::SocketAddress(peer_addr), &rule);
+                } else {
+                    return Ok::<_, IoError>((stream, peer_addr));
+                }
+            }
+        };
+
+        let (mut stream, peer_addr) = time::timeout(timeout, accept)
+            .await
+            .map_err(|_| (ConnectFailure::Timeout, Error::BindTimeout(timeout)))?
+            .map_err(|err| (ConnectFailure::from_io_error(&err), Error::from(err)))?;
+
+        self.settings.acl.log_allowed(
+            self.peer(),
+            self.user(),
+            "bind",
+            &Address::SocketAddress(peer_addr),
+        );
+
+        if let Err(err) = self.set_socket_options(&stream) {
+            log::warn!("target={peer_addr} failed to set socket options: {err}");
         }
-    }
 
-    fn set_authed(&self) {
-        self.is_authed.set_authed();
+        match self.send_addr(conn, peer_addr).await {
+            Ok(()) => Ok(stream),
+            Err(err) => {
+                let _ = stream.shutdown().await;
+                Err((ConnectFailure::ResponseFailed, err))
+            }
+        }
     }
 
-    fn is_authed(&self) -> bool {
+    /// Opens the listener of a `Bind` in the family of `peer`, returning it with the address to
+    /// answer the client with
+    ///
+    /// The listener is bound to the outbound bind address of the family if there is one, or to
+    /// every address. In the latter case, the client is answered with the address `peer` is
+    /// reached from, or the one the client reached the server at if the peer is unknown.
+    fn bind_listener(&self, peer: Option<IpAddr>) -> Result<(TcpListener, SocketAddr), IoError> {
+        let local_ip = self.inner.local_ip().map(acl::canonicalize);
+        let bind = self.outbound_bind();
+
+        let bind_ip = match peer.or(local_ip) {
+            Some(IpAddr::V6(_)) => bind
+                .v6
+                .map_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED), IpAddr::V6),
+            _ => bind
+                .v4
+                .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V4),
+        };
+
+        let listener = StdTcpListener::bind(SocketAddr::new(bind_ip, 0))?;
+        listener.set_nonblocking(true)?;
+        let mut bound_addr = listener.local_addr()?;
+
+        if bound_addr.ip().is_unspecified() {
+            let answer_ip = match peer {
+                Some(peer) => route_ip(peer),
+                None => local_ip,
+            };
+
+            if let Some(ip) = answer_ip.filter(|ip| ip.is_ipv4() == bind_ip.is_ipv4()) {
+                bound_addr.set_ip(ip);
+            }
+        }
+
+        Ok((TcpListener::from_std(listener)?, bound_addr))
+    }
+
+    async fn connect_target(&self, addr: &Address) -> Result<TcpStream, (ConnectFailure, Error)> {
+        if let Err(rule) = self.settings.acl.check_addr(addr) {
+            self.log_acl_denied("connect", addr, &rule);
+            return Err((ConnectFailure::Forbidden, Error::Forbidden(addr.clone())));
+        }
+
+        let pooled = match &self.settings.connection_pool {
+            Some(pool) if pool.applies_to(addr) => {
+                let stream = pool.take(addr, self.outbound_bind(), self.outbound_mark());
+                self.fill_pool(pool, addr);
+                stream
+            }
+            _ => None,
+        };
+
+        let mut stream = match pooled {
+            Some(stream) => stream,
+            None => self.open_target(addr).await?,
+        };
+
+        self.settings
+            .acl
+            .log_allowed(self.peer(), self.user(), "connect", addr);
+
+        if let Some(proxy_protocol) = self
+            .settings
+            .proxy_protocol
+            .as_ref()
+            .filter(|_| self.settings.upstream_proxy.is_none())
+        {
+            let dst = stream
+                .peer_addr()
+                .map_err(|err| (ConnectFailure::Unreachable, Error::from(err)))?;
+
+            if proxy_protocol.applies_to(dst) {
+                let header = proxy_protocol::header(self.inner.remote_address(), dst);
+                stream
+                    .write_all(&header)
+                    .await
+                    .map_err(|err| (ConnectFailure::Unreachable, Error::from(err)))?;
+            }
+        }
+
+        Ok(stream)
+    }
+
+    /// Establishes connections to `addr` in the background, until its pool is full
+    fn fill_pool(&self, pool: &Arc<ConnectionPool>, addr: &Address) {
+        let bind = self.outbound_bind();
+        let mark = self.outbound_mark();
+
+        for _ in 0..pool.reserve(addr, bind, mark) {
+            let conn = self.clone();
+            let pool = pool.clone();
+            let addr = addr.clone();
+
+            tokio::spawn(async move {
+                match conn.open_target(&addr).await {
+                    Ok(stream) => pool.put(&addr, bind, mark, Some(stream)),
+                    Err((_, err)) => {
+                        log::debug!("event=pool target={addr} {err}");
+                        pool.put(&addr, bind, mark, None);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Opens a TCP connection to `addr`, either directly or through the upstream proxy, and
+    /// applies the configured socket options to it
+    async fn open_target(&self, addr: &Address) -> Result<TcpStream, (ConnectFailure, Error)> {
+        let stream = match &self.settings.upstream_proxy {
+            Some(upstream_proxy) => self.connect_upstream_proxy(upstream_proxy, addr).await?,
+            None => self.connect_direct(addr).await?,
+        };
+
+        if let Err(err) = self.set_socket_options(&stream) {
+            log::warn!("target={addr} failed to set socket options: {err}");
+        }
+
+        Ok(stream)
+    }
+
+    fn set_socket_options(&self, stream: &TcpStream) -> Result<(), IoError> {
+        stream.set_nodelay(self.settings.tcp_nodelay)?;
+
+        if let Some(keepalive) = self.settings.tcp_keepalive {
+            let params = SockKeepalive::new().with_time(keepalive.time);
+
+            #[cfg(any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "linux",
+                target_vendor = "apple",
+                windows,
+            ))]
+            let params = match keepalive.interval {
+                Some(interval) => params.with_interval(interval),
+                None => params,
+            };
+
+            SockRef::from(stream).set_tcp_keepalive(&params)?;
+        }
+
+        Ok(())
+    }
+
+    async fn connect_direct(&self, addr: &Address) -> Result<TcpStream, (ConnectFailure, Error)> {
+        let addrs = self
+            .settings
+            .dns_cache
+            .resolve(addr)
+            .await
+            .map_err(|err| (ConnectFailure::DnsFailure, Error::from(err)))?;
+
+        let mut addrs = self
+            .filter_resolved("connect", addr, addrs)
+            .map_err(|err| (ConnectFailure::Forbidden, err))?;
+
+        let bind = self.outbound_bind();
+
+        if !addrs.is_empty() {
+            self.settings.outbound_family.apply(&mut addrs);
+
+            if addrs.is_empty() {
+                return Err((
+                    ConnectFailure::Unreachable,
+                    Error::OutboundFamilyMismatch(addr.clone(), self.settings.outbound_family),
+                ));
+            }
+
+            addrs.retain(|addr| bind.matches(addr.ip()));
+
+            if addrs.is_empty() {
+                return Err((
+                    ConnectFailure::Unreachable,
+                    Error::OutboundBindMismatch(addr.clone()),
+                ));
+            }
+        }
+
+        connect_tcp(
+            addrs.into_iter(),
+            bind,
+            self.outbound_mark(),
+            self.settings.connect_timeout,
+            self.settings.connect_retry,
+        )
+        .await
+        .map_err(|err| (ConnectFailure::from_io_error(&err), Error::from(err)))
+    }
+
+    /// Connects to `addr` through the upstream proxy, leaving resolving it to the proxy
+    async fn connect_upstream_proxy(
+        &self,
+        upstream_proxy: &UpstreamProxy,
+        addr: &Address,
+    ) -> Result<TcpStream, (ConnectFailure, Error)> {
+        let bind = self.outbound_bind();
+
+        let mut addrs = net::lookup_host(upstream_proxy.server())
+            .await
+            .map_err(|err| (ConnectFailure::DnsFailure, Error::from(err)))?
+            .filter(|addr| bind.matches(addr.ip()))
+            .collect();
+
+        self.settings.outbound_family.apply(&mut addrs);
+
+        let res = async {
+            let mut stream = connect_tcp(
+                addrs.into_iter(),
+                bind,
+                self.outbound_mark(),
+                self.settings.connect_timeout,
+                self.settings.connect_retry,
+            )
+            .await?;
+
+            match time::timeout(
+                self.settings.connect_timeout,
+                upstream_proxy.handshake(&mut stream, addr),
+            )
+            .await
+            {
+                Ok(res) => res.map(|()| stream),
+                Err(_) => Err(IoError::new(
+                    ErrorKind::TimedOut,
+                    "upstream proxy handshake timed out",
+                )),
+            }
+        };
+
+        res.await
+            .map_err(|err| (ConnectFailure::from_io_error(&err), Error::from(err)))
+    }
+
+    fn log_acl_denied(&self, cmd: &str, addr: &Address, rule: &Rule) {
+        self.settings
+            .acl
+            .log_denied(self.peer(), self.user(), cmd, addr, rule);
+    }
+
+    /// Drops resolved addresses blocked by the ACL or not relayable, failing if all of them are
+    /// dropped
+    fn filter_resolved(
+        &self,
+        cmd: &str,
+        addr: &Address,
+        resolved: impl Iterator<Item = SocketAddr>,
+    ) -> Result<Vec<SocketAddr>, Error> {
+        let mut denied_by = None;
+
+        let allowed = resolved
+            .filter(|socket_addr| {
+                let res = self.settings.acl.check_ip(socket_addr.ip()).and_then(|()| {
+                    if is_ipv6_link_local(socket_addr.ip()) {
+                        Err(Rule::Ipv6LinkLocal)
+                    } else {
+                        Ok(())
+                    }
+                });
+
+                match res {
+                    Ok(()) => true,
+                    Err(rule) => {
+                        denied_by.get_or_insert(rule);
+                        false
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        match denied_by {
+            Some(rule) if allowed.is_empty() => {
+                self.log_acl_denied(cmd, addr, &rule);
+                Err(Error::Forbidden(addr.clone()))
+            }
+            _ => Ok(allowed),
+        }
+    }
+
+    async fn handle_packet(&self, pkt: Packet) -> Result<(), Error> {
+        // over a stream, the payload follows the header and a client could trickle it, holding a
+        // task and the buffer. Dropping the packet on timeout aborts the stream.
+        let pkt = time::timeout(self.settings.packet_timeout, pkt.accept())
+            .await
+            .map_err(|_| Error::PacketTimeout(self.settings.packet_timeout))?;
+
+        let Some((pkt, addr, assoc_id)) = pkt? else {
+            return Ok(());
+        };
+
+        if let Err(rule) = self.settings.acl.check_addr(&addr) {
+            self.log_acl_denied("packet", &addr, &rule);
+            return Err(Error::Forbidden(addr));
+        }
+
+        let mut udp_sessions = self.udp_sessions.lock().await;
+        let udp_session_cnt = udp_sessions.len();
+
+        let session = match udp_sessions.entry(assoc_id) {
+            Entry::Occupied(entry) => {
+                let session = entry.into_mut();
+                session.last_activity.store(Instant::now());
+                session
+            }
+            // torn down already, see `Connection::dissociate_all`
+            Entry::Vacant(_) if self.is_closed() => return Ok(()),
+            Entry::Vacant(_) if udp_session_cnt >= self.settings.max_udp_sessions => {
+                return Err(Error::TooManyUdpSessions(self.settings.max_udp_sessions));
+            }
+            Entry::Vacant(entry) => {
+                let session = UdpSession::new(
+                    assoc_id,
+                    self.clone(),
+                    self.settings.udp_relay_ipv6,
+                    self.udp_nat_mode(),
+                )?;
+                self.associations.fetch_add(1, Ordering::Relaxed);
+                entry.insert(session)
+            }
+        };
+
+        let (socket_v4, socket_v6, peers) = (
+            session.socket_v4.clone(),
+            session.socket_v6.clone(),
+            session.peers.clone(),
+        );
+
+        drop(udp_sessions);
+
+        // a domain that fails to resolve costs only the packet, not the association
+        let resolved = match self.settings.dns_cache.resolve(&addr).await {
+            Ok(resolved) => resolved,
+            Err(err) => {
+                log::debug!(
+                    "{remote} cmd=packet dir=upload assoc_id={assoc_id} target={addr} dropped, failed to resolve: {err}",
+                    remote = self.peer()
+                );
+                metrics::UDP_PACKETS_UNRESOLVED.inc();
+                return Ok(());
+            }
+        };
+
+        let mut resolved = self.filter_resolved("packet", &addr, resolved)?;
+        self.settings.outbound_family.apply(&mut resolved);
+
+        let Some(socket_addr) = resolved.into_iter().next() else {
+            log::debug!(
+                "{remote} cmd=packet dir=upload assoc_id={assoc_id} target={addr} dropped, resolved to no usable address",
+                remote = self.peer()
+            );
+            metrics::UDP_PACKETS_UNRESOLVED.inc();
+            return Ok(());
+        };
+
+        let socket = match socket_addr {
+            SocketAddr::V4(_) => socket_v4,
+            SocketAddr::V6(_) if !self.settings.udp_relay_ipv6 => {
+                return Err(Error::UdpRelayIpv6Disabled(addr, socket_addr));
+            }
+            SocketAddr::V6(_) => socket_v6,
+        };
+
+        // without a shared socket, the session is symmetric and each destination gets its own
+        let socket = match socket {
+            Some(socket) => socket,
+            None => {
+                let mut udp_sessions = self.udp_sessions.lock().await;
+
+                // dissociated while resolving
+                let Some(session) = udp_sessions.get_mut(&assoc_id) else {
+                    log::debug!(
+                        "{addr} cmd=packet dir=upload assoc_id={assoc_id} dropped, session dissociated",
+                        addr = self.peer()
+                    );
+                    return Ok(());
+                };
+
+                session.mapping(self, socket_addr).await?
+            }
+        };
+
+        if let Some(peers) = peers {
+            peers.lock().insert(socket_addr);
+        }
+
+        if let Some(limiter) = &self.upload_limiter {
+            limiter.acquire(pkt.len()).await;
+        }
+
+        socket
+            .send_to(&pkt, socket_addr)
+            .await
+            .map_err(|err| Error::UdpSend(socket_addr, err))?;
+        metrics::UDP_BYTES_UPLOAD.add(pkt.len() as u64);
+
+        self.traffic.add_upload(pkt.len() as u64);
+
+        Ok(())
+    }
+
+    /// Sets the idle timeout of the UDP session `assoc_id`, back to `udp_session_timeout` if
+    /// `None`. The session may not have started yet.
+    fn handle_associate(&self, assoc_id: u16, timeout: Option<Duration>) -> Result<(), Error> {
+        let mut timeouts = self.udp_session_timeouts.lock();
+
+        let Some(timeout) = timeout else {
+            timeouts.remove(&assoc_id);
+            return Ok(());
+        };
+
+        if !timeouts.contains_key(&assoc_id) && timeouts.len() >= self.settings.max_udp_sessions {
+            return Err(Error::TooManyUdpSessions(self.settings.max_udp_sessions));
+        }
+
+        timeouts.insert(assoc_id, timeout);
+        Ok(())
+    }
+
+    async fn handle_dissociate(&self, assoc_id: u16) -> Result<(), Error> {
+        self.udp_sessions.lock().await.remove(&assoc_id);
+        self.udp_session_timeouts.lock().remove(&assoc_id);
+        Ok(())
+    }
+
+    /// Drops every UDP session at the client's request, keeping the connection open
+    ///
+    /// Packets arriving afterwards start new sessions, as they would after a `Dissociate`.
+    async fn handle_dissociate_all(&self) -> Result<(), Error> {
+        let addr = self.peer();
+        let mut udp_sessions = self.udp_sessions.lock().await;
+
+        for assoc_id in udp_sessions.keys() {
+            log::debug!("{addr} cmd=dissociate_all assoc_id={assoc_id}");
+        }
+
+        udp_sessions.clear();
+        self.udp_session_timeouts.lock().clear();
+        Ok(())
+    }
+
+    /// Drops every UDP session of the closed connection, releasing their sockets
+    ///
+    /// The listening tasks of UDP sessions hold clones of the connection, so the sessions have to
+    /// be dropped explicitly. This runs once the connection handler exits, whichever way the
+    /// connection was closed, and no session is created after that.
+    async fn dissociate_all(&self) {
+        let addr = self.peer();
+        let mut udp_sessions = self.udp_sessions.lock().await;
+
+        for assoc_id in udp_sessions.keys() {
+            log::info!("{addr} assoc_id={assoc_id} session dropped, connection closed");
+        }
+
+        udp_sessions.clear();
+    }
+
+    /// Closes the connection if it is not authenticated in time. The connection counts as
+    /// unauthenticated for its source address until this returns.
+    async fn handle_auth_timeout(self, timeout: Duration, _unauthed_guard: Option<IpLimiterGuard>) {
+        tokio::select! {
+            () = self.authed() => {}
+            _ = self.inner.closed() => {}
+            () = time::sleep(timeout) => {
+                // a failed authentication is closed as such once its delay is over
+                if !self.is_authed() && !self.is_auth_failed() {
+                    let addr = self.peer();
+                    log::warn!("{addr} authentication timeout");
+                    metrics::AUTH_TIMEOUTS.inc();
+                    self.close_jittered(CloseReason::AuthTimeout).await;
+                }
+            }
+        }
+    }
+
+    /// Marks the connection as authenticated without credentials once the handshake is
+    /// complete, for `no_auth`. The nil UUID stands in for the user.
+    async fn authenticate_anonymously(self) {
+        if self.handshake_confirmed().await.is_ok() {
+            // nothing is negotiated without an `Authenticate`, so every extension is served
+            self.extensions.store(EXTENSION_COMMANDS, Ordering::Relaxed);
+            self.set_authed(Uuid::nil());
+        }
+    }
+
+    async fn collect_garbage(
+        self,
+        gc_interval: Duration,
+        gc_lifetime: Duration,
+        udp_session_timeout: Duration,
+        max_udp_session_timeout: Duration,
+    ) {
+        loop {
+            time::sleep(gc_interval).await;
+
+            if self.is_closed() {
+                break;
+            }
+
+            self.model.collect_garbage(gc_lifetime);
+            self.collect_idle_udp_sessions(udp_session_timeout, max_udp_session_timeout)
+                .await;
+        }
+    }
+
+    /// Logs what was negotiated in the TLS handshake
+    ///
+    /// QUIC always runs TLS 1.3, and quinn doesn't expose the cipher suite it settled on.
+    async fn log_handshake(&self, level: Level) {
+        let data = self
+            .inner
+            .handshake_data()
+            .and_then(|data| data.downcast::<HandshakeData>().ok());
+
+        let (alpn, sni) = match data {
+            Some(data) => (
+                data.protocol
+                    .map(|alpn| String::from_utf8_lossy(&alpn).into_owned()),
+                data.server_name,
+            ),
+            None => (None, None),
+        };
+
+        let client_certs = self
+            .inner
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<Certificate>>().ok())
+            .map_or(0, |certs| certs.len());
+
+        log::log!(
+            level,
+            "{addr} event=handshake alpn={alpn} sni={sni} zero_rtt={zero_rtt} client_certs={client_certs}",
+            addr = self.peer(),
+            alpn = alpn.as_deref().unwrap_or("-"),
+            sni = sni.as_deref().unwrap_or("-"),
+            zero_rtt = self.zero_rtt_accepted.lock().await.is_some(),
+        );
+    }
+
+    /// Logs the round-trip time, congestion window and packet loss of the connection's current
+    /// path every `interval`
+    async fn log_path_stats(self, interval: Duration) {
+        loop {
+            time::sleep(interval).await;
+
+            if self.is_closed() {
+                break;
+            }
+
+            let stats = self.inner.stats().path;
+
+            log::info!(
+                "{addr} event=path rtt_ms={rtt} cwnd={cwnd} sent_packets={sent} lost_packets={lost} lost_bytes={lost_bytes} congestion_events={congestion_events}",
+                addr = self.peer(),
+                rtt = stats.rtt.as_millis(),
+                cwnd = stats.cwnd,
+                sent = stats.sent_packets,
+                lost = stats.lost_packets,
+                lost_bytes = stats.lost_bytes,
+                congestion_events = stats.congestion_events,
+            );
+        }
+    }
+
+    /// Marks every UDP session as active, so a client that is still around keeps its idle
+    /// sessions past `udp_session_timeout` by sending heartbeats
+    async fn refresh_udp_sessions(&self) {
+        let now = Instant::now();
+
+        for session in self.udp_sessions.lock().await.values() {
+            session.last_activity.store(now);
+        }
+    }
+
+    /// Drops the UDP sessions idle for longer than their timeout, the one the client asked for
+    /// capped at `max_timeout`, or `default_timeout`
+    async fn collect_idle_udp_sessions(&self, default_timeout: Duration, max_timeout: Duration) {
+        let addr = self.peer();
+        let mut udp_sessions = self.udp_sessions.lock().await;
+        let mut timeouts = self.udp_session_timeouts.lock();
+
+        // dropping a session only cancels its listening tasks, packets already being sent keep
+        // their own reference to the socket
+        udp_sessions.retain(|assoc_id, session| {
+            let timeout = timeouts
+                .get(assoc_id)
+                .map_or(default_timeout, |timeout| (*timeout).min(max_timeout));
+            let is_alive = session.last_activity.load().elapsed() < timeout;
+
+            if !is_alive {
+                log::info!("{addr} assoc_id={assoc_id} session dropped, idle timeout");
+                timeouts.remove(assoc_id);
+            }
+
+            is_alive
+        });
+    }
+
+    /// Waits for the handshake to complete if the connection was accepted with 0-RTT
+    ///
+    /// 0-RTT data is not protected against replay, so an attacker could resend a captured first
+    /// flight, including the `Authenticate` and `Connect` commands in it, to make the server
+    /// open the same relays again. The authentication is validated on arrival, but the connection
+    /// is only marked as authenticated, which releases all relay tasks waiting for it, once the
+    /// client has finished the handshake. A replayed flight never gets that far.
+    async fn handshake_confirmed(&self) -> Result<(), Error> {
+        if let Some(accepted) = self.zero_rtt_accepted.lock().await.take() {
+            accepted.await;
+        }
+
+        match self.inner.close_reason() {
+            Some(err) => Err(Error::Connection(err)),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails if the authenticated user has used up its traffic quota. Relays already running are
+    /// not interrupted.
+    fn check_quota(&self) -> Result<(), Error> {
+        match self.traffic.usage() {
+            Some(usage) if usage.is_exceeded() => Err(Error::QuotaExceeded(self.user().unwrap())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the authenticated user's UDP NAT mode, or the global one if it has none
+    fn udp_nat_mode(&self) -> UdpNatMode {
+        self.user_settings
+            .get()
+            .and_then(|user| user.udp_nat_mode)
+            .unwrap_or(self.settings.udp_nat_mode)
+    }
+
+    /// Returns the authenticated user's outbound bind addresses, or the global ones if it has none
+    fn outbound_bind(&self) -> OutboundBind {
+        self.user_settings
+            .get()
+            .map(|user| user.outbound_bind)
+            .filter(|bind| !bind.is_empty())
+            .unwrap_or(self.settings.outbound_bind)
+    }
+
+    /// Returns the authenticated user's outbound fwmark, or the global one if it has none
+    fn outbound_mark(&self) -> Option<u32> {
+        self.user_settings
+            .get()
+            .and_then(|user| user.outbound_mark)
+            .or(self.settings.outbound_mark)
+    }
+
+    fn set_authed(&self, user: Uuid) {
+        self.is_authed.set_authed(user);
+    }
+
+    /// Fails unless the client set `EXTENSION_COMMANDS` when authenticating, for the extension
+    /// command `name`
+    fn check_extension(&self, name: &'static str) -> Result<(), Error> {
+        if self.extensions.load(Ordering::Relaxed) & EXTENSION_COMMANDS == 0 {
+            return Err(Error::ExtensionNotNegotiated(name));
+        }
+
+        Ok(())
+    }
+
+    fn is_auth_failed(&self) -> bool {
+        self.auth_failed.load(Ordering::Relaxed)
+    }
+
+    fn is_authed(&self) -> bool {
         self.is_authed.is_authed()
     }
 
+    fn user(&self) -> Option<Uuid> {
+        self.is_authed.user()
+    }
+
     fn authed(&self) -> IsAuthed {
         self.is_authed.clone()
     }
 
+    /// Waits for the connection to be authenticated, failing if it is closed first
+    ///
+    /// Once authenticated, as the connection is for all but its first few streams, this returns
+    /// right away without polling anything.
+    async fn wait_authed(&self) -> Result<(), Error> {
+        if self.is_authed() {
+            return Ok(());
+        }
+
+        tokio::select! {
+            () = self.authed() => Ok(()),
+            err = self.inner.closed() => Err(Error::from(err)),
+        }
+    }
+
     fn set_udp_relay_mode(&self, mode: UdpRelayMode) {
         self.udp_relay_mode.store(Some(mode));
     }
@@ -562,152 +2052,849 @@ impl Connection {
         self.udp_relay_mode.load()
     }
 
+    /// Returns the authenticated user's UDP relay mode, or the one the client relays UDP in if it
+    /// has none
+    fn udp_relay_mode(&self) -> Option<UdpRelayMode> {
+        self.user_settings
+            .get()
+            .and_then(|user| user.udp_relay_mode)
+            .or_else(|| self.get_udp_relay_mode())
+    }
+
+    /// Refuses UDP relay commands if UDP relay is disabled, before any UDP session is created
+    fn check_udp_relay(&self) -> Result<(), Error> {
+        if self.settings.udp_relay {
+            Ok(())
+        } else {
+            Err(Error::UdpRelayDisabled)
+        }
+    }
+
+    fn check_udp_relay_mode(&self, mode: UdpRelayMode) -> Result<(), Error> {
+        match self
+            .user_settings
+            .get()
+            .and_then(|user| user.udp_relay_mode)
+        {
+            Some(allowed) if allowed != mode => Err(Error::UdpRelayModeNotAllowed(mode)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Warns if the user is restricted to native UDP relaying, but the client did not advertise
+    /// datagram support, so it cannot relay UDP at all
+    fn warn_unsupported_udp_relay_mode(&self, user: &User) {
+        if user.udp_relay_mode == Some(UdpRelayMode::Native)
+            && self.inner.max_datagram_size().is_none()
+        {
+            log::warn!(
+                "{addr} user is restricted to native UDP relay mode, but the client does not support datagrams",
+                addr = self.peer()
+            );
+        }
+    }
+
     fn is_closed(&self) -> bool {
         self.inner.close_reason().is_some()
     }
 
-    fn close(&self) {
-        self.inner.close(VarInt::from_u32(0), b"");
+    fn close(&self, reason: CloseReason) {
+        self.close_reason.store(Some(reason));
+
+        // the log still gets the actual reason from `close_reason`
+        match self.settings.fingerprint_resistance {
+            Some(cfg) if cfg.conceal_close_reason && reason.is_caused_by_client() => {
+                self.inner.close(CloseReason::ShuttingDown.code(), &[]);
+            }
+            _ => self.inner.close(reason.code(), reason.as_str().as_bytes()),
+        }
+    }
+
+    /// Closes the connection for something the client did, after a random delay of up to
+    /// `close_jitter` if fingerprint resistance is enabled, so a probe can't time the server's
+    /// reaction
+    async fn close_jittered(&self, reason: CloseReason) {
+        if let Some(cfg) = self.settings.fingerprint_resistance {
+            if !cfg.close_jitter.is_zero() {
+                let delay = rand::thread_rng().gen_range(Duration::ZERO..=cfg.close_jitter);
+                time::sleep(delay).await;
+            }
+        }
+
+        self.close(reason);
     }
+
+    /// Logs a summary of the closed connection: why and with which error code it was closed, how
+    /// long it lasted, and what it relayed
+    fn log_close(&self) {
+        let (reason, code) = match (self.close_reason.load(), self.inner.close_reason()) {
+            (Some(reason), _) => (reason.as_str(), Some(reason.code().into_inner())),
+            // only the endpoint closes connections without going through `Connection::close`
+            (None, Some(ConnectionError::LocallyClosed)) => (
+                CloseReason::ShuttingDown.as_str(),
+                Some(CloseReason::ShuttingDown.code().into_inner()),
+            ),
+            (None, Some(ConnectionError::ApplicationClosed(close))) => {
+                ("client_closed", Some(close.error_code.into_inner()))
+            }
+            (None, Some(ConnectionError::ConnectionClosed(close))) => {
+                ("transport_error", Some(u64::from(close.error_code)))
+            }
+            (None, Some(ConnectionError::TransportError(err))) => {
+                ("transport_error", Some(u64::from(err.code)))
+            }
+            (None, Some(ConnectionError::TimedOut)) => ("timeout", None),
+            (None, Some(ConnectionError::Reset)) => ("reset", None),
+            (None, Some(ConnectionError::VersionMismatch)) => ("version_mismatch", None),
+            (None, None) => ("unknown", None),
+        };
+
+        log::info!(
+            "{addr} event=close user={user} reason={reason} code={code} duration_ms={duration} upload={upload} download={download} streams={streams} associations={associations}",
+            addr = self.peer(),
+            user = self.user().map_or_else(|| "-".to_owned(), |user| user.to_string()),
+            code = code.map_or_else(|| "-".to_owned(), |code| code.to_string()),
+            duration = self.established_at.elapsed().as_millis(),
+            upload = self.traffic.upload(),
+            download = self.traffic.download(),
+            streams = self.streams.load(Ordering::Relaxed),
+            associations = self.associations.load(Ordering::Relaxed),
+        );
+    }
+}
+
+/// A connection as it is referred to in logs, by its ID and current remote address, like
+/// `conn=42 addr=203.0.113.7:51234`
+///
+/// Every line logged about a connection starts with these two fields, followed by the fields of
+/// what is logged as `key=value` pairs, e.g. `event=close`, `cmd=connect`, `user`, `assoc_id` or
+/// `target`, then a free-form message or error if any. Values hold no spaces, and `-` stands for
+/// none. The ID is the one listed by the admin API, and greps for every line logged about the
+/// connection even across migrations.
+struct Peer {
+    id: u64,
+    addr: SocketAddr,
 }
 
-async fn resolve_dns(addr: &Address) -> Result<impl Iterator<Item = SocketAddr>, IoError> {
-    match addr {
-        Address::None => Err(IoError::new(ErrorKind::InvalidInput, "empty address")),
-        Address::DomainAddress(domain, port) => Ok(net::lookup_host((domain.as_str(), *port))
-            .await?
-            .collect::<Vec<_>>()
-            .into_iter()),
-        Address::SocketAddress(addr) => Ok(vec![*addr].into_iter()),
+impl Display for Peer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "conn={} addr={}", self.id, self.addr)
     }
 }
 
+/// The established connections, listed and closed through the admin API
+pub struct Registry {
+    next_id: AtomicU64,
+    conns: Mutex<HashMap<u64, Connection>>,
+}
+
+impl Registry {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            next_id: AtomicU64::new(0),
+            conns: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Allocates the ID of a new connection, unique for the lifetime of the server
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Registers a connection under its ID. It stays listed until the returned guard is dropped.
+    fn register(self: &Arc<Self>, conn: Connection) -> RegistryGuard {
+        let id = conn.id;
+        self.conns.lock().insert(id, conn);
+
+        RegistryGuard {
+            registry: self.clone(),
+            id,
+        }
+    }
+
+    pub async fn list(&self) -> Vec<ConnectionInfo> {
+        let conns = self
+            .conns
+            .lock()
+            .iter()
+            .map(|(id, conn)| (*id, conn.clone()))
+            .collect::<Vec<_>>();
+
+        let mut infos = Vec::with_capacity(conns.len());
+
+        for (id, conn) in conns {
+            infos.push(ConnectionInfo {
+                id,
+                remote_addr: conn.inner.remote_address(),
+                user: conn.user(),
+                tcp_relays: conn.settings.max_tcp_relays - conn.tcp_relays.available_permits(),
+                udp_sessions: conn.udp_sessions.lock().await.len(),
+                upload: conn.traffic.upload(),
+                download: conn.traffic.download(),
+            });
+        }
+
+        infos.sort_unstable_by_key(|info| info.id);
+        infos
+    }
+
+    /// Closes the connection with the given ID, returning `false` if there is none
+    pub fn close(&self, id: u64) -> bool {
+        let Some(conn) = self.conns.lock().get(&id).cloned() else {
+            return false;
+        };
+
+        log::info!("{addr} connection closed by admin", addr = conn.peer());
+
+        conn.close(CloseReason::ClosedByAdmin);
+        true
+    }
+}
+
+struct RegistryGuard {
+    registry: Arc<Registry>,
+    id: u64,
+}
+
+impl Drop for RegistryGuard {
+    fn drop(&mut self) {
+        self.registry.conns.lock().remove(&self.id);
+    }
+}
+
+#[derive(Serialize)]
+pub struct ConnectionInfo {
+    id: u64,
+    remote_addr: SocketAddr,
+    user: Option<Uuid>,
+    tcp_relays: usize,
+    udp_sessions: usize,
+    upload: u64,
+    download: u64,
+}
+
+/// Checks if the address is an IPv6 link-local unicast address (`fe80::/10`)
+///
+/// TUIC addresses carry no scope ID, and a link-local address without one is ambiguous on a host
+/// with more than one interface, so these are never relayed.
+fn is_ipv6_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// Returns the local IP address the host sends packets to `dst` from, without sending any
+fn route_ip(dst: IpAddr) -> Option<IpAddr> {
+    let unspecified = match dst {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let socket = StdUdpSocket::bind((unspecified, 0)).ok()?;
+    socket.connect((dst, 9)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// The reason the server closed a connection
+///
+/// The code is sent as the QUIC application error code and the string as the reason phrase, so
+/// clients can tell a fatal error from a transient one:
+///
+/// - `0x00` `shutting_down`: the server is shutting down. Reconnecting later is expected to work.
+/// - `0x01` `protocol_error`: the client sent a malformed or unexpected command. Retrying with the
+///   same client is unlikely to help. Unless `strict_protocol` is enabled, only the stream that
+///   carried the command is reset, with this code, and the connection is kept.
+/// - `0x02` `auth_failed`: the UUID is unknown or the password is wrong. Clients should not retry
+///   with the same credentials.
+/// - `0x03` `auth_timeout`: no valid `Authenticate` arrived within `auth_timeout`. Usually
+///   caused by packet loss, so retrying is reasonable.
+/// - `0x04` `quota_exceeded`: the user has used up its traffic quota. Retrying won't help until
+///   the operator raises or resets it.
+/// - `0x05` `closed_by_admin`: the connection was closed through the admin API.
+/// - `0x06` `wrong_stream_type`: the client sent a well-formed command over a kind of stream, or
+///   as a datagram, the command is not allowed on, e.g. `Connect` on a unidirectional stream. A
+///   bug in the client. As with `protocol_error`, only the stream is reset unless
+///   `strict_protocol` is enabled.
+/// - `0x07` `too_many_connections`: the user already holds as many connections as it may. Retrying
+///   works once one of them is closed.
+/// - `0x08` `udp_relay_disabled`: the client sent a UDP packet or an `Associate`, but the server
+///   only relays TCP. Only the stream of the packet is stopped with it, packets in datagrams are
+///   dropped and the connection is kept for its TCP relays. Retrying won't help, the client should
+///   not relay UDP through it.
+/// - `0x09` `extension_not_negotiated`: the client sent an extension command, e.g. `Stats`,
+///   without setting `EXTENSION_COMMANDS` when authenticating. As with `protocol_error`, only the
+///   stream is reset unless `strict_protocol` is enabled.
+#[derive(Clone, Copy)]
+enum CloseReason {
+    ShuttingDown = 0x00,
+    ProtocolError = 0x01,
+    AuthFailed = 0x02,
+    AuthTimeout = 0x03,
+    QuotaExceeded = 0x04,
+    ClosedByAdmin = 0x05,
+    WrongStreamType = 0x06,
+    TooManyConnections = 0x07,
+    UdpRelayDisabled = 0x08,
+    ExtensionNotNegotiated = 0x09,
+}
+
+impl CloseReason {
+    fn from_error(err: &Error) -> Self {
+        match err {
+            Error::AuthFailed(_) => Self::AuthFailed,
+            Error::QuotaExceeded(_) => Self::QuotaExceeded,
+            Error::TooManyUserConnections(..) => Self::TooManyConnections,
+            Error::UdpRelayDisabled => Self::UdpRelayDisabled,
+            Error::ExtensionNotNegotiated(_) => Self::ExtensionNotNegotiated,
+            err if err.is_wrong_stream_type() => Self::WrongStreamType,
+            _ => Self::ProtocolError,
+        }
+    }
+
+    fn code(self) -> VarInt {
+        VarInt::from_u32(self as u32)
+    }
+
+    /// Checks if a probing client could have triggered the close, as opposed to the server or its
+    /// operator
+    fn is_caused_by_client(self) -> bool {
+        matches!(
+            self,
+            Self::ProtocolError
+                | Self::AuthFailed
+                | Self::AuthTimeout
+                | Self::WrongStreamType
+                | Self::ExtensionNotNegotiated
+        )
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::ShuttingDown => "shutting_down",
+            Self::ProtocolError => "protocol_error",
+            Self::AuthFailed => "auth_failed",
+            Self::AuthTimeout => "auth_timeout",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::ClosedByAdmin => "closed_by_admin",
+            Self::WrongStreamType => "wrong_stream_type",
+            Self::TooManyConnections => "too_many_connections",
+            Self::UdpRelayDisabled => "udp_relay_disabled",
+            Self::ExtensionNotNegotiated => "extension_not_negotiated",
+        }
+    }
+}
+
+/// The reason a `Connect` relay could not be established, or was reset once established
+///
+/// TUIC has no response for `Connect`, so on failure the server resets the stream with one of these
+/// as the application error code. `Reset` is used while relaying, when the connection to the target
+/// is reset, so the client can tell it from the target closing the connection. `ResponseFailed`
+/// means the target was reached, but the address answering a `ConnectBound` couldn't be sent.
+///
+/// A `Bind` is reset the same way, with `Forbidden` if the server doesn't serve it and `Timeout` if
+/// no peer connected in time. `ResponseFailed` is then used for either address it is answered with.
+#[derive(Clone, Copy)]
+enum ConnectFailure {
+    ConnectRefused = 0x01,
+    DnsFailure = 0x02,
+    Timeout = 0x03,
+    Unreachable = 0x04,
+    Forbidden = 0x05,
+    TooManyRelays = 0x06,
+    Reset = 0x07,
+    ResponseFailed = 0x08,
+}
+
+impl ConnectFailure {
+    fn from_io_error(err: &IoError) -> Self {
+        match err.kind() {
+            ErrorKind::ConnectionRefused => Self::ConnectRefused,
+            ErrorKind::NotFound => Self::DnsFailure,
+            ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Unreachable,
+        }
+    }
+
+    fn code(self) -> VarInt {
+        VarInt::from_u32(self as u32)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::ConnectRefused => "connect_refused",
+            Self::DnsFailure => "dns_failure",
+            Self::Timeout => "timeout",
+            Self::Unreachable => "unreachable",
+            Self::Forbidden => "forbidden",
+            Self::TooManyRelays => "too_many_relays",
+            Self::Reset => "reset",
+            Self::ResponseFailed => "response_failed",
+        }
+    }
+}
+
+/// Connects to the first reachable address using Happy Eyeballs (RFC 8305)
+///
+/// Addresses are tried in an order alternating between IPv6 and IPv4, starting with the family of
+/// the first address. A new attempt starts every `HAPPY_EYEBALLS_DELAY`, or right after a previous
+/// attempt fails. The first established stream wins, and the remaining attempts are cancelled.
+///
+/// With a retry policy, an attempt only fails once its address has been retried as configured,
+/// and all attempts share `timeout` instead of each getting their own.
+async fn connect_tcp(
+    addrs: impl Iterator<Item = SocketAddr>,
+    bind: OutboundBind,
+    mark: Option<u32>,
+    timeout: Duration,
+    retry: Option<ConnectRetry>,
+) -> Result<TcpStream, IoError> {
+    let mut addrs = interleave_addrs(addrs).into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Some(addr) = addrs.next() {
+            attempts.spawn(async move {
+                match retry {
+                    Some(retry) => connect_tcp_retrying(addr, bind, mark, retry, deadline).await,
+                    None => time::timeout(
+                        timeout,
+                        connect_tcp_from(addr, bind.bind_addr(addr.ip()), mark),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err(connect_timed_out(addr))),
+                }
+            });
+        } else if attempts.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            Some(res) = attempts.join_next() => match res {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(err)) => last_err = Some(err),
+                Err(err) => last_err = Some(IoError::other(err)),
+            },
+            () = time::sleep(HAPPY_EYEBALLS_DELAY), if addrs.len() > 0 => {}
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| IoError::new(ErrorKind::NotFound, "no address resolved")))
+}
+
+/// Connects to `addr`, retrying after a jittered exponential backoff on failure, until the
+/// attempts run out or the next one would start after `deadline`
+async fn connect_tcp_retrying(
+    addr: SocketAddr,
+    bind: OutboundBind,
+    mark: Option<u32>,
+    retry: ConnectRetry,
+    deadline: Instant,
+) -> Result<TcpStream, IoError> {
+    let mut backoff = retry.initial_backoff;
+    let mut attempt = 1;
+
+    loop {
+        let err = match time::timeout_at(
+            deadline,
+            connect_tcp_from(addr, bind.bind_addr(addr.ip()), mark),
+        )
+        .await
+        {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(err)) => err,
+            Err(_) => return Err(connect_timed_out(addr)),
+        };
+
+        let delay = backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+
+        if attempt >= retry.attempts || Instant::now() + delay >= deadline {
+            return Err(err);
+        }
+
+        log::debug!("target={addr} connecting failed, retrying in {delay:?}: {err}");
+
+        time::sleep(delay).await;
+        backoff = backoff.saturating_mul(2).min(retry.max_backoff);
+        attempt += 1;
+    }
+}
+
+fn is_reset(err: &IoError) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
+}
+
+fn connect_timed_out(addr: SocketAddr) -> IoError {
+    IoError::new(
+        ErrorKind::TimedOut,
+        format!("connecting to {addr} timed out"),
+    )
+}
+
+async fn connect_tcp_from(
+    addr: SocketAddr,
+    bind: Option<IpAddr>,
+    mark: Option<u32>,
+) -> Result<TcpStream, IoError> {
+    if bind.is_none() && mark.is_none() {
+        return TcpStream::connect(addr).await;
+    }
+
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    if let Some(mark) = mark {
+        set_mark(SockRef::from(&socket), mark)?;
+    }
+
+    if let Some(bind) = bind {
+        socket.bind(SocketAddr::new(bind, 0))?;
+    }
+
+    socket.connect(addr).await
+}
+
+/// Sets the fwmark of an outbound socket, so policy routing rules can match its traffic
+fn set_mark(socket: SockRef, mark: u32) -> Result<(), IoError> {
+    #[cfg(target_os = "linux")]
+    return socket.set_mark(mark);
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (socket, mark);
+        Err(IoError::new(
+            ErrorKind::Unsupported,
+            "outbound mark is only supported on Linux",
+        ))
+    }
+}
+
+fn interleave_addrs(addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let mut addrs = addrs.peekable();
+    let prefer_ipv6 = addrs.peek().is_some_and(SocketAddr::is_ipv6);
+    let (ipv6, ipv4): (Vec<_>, Vec<_>) = addrs.partition(SocketAddr::is_ipv6);
+
+    let (mut first, mut second) = if prefer_ipv6 {
+        (ipv6.into_iter(), ipv4.into_iter())
+    } else {
+        (ipv4.into_iter(), ipv6.into_iter())
+    };
+
+    let mut res = Vec::with_capacity(first.len() + second.len());
+
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => res.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    res
+}
+
+/// The most destinations a symmetric NAT session can send to, as each one takes a socket
+const MAX_UDP_MAPPINGS: usize = 256;
+
 struct UdpSession {
-    socket_v4: Arc<UdpSocket>,
+    assoc_id: u16,
+    // shared by all destinations, or `None` with symmetric NAT, where each destination gets its
+    // own socket in `mappings`
+    socket_v4: Option<Arc<UdpSocket>>,
     socket_v6: Option<Arc<UdpSocket>>,
-    cancel: Option<Sender<()>>,
+    mappings: HashMap<SocketAddr, Arc<UdpSocket>>,
+    last_activity: Arc<AtomicCell<Instant>>,
+    peers: Option<Arc<Mutex<HashSet<SocketAddr>>>>,
+    // dropping the session aborts its listening tasks
+    listeners: JoinSet<()>,
+    _guard: GaugeGuard,
 }
 
 impl UdpSession {
-    async fn new(assoc_id: u16, conn: Connection, udp_relay_ipv6: bool) -> Result<Self, Error> {
-        let socket_v4 =
-            Arc::new(UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?);
-        let socket_v6 = if udp_relay_ipv6 {
-            let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
-            socket.set_only_v6(true)?;
-            socket.bind(&SockAddr::from(SocketAddr::from((
-                Ipv6Addr::UNSPECIFIED,
-                0,
-            ))))?;
-            Some(Arc::new(UdpSocket::from_std(StdUdpSocket::from(socket))?))
-        } else {
-            None
+    fn new(
+        assoc_id: u16,
+        conn: Connection,
+        udp_relay_ipv6: bool,
+        udp_nat_mode: UdpNatMode,
+    ) -> Result<Self, Error> {
+        let last_activity = Arc::new(AtomicCell::new(Instant::now()));
+        let mut listeners = JoinSet::new();
+
+        let (socket_v4, socket_v6, peers) = match udp_nat_mode {
+            UdpNatMode::Symmetric => (None, None, None),
+            UdpNatMode::FullCone | UdpNatMode::Restricted => {
+                let mark = conn.outbound_mark();
+                let socket_v4 = Arc::new(bind_udp(
+                    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    mark,
+                )?);
+                let socket_v6 = if udp_relay_ipv6 {
+                    Some(Arc::new(bind_udp(
+                        SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                        mark,
+                    )?))
+                } else {
+                    None
+                };
+
+                // the addresses the client has sent to, only tracked when unsolicited packets are
+                // dropped
+                let peers = matches!(udp_nat_mode, UdpNatMode::Restricted)
+                    .then(|| Arc::new(Mutex::new(HashSet::new())));
+
+                listeners.spawn(Self::listen_incoming(
+                    assoc_id,
+                    conn,
+                    socket_v4.clone(),
+                    socket_v6.clone(),
+                    last_activity.clone(),
+                    peers.clone(),
+                ));
+
+                (Some(socket_v4), socket_v6, peers)
+            }
         };
 
-        let (tx, rx) = oneshot::channel();
-
-        tokio::spawn(Self::listen_incoming(
-            assoc_id,
-            conn,
-            socket_v4.clone(),
-            socket_v6.clone(),
-            rx,
-        ));
-
         Ok(Self {
+            assoc_id,
             socket_v4,
             socket_v6,
-            cancel: Some(tx),
+            mappings: HashMap::new(),
+            last_activity,
+            peers,
+            listeners,
+            _guard: metrics::UDP_SESSIONS.track(),
         })
     }
 
+    /// Returns the socket dedicated to `dst` in a symmetric NAT session, binding it on first use
+    ///
+    /// The socket is connected to `dst`, so the kernel drops packets from any other source.
+    async fn mapping(
+        &mut self,
+        conn: &Connection,
+        dst: SocketAddr,
+    ) -> Result<Arc<UdpSocket>, Error> {
+        if let Some(socket) = self.mappings.get(&dst) {
+            return Ok(socket.clone());
+        }
+
+        if self.mappings.len() >= MAX_UDP_MAPPINGS {
+            return Err(Error::TooManyUdpMappings(MAX_UDP_MAPPINGS));
+        }
+
+        let local_addr = match dst {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+
+        let socket = bind_udp(local_addr, conn.outbound_mark())?;
+        socket.connect(dst).await?;
+        let socket = Arc::new(socket);
+
+        self.listeners.spawn(Self::listen_incoming(
+            self.assoc_id,
+            conn.clone(),
+            socket.clone(),
+            None,
+            self.last_activity.clone(),
+            None,
+        ));
+
+        self.mappings.insert(dst, socket.clone());
+        Ok(socket)
+    }
+
+    /// Forwards packets received on `socket`, and on `socket_v6` if given, to the client
     async fn listen_incoming(
         assoc_id: u16,
         conn: Connection,
-        socket_v4: Arc<UdpSocket>,
+        socket: Arc<UdpSocket>,
         socket_v6: Option<Arc<UdpSocket>>,
-        cancel: Receiver<()>,
+        last_activity: Arc<AtomicCell<Instant>>,
+        peers: Option<Arc<Mutex<HashSet<SocketAddr>>>>,
     ) {
-        async fn send_pkt(conn: Connection, pkt: Bytes, target_addr: SocketAddr, assoc_id: u16) {
-            let addr = conn.inner.remote_address();
+        async fn send_pkt(
+            conn: Connection,
+            pkt: Bytes,
+            target_addr: SocketAddr,
+            assoc_id: u16,
+            _permit: OwnedSemaphorePermit,
+            _in_flight: GaugeGuard,
+        ) {
+            // waiting here keeps the socket read, packets beyond the in-flight cap are dropped
+            if let Some(limiter) = &conn.download_limiter {
+                limiter.acquire(pkt.len()).await;
+            }
+
+            let addr = conn.peer();
             let target_addr_tuic = Address::SocketAddress(target_addr);
 
-            let res = match conn.get_udp_relay_mode() {
+            let (res, counter) = match conn.udp_relay_mode() {
                 Some(UdpRelayMode::Native) => {
-                    log::info!("[{addr}] [packet-to-native] [{assoc_id}] [{target_addr_tuic}]");
-                    conn.model.packet_native(pkt, target_addr_tuic, assoc_id)
+                    log::info!("{addr} cmd=packet dir=download mode=native assoc_id={assoc_id} target={target_addr_tuic}");
+                    let res = conn.model.packet_native(pkt, target_addr_tuic, assoc_id);
+                    (res, &metrics::UDP_DATAGRAM_PACKETS_DOWNLOAD)
                 }
                 Some(UdpRelayMode::Quic) => {
-                    log::info!("[{addr}] [packet-to-quic] [{assoc_id}] [{target_addr_tuic}]");
-                    conn.model
+                    log::info!("{addr} cmd=packet dir=download mode=quic assoc_id={assoc_id} target={target_addr_tuic}");
+                    let res = conn
+                        .model
                         .packet_quic(pkt, target_addr_tuic, assoc_id)
-                        .await
+                        .await;
+                    (res, &metrics::UDP_STREAM_PACKETS_DOWNLOAD)
                 }
                 None => unreachable!(),
             };
 
-            if let Err(err) = res {
-                let target_addr_tuic = Address::SocketAddress(target_addr);
-                log::warn!("[{addr}] [packet-to-quic] [{assoc_id}] [{target_addr_tuic}] {err}");
+            let err = match res {
+                Ok(frag_cnt) => {
+                    counter.inc();
+
+                    if frag_cnt > 1 {
+                        metrics::UDP_PACKETS_FRAGMENTED_DOWNLOAD.inc();
+                    }
+
+                    return;
+                }
+                Err(err) => err,
+            };
+
+            let target_addr_tuic = Address::SocketAddress(target_addr);
+
+            if conn.is_closed() {
+                // the connection is going away, and its UDP sessions with it
+                log::debug!("{addr} cmd=packet dir=download assoc_id={assoc_id} target={target_addr_tuic} {err}");
+            } else if matches!(
+                err,
+                ModelError::SendDatagram(
+                    SendDatagramError::Disabled | SendDatagramError::UnsupportedByPeer
+                )
+            ) {
+                // every later packet of the session would fail the same way
+                log::warn!(
+                    "{addr} cmd=packet dir=download mode=native assoc_id={assoc_id} target={target_addr_tuic} {err}, dissociating"
+                );
+                let _ = conn.handle_dissociate(assoc_id).await;
+            } else if matches!(
+                err,
+                ModelError::Fragment(_) | ModelError::SendDatagram(SendDatagramError::TooLarge)
+            ) {
+                log::debug!(
+                    "{addr} cmd=packet dir=download assoc_id={assoc_id} target={target_addr_tuic} dropped, {err}"
+                );
+                metrics::UDP_PACKETS_OVERSIZED.inc();
+            } else {
+                log::warn!("{addr} cmd=packet dir=download assoc_id={assoc_id} target={target_addr_tuic} {err}");
             }
         }
 
-        let addr = conn.inner.remote_address();
+        let addr = conn.peer();
 
-        tokio::select! {
-            _ = cancel => {}
-            () = async {
-                loop {
-                    match Self::accept(
-                        &socket_v4,
-                        socket_v6.as_deref(),
-                        conn.max_external_pkt_size,
-                    ).await {
-                        Ok((pkt, target_addr)) => {
-                            tokio::spawn(send_pkt(conn.clone(), pkt, target_addr, assoc_id));
-                        }
-                        Err(err) => log::warn!("[{addr}] [packet-to-*] [{assoc_id}] {err}"),
-                    }
+        // receive buffers are reused across packets, each packet is copied out at its actual size
+        let mut buf = vec![0; conn.settings.max_external_pkt_size];
+        let mut buf_v6 = vec![
+            0;
+            socket_v6
+                .as_ref()
+                .map_or(0, |_| conn.settings.max_external_pkt_size)
+        ];
+
+        loop {
+            match Self::accept(&socket, socket_v6.as_deref(), &mut buf, &mut buf_v6).await {
+                Ok((_, target_addr))
+                    if peers
+                        .as_ref()
+                        .is_some_and(|peers| !peers.lock().contains(&target_addr)) =>
+                {
+                    log::debug!(
+                        "{addr} cmd=packet dir=download assoc_id={assoc_id} target={target_addr} dropped unsolicited packet"
+                    );
                 }
-            } => unreachable!(),
+                // nothing can be sent anymore, and the session is dropped with the connection
+                Ok(_) if conn.is_closed() => return,
+                Ok((pkt, target_addr)) => {
+                    last_activity.store(Instant::now());
+
+                    let Ok(permit) = conn.udp_download_packets.clone().try_acquire_owned() else {
+                        log::debug!(
+                            "{addr} cmd=packet dir=download assoc_id={assoc_id} target={target_addr} dropped, too many packets in flight"
+                        );
+                        metrics::UDP_PACKETS_DROPPED_DOWNLOAD.inc();
+                        continue;
+                    };
+
+                    let in_flight = metrics::UDP_PACKETS_IN_FLIGHT_DOWNLOAD.track();
+                    metrics::UDP_BYTES_DOWNLOAD.add(pkt.len() as u64);
+
+                    conn.traffic.add_download(pkt.len() as u64);
+
+                    tokio::spawn(send_pkt(
+                        conn.clone(),
+                        pkt,
+                        target_addr,
+                        assoc_id,
+                        permit,
+                        in_flight,
+                    ));
+                }
+                Err(err) => log::warn!("{addr} cmd=packet dir=download assoc_id={assoc_id} {err}"),
+            }
         }
     }
 
     async fn accept(
-        socket_v4: &UdpSocket,
+        socket: &UdpSocket,
         socket_v6: Option<&UdpSocket>,
-        max_pkt_size: usize,
+        buf: &mut [u8],
+        buf_v6: &mut [u8],
     ) -> Result<(Bytes, SocketAddr), IoError> {
         async fn read_pkt(
             socket: &UdpSocket,
-            max_pkt_size: usize,
+            buf: &mut [u8],
         ) -> Result<(Bytes, SocketAddr), IoError> {
-            let mut buf = vec![0u8; max_pkt_size];
-            let (n, addr) = socket.recv_from(&mut buf).await?;
-            buf.truncate(n);
-            Ok((Bytes::from(buf), addr))
+            let (n, addr) = socket.recv_from(buf).await?;
+            Ok((Bytes::copy_from_slice(&buf[..n]), addr))
         }
 
         if let Some(socket_v6) = socket_v6 {
             tokio::select! {
-                res = read_pkt(socket_v4, max_pkt_size) => res,
-                res = read_pkt(socket_v6, max_pkt_size) => res,
+                res = read_pkt(socket, buf) => res,
+                res = read_pkt(socket_v6, buf_v6) => res,
             }
         } else {
-            read_pkt(socket_v4, max_pkt_size).await
+            read_pkt(socket, buf).await
         }
     }
 }
 
-impl Drop for UdpSession {
-    fn drop(&mut self) {
-        let _ = self.cancel.take().unwrap().send(());
+/// Binds a UDP socket to `addr`, with an optional fwmark. IPv6 sockets don't accept IPv4 traffic.
+fn bind_udp(addr: SocketAddr, mark: Option<u32>) -> Result<UdpSocket, IoError> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
     }
+
+    if let Some(mark) = mark {
+        set_mark(SockRef::from(&socket), mark)?;
+    }
+
+    socket.bind(&SockAddr::from(addr))?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(StdUdpSocket::from(socket))
 }
 
 #[derive(Clone)]
 struct IsAuthed {
     is_authed: Arc<AtomicBool>,
+    user: Arc<AtomicCell<Option<Uuid>>>,
     broadcast: Arc<Mutex<Vec<Waker>>>,
 }
 
@@ -715,11 +2902,13 @@ impl IsAuthed {
     fn new() -> Self {
         Self {
             is_authed: Arc::new(AtomicBool::new(false)),
+            user: Arc::new(AtomicCell::new(None)),
             broadcast: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
-    fn set_authed(&self) {
+    fn set_authed(&self, user: Uuid) {
+        self.user.store(Some(user));
         self.is_authed.store(true, Ordering::Release);
 
         for waker in self.broadcast.lock().drain(..) {
@@ -728,7 +2917,11 @@ impl IsAuthed {
     }
 
     fn is_authed(&self) -> bool {
-        self.is_authed.load(Ordering::Relaxed)
+        self.is_authed.load(Ordering::Acquire)
+    }
+
+    fn user(&self) -> Option<Uuid> {
+        self.user.load()
     }
 }
 
@@ -736,10 +2929,14 @@ impl Future for IsAuthed {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self.is_authed.load(Ordering::Relaxed) {
+        // hold the lock while checking the flag, so `set_authed` can't drain the wakers between
+        // the check and the push
+        let mut broadcast = self.broadcast.lock();
+
+        if self.is_authed.load(Ordering::Acquire) {
             Poll::Ready(())
         } else {
-            self.broadcast.lock().push(cx.waker().clone());
+            broadcast.push(cx.waker().clone());
             Poll::Pending
         }
     }
@@ -753,4 +2950,70 @@ impl Error {
     fn is_timeout_closed(&self) -> bool {
         matches!(self, Self::Connection(ConnectionError::TimedOut))
     }
+
+    /// Checks if the error is a malformed or unexpected command, which only concerns the stream or
+    /// datagram that carried it
+    fn is_bad_command(&self) -> bool {
+        matches!(
+            self,
+            Self::Model(
+                ModelError::UnmarshalUniStream(..)
+                    | ModelError::UnmarshalBiStream(..)
+                    | ModelError::UnmarshalDatagram(..)
+                    | ModelError::BadCommandUniStream(..)
+                    | ModelError::BadCommandBiStream(..)
+                    | ModelError::BadCommandDatagram(..)
+            ) | Self::UnexpectedPacketSource
+                | Self::ExtensionNotNegotiated(_)
+        )
+    }
+
+    /// Checks if the error is a valid command received over a kind of stream it is not allowed on
+    fn is_wrong_stream_type(&self) -> bool {
+        matches!(
+            self,
+            Self::Model(
+                ModelError::BadCommandUniStream(..)
+                    | ModelError::BadCommandBiStream(..)
+                    | ModelError::BadCommandDatagram(..)
+            )
+        )
+    }
+
+    /// Checks if the stream ended, was reset or lost its connection before carrying a whole
+    /// command, which is not a protocol violation
+    fn is_stream_closed(&self) -> bool {
+        let Self::Model(
+            ModelError::UnmarshalUniStream(UnmarshalError::Io(err), _)
+            | ModelError::UnmarshalBiStream(UnmarshalError::Io(err), ..),
+        ) = self
+        else {
+            return false;
+        };
+
+        matches!(
+            err.kind(),
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::NotConnected
+        )
+    }
+
+    /// Resets the stream that carried the command, if the error holds it
+    fn reset_stream(self, code: VarInt) {
+        match self {
+            Self::Model(
+                ModelError::UnmarshalUniStream(_, mut recv)
+                | ModelError::BadCommandUniStream(_, mut recv),
+            ) => {
+                let _ = recv.stop(code);
+            }
+            Self::Model(
+                ModelError::UnmarshalBiStream(_, mut send, mut recv)
+                | ModelError::BadCommandBiStream(_, mut send, mut recv),
+            ) => {
+                let _ = send.reset(code);
+                let _ = recv.stop(code);
+            }
+            _ => {}
+        }
+    }
 }
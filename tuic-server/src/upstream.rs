@@ -0,0 +1,207 @@
+use crate::{config::UpstreamProxy as UpstreamProxyConfig, utils::UpstreamProtocol};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::IpAddr,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tuic::Address;
+
+const MAX_HTTP_RESPONSE_HEADER_SIZE: usize = 8192;
+
+/// A SOCKS5 or HTTP CONNECT proxy that relayed TCP connections are established through
+pub struct UpstreamProxy {
+    protocol: UpstreamProtocol,
+    server: String,
+    auth: Option<(String, String)>,
+}
+
+impl UpstreamProxy {
+    pub fn new(cfg: UpstreamProxyConfig) -> Self {
+        Self {
+            protocol: cfg.protocol,
+            server: cfg.server,
+            auth: cfg.username.zip(cfg.password),
+        }
+    }
+
+    /// The address of the proxy, as `host:port`
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Asks the proxy, already connected to through `stream`, to connect to `addr`
+    pub async fn handshake(&self, stream: &mut TcpStream, addr: &Address) -> Result<(), IoError> {
+        if matches!(addr, Address::None) {
+            return Err(IoError::new(ErrorKind::InvalidInput, "empty address"));
+        }
+
+        match self.protocol {
+            UpstreamProtocol::Socks5 => self.handshake_socks5(stream, addr).await,
+            UpstreamProtocol::Http => self.handshake_http(stream, addr).await,
+        }
+    }
+
+    async fn handshake_socks5(
+        &self,
+        stream: &mut TcpStream,
+        addr: &Address,
+    ) -> Result<(), IoError> {
+        const VERSION: u8 = 0x05;
+        const METHOD_NONE: u8 = 0x00;
+        const METHOD_PASSWORD: u8 = 0x02;
+
+        if self.auth.is_some() {
+            stream
+                .write_all(&[VERSION, 2, METHOD_NONE, METHOD_PASSWORD])
+                .await?;
+        } else {
+            stream.write_all(&[VERSION, 1, METHOD_NONE]).await?;
+        }
+
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf).await?;
+
+        match (buf, &self.auth) {
+            ([VERSION, METHOD_NONE], _) => {}
+            ([VERSION, METHOD_PASSWORD], Some((username, password))) => {
+                if username.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidInput,
+                        "upstream proxy credentials too long",
+                    ));
+                }
+
+                let mut req = vec![0x01, username.len() as u8];
+                req.extend_from_slice(username.as_bytes());
+                req.push(password.len() as u8);
+                req.extend_from_slice(password.as_bytes());
+                stream.write_all(&req).await?;
+
+                stream.read_exact(&mut buf).await?;
+
+                if buf[1] != 0x00 {
+                    return Err(IoError::new(
+                        ErrorKind::PermissionDenied,
+                        "upstream proxy authentication failed",
+                    ));
+                }
+            }
+            _ => {
+                return Err(IoError::new(
+                    ErrorKind::PermissionDenied,
+                    "upstream proxy accepts no supported authentication method",
+                ))
+            }
+        }
+
+        let mut req = vec![VERSION, 0x01, 0x00];
+
+        match addr {
+            Address::None => unreachable!(),
+            Address::DomainAddress(domain, port) => {
+                req.push(0x03);
+                req.push(domain.len() as u8);
+                req.extend_from_slice(domain.as_bytes());
+                req.extend_from_slice(&port.to_be_bytes());
+            }
+            Address::SocketAddress(addr) => {
+                match addr.ip() {
+                    IpAddr::V4(ip) => {
+                        req.push(0x01);
+                        req.extend_from_slice(&ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        req.push(0x04);
+                        req.extend_from_slice(&ip.octets());
+                    }
+                }
+
+                req.extend_from_slice(&addr.port().to_be_bytes());
+            }
+        }
+
+        stream.write_all(&req).await?;
+
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await?;
+
+        let kind = match buf[1] {
+            0x00 => None,
+            0x02 => Some(ErrorKind::PermissionDenied),
+            0x05 => Some(ErrorKind::ConnectionRefused),
+            0x06 => Some(ErrorKind::TimedOut),
+            _ => Some(ErrorKind::Other),
+        };
+
+        if let Some(kind) = kind {
+            return Err(IoError::new(
+                kind,
+                format!("upstream proxy replied with code {}", buf[1]),
+            ));
+        }
+
+        // skip the bound address, which is of no use here
+        let len = match buf[3] {
+            0x01 => 4 + 2,
+            0x04 => 16 + 2,
+            0x03 => stream.read_u8().await? as usize + 2,
+            _ => {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    "invalid address type from upstream proxy",
+                ))
+            }
+        };
+
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).await?;
+
+        Ok(())
+    }
+
+    async fn handshake_http(&self, stream: &mut TcpStream, addr: &Address) -> Result<(), IoError> {
+        let mut req = format!("CONNECT {addr} HTTP/1.1\r\nHost: {addr}\r\n");
+
+        if let Some((username, password)) = &self.auth {
+            let credentials = STANDARD.encode(format!("{username}:{password}"));
+            req.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+        }
+
+        req.push_str("\r\n");
+        stream.write_all(req.as_bytes()).await?;
+
+        // read byte by byte, so nothing after the header is consumed
+        let mut resp = Vec::new();
+
+        while !resp.ends_with(b"\r\n\r\n") {
+            if resp.len() >= MAX_HTTP_RESPONSE_HEADER_SIZE {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    "upstream proxy response header too large",
+                ));
+            }
+
+            resp.push(stream.read_u8().await?);
+        }
+
+        let resp = String::from_utf8_lossy(&resp);
+        let status_line = resp.lines().next().unwrap_or_default();
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+
+        let kind = match status.as_bytes().first() {
+            Some(b'2') => return Ok(()),
+            _ if status == "403" || status == "407" => ErrorKind::PermissionDenied,
+            _ if status == "504" => ErrorKind::TimedOut,
+            _ => ErrorKind::Other,
+        };
+
+        Err(IoError::new(
+            kind,
+            format!("upstream proxy responded `{status_line}`"),
+        ))
+    }
+}
@@ -1,11 +1,16 @@
 use rustls::{Certificate, PrivateKey};
 use rustls_pemfile::Item;
 use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
     fs::{self, File},
     io::{BufReader, Error as IoError},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::PathBuf,
+    pin::Pin,
     str::FromStr,
+    task::{Context, Poll},
 };
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 pub fn load_certs(path: PathBuf) -> Result<Vec<Certificate>, IoError> {
     let mut file = BufReader::new(File::open(&path)?);
@@ -40,12 +45,181 @@ pub fn load_priv_key(path: PathBuf) -> Result<PrivateKey, IoError> {
         .map(PrivateKey)
 }
 
-#[derive(Clone, Copy)]
+/// How UDP packets are carried between the client and the server
+///
+/// - `Native`: in QUIC datagrams, unreliable and unordered like UDP itself, for real-time traffic
+/// - `Quic`: in QUIC unidirectional streams, reliable and without a size limit, for bulk traffic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UdpRelayMode {
     Native,
     Quic,
 }
 
+impl FromStr for UdpRelayMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("native") {
+            Ok(Self::Native)
+        } else if s.eq_ignore_ascii_case("quic") {
+            Ok(Self::Quic)
+        } else {
+            Err("invalid UDP relay mode")
+        }
+    }
+}
+
+impl Display for UdpRelayMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Native => write!(f, "native"),
+            Self::Quic => write!(f, "quic"),
+        }
+    }
+}
+
+/// How UDP sessions map and filter packets, as seen by NAT type detection on the client
+///
+/// - `FullCone`: one relay port per session, and packets from any source are forwarded to the
+///   client. Peers the client has never contacted can reach it, which P2P applications and game
+///   consoles rely on, but so can anyone who learns the relay port.
+/// - `Restricted`: one relay port per session, and only packets from addresses the client has sent
+///   to in the same session are forwarded, the way a port-restricted cone NAT behaves. Hole
+///   punching still works, since peers see the same port.
+/// - `Symmetric`: a separate relay port for each destination, only accepting packets from that
+///   destination. This is the strictest mode and uses one socket per destination, but peers see
+///   a different port each, so most P2P hole punching fails.
+#[derive(Clone, Copy)]
+pub enum UpstreamProtocol {
+    Socks5,
+    Http,
+}
+
+impl FromStr for UpstreamProtocol {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("socks5") {
+            Ok(Self::Socks5)
+        } else if s.eq_ignore_ascii_case("http") {
+            Ok(Self::Http)
+        } else {
+            Err("invalid upstream proxy protocol")
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum UdpNatMode {
+    FullCone,
+    Restricted,
+    Symmetric,
+}
+
+impl FromStr for UdpNatMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("full_cone") || s.eq_ignore_ascii_case("fullcone") {
+            Ok(Self::FullCone)
+        } else if s.eq_ignore_ascii_case("restricted") {
+            Ok(Self::Restricted)
+        } else if s.eq_ignore_ascii_case("symmetric") {
+            Ok(Self::Symmetric)
+        } else {
+            Err("invalid UDP NAT mode")
+        }
+    }
+}
+
+/// Which address family relays use when a destination resolves to addresses of both
+#[derive(Clone, Copy, Debug)]
+pub enum OutboundFamily {
+    Auto,
+    V4,
+    V6,
+    V4First,
+    V6First,
+}
+
+impl OutboundFamily {
+    /// Drops or reorders resolved addresses by family, keeping the resolver's order within each
+    /// family
+    ///
+    /// TCP relays try the addresses in Happy Eyeballs order, starting with the family of the first
+    /// address, so preferring a family makes it the one tried first.
+    pub fn apply(self, addrs: &mut Vec<SocketAddr>) {
+        match self {
+            Self::Auto => {}
+            Self::V4 => addrs.retain(SocketAddr::is_ipv4),
+            Self::V6 => addrs.retain(SocketAddr::is_ipv6),
+            Self::V4First => addrs.sort_by_key(SocketAddr::is_ipv6),
+            Self::V6First => addrs.sort_by_key(SocketAddr::is_ipv4),
+        }
+    }
+}
+
+impl FromStr for OutboundFamily {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            Ok(Self::Auto)
+        } else if s.eq_ignore_ascii_case("v4") {
+            Ok(Self::V4)
+        } else if s.eq_ignore_ascii_case("v6") {
+            Ok(Self::V6)
+        } else if s.eq_ignore_ascii_case("v4-first") || s.eq_ignore_ascii_case("v4_first") {
+            Ok(Self::V4First)
+        } else if s.eq_ignore_ascii_case("v6-first") || s.eq_ignore_ascii_case("v6_first") {
+            Ok(Self::V6First)
+        } else {
+            Err("invalid outbound family")
+        }
+    }
+}
+
+impl Display for OutboundFamily {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::V4 => write!(f, "v4"),
+            Self::V6 => write!(f, "v6"),
+            Self::V4First => write!(f, "v4-first"),
+            Self::V6First => write!(f, "v6-first"),
+        }
+    }
+}
+
+/// The source addresses relayed TCP connections are bound to, at most one per address family
+///
+/// Once either is set, destinations of a family without a bind address are not connected to, so
+/// traffic never silently egresses from the default address.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct OutboundBind {
+    pub v4: Option<Ipv4Addr>,
+    pub v6: Option<Ipv6Addr>,
+}
+
+impl OutboundBind {
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_none() && self.v6.is_none()
+    }
+
+    /// Checks if `dst` can be connected to from the bind addresses
+    pub fn matches(&self, dst: IpAddr) -> bool {
+        self.is_empty() || self.bind_addr(dst).is_some()
+    }
+
+    /// Returns the address to bind to before connecting to `dst`
+    pub fn bind_addr(&self, dst: IpAddr) -> Option<IpAddr> {
+        match dst {
+            IpAddr::V4(_) => self.v4.map(IpAddr::V4),
+            IpAddr::V6(_) => self.v6.map(IpAddr::V6),
+        }
+    }
+}
+
 pub enum CongestionControl {
     Cubic,
     NewReno,
@@ -67,3 +241,69 @@ impl FromStr for CongestionControl {
         }
     }
 }
+
+/// Remembers whether reading from or writing to the wrapped stream failed
+///
+/// When two streams are copied into each other, this tells which of them an error came from.
+pub struct Faulted<T> {
+    inner: T,
+    faulted: bool,
+}
+
+impl<T> Faulted<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            faulted: false,
+        }
+    }
+
+    /// Checks if an I/O operation on the stream returned an error
+    pub fn is_faulted(&self) -> bool {
+        self.faulted
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn track<R>(&mut self, res: Poll<Result<R, IoError>>) -> Poll<Result<R, IoError>> {
+        if let Poll::Ready(Err(_)) = res {
+            self.faulted = true;
+        }
+
+        res
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Faulted<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.track(res)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Faulted<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.track(res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        let res = Pin::new(&mut self.inner).poll_flush(cx);
+        self.track(res)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        let res = Pin::new(&mut self.inner).poll_shutdown(cx);
+        self.track(res)
+    }
+}
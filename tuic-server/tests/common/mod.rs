@@ -0,0 +1,215 @@
+//! A server on a loopback port and a minimal client speaking TUIC to it, shared by the
+//! integration tests
+//!
+//! The certificate in `fixtures` is issued for `localhost` by the CA next to it.
+
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use quinn::{ClientConfig, Connection as QuinnConnection, Endpoint, VarInt};
+use rustls::{Certificate, ClientConfig as RustlsClientConfig, RootCertStore};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::BufReader,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UdpSocket},
+    task::JoinHandle,
+    time,
+};
+use tuic::{Address, EXTENSION_COMMANDS};
+use tuic_quinn::{side, Connection as Model, Task};
+use tuic_server::{config::Config, Server};
+use uuid::Uuid;
+
+pub const UUID: Uuid = Uuid::from_u128(1);
+pub const PASSWORD: &str = "password";
+
+/// How long a test waits for anything to happen before failing
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct TestServer {
+    pub server: Arc<Server>,
+    pub addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Starts a server with a single user, listening on a loopback port. `overrides` replaces
+    /// the fields of the config it names.
+    pub async fn start(overrides: Value) -> Self {
+        let server = Server::builder(config(overrides)).build().unwrap();
+        let server = Arc::new(server);
+        let addr = server.local_addrs()[0];
+
+        let task = tokio::spawn({
+            let server = server.clone();
+            async move { server.run().await }
+        });
+
+        Self { server, addr, task }
+    }
+
+    /// Connects a client, without authenticating
+    pub async fn connect(&self) -> Client {
+        Client::connect(self.addr).await
+    }
+
+    /// Connects a client and authenticates it, announcing the extension commands
+    pub async fn connect_authed(&self) -> Client {
+        let client = self.connect().await;
+        client.authenticate(PASSWORD).await;
+        client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+fn config(overrides: Value) -> Config {
+    let mut cfg = json!({
+        "server": "127.0.0.1:0",
+        "users": { UUID.to_string(): PASSWORD },
+        "certificate": fixture("cert.pem"),
+        "private_key": fixture("key.pem"),
+        // the targets of the tests are all on loopback
+        "acl": { "deny_ips": [] },
+        "auth_failure_delay": { "secs": 0, "nanos": 0 },
+    });
+
+    for (key, value) in overrides.as_object().unwrap() {
+        cfg[key] = value.clone();
+    }
+
+    serde_json::from_value(cfg).unwrap()
+}
+
+pub struct Client {
+    pub conn: QuinnConnection,
+    pub model: Model<side::Client>,
+    _ep: Endpoint,
+}
+
+impl Client {
+    pub async fn connect(addr: SocketAddr) -> Self {
+        let mut roots = RootCertStore::empty();
+        let mut ca = BufReader::new(File::open(fixture("ca.pem")).unwrap());
+
+        for cert in rustls_pemfile::certs(&mut ca).unwrap() {
+            roots.add(&Certificate(cert)).unwrap();
+        }
+
+        let crypto = RustlsClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let mut ep = Endpoint::client(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        ep.set_default_client_config(ClientConfig::new(Arc::new(crypto)));
+
+        let conn = time::timeout(TIMEOUT, ep.connect(addr, "localhost").unwrap())
+            .await
+            .expect("connecting timed out")
+            .unwrap();
+
+        Self {
+            model: Model::<side::Client>::new(conn.clone()),
+            conn,
+            _ep: ep,
+        }
+    }
+
+    pub async fn authenticate(&self, password: &str) {
+        self.model
+            .authenticate_with_extensions(UUID, password, EXTENSION_COMMANDS)
+            .await
+            .unwrap();
+    }
+
+    /// Waits for the server to close the connection, returning the error code it closed with
+    pub async fn closed(&self) -> VarInt {
+        match time::timeout(TIMEOUT, self.conn.closed()).await {
+            Ok(quinn::ConnectionError::ApplicationClosed(close)) => close.error_code,
+            Ok(err) => panic!("connection closed with {err}"),
+            Err(_) => panic!("connection not closed"),
+        }
+    }
+
+    /// Waits for the next UDP packet from the server, in either relay mode
+    pub async fn recv_packet(&self) -> (Bytes, Address, u16) {
+        let recv = async {
+            loop {
+                let task = tokio::select! {
+                    dg = self.conn.read_datagram() => self.model.accept_datagram(dg.unwrap()),
+                    recv = self.conn.accept_uni() => {
+                        self.model.accept_uni_stream(recv.unwrap()).await
+                    }
+                };
+
+                if let Task::Packet(pkt) = task.unwrap() {
+                    if let Some(pkt) = pkt.accept().await.unwrap() {
+                        return pkt;
+                    }
+                }
+            }
+        };
+
+        time::timeout(TIMEOUT, recv)
+            .await
+            .expect("no packet received")
+    }
+}
+
+/// Starts a TCP server echoing everything back on a loopback port
+pub async fn tcp_echo() -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0; 4096];
+
+                while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                    if stream.write_all(&buf[..n]).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    addr
+}
+
+/// Starts a UDP server echoing every packet back to its sender on a loopback port
+pub async fn udp_echo() -> SocketAddr {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = vec![0; 65535];
+
+        while let Ok((n, addr)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..n], addr).await;
+        }
+    });
+
+    addr
+}
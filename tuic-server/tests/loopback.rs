@@ -0,0 +1,119 @@
+mod common;
+
+use self::common::{TestServer, PASSWORD, TIMEOUT};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::Address;
+
+#[tokio::test]
+async fn connect_round_trip() {
+    let server = TestServer::start(json!({})).await;
+    let target = common::tcp_echo().await;
+    let client = server.connect_authed().await;
+
+    let relay = client
+        .model
+        .connect(Address::SocketAddress(target))
+        .await
+        .unwrap();
+    let mut relay = relay.compat();
+
+    let relay = async {
+        relay.write_all(b"hello over tuic").await?;
+        relay.shutdown().await?;
+
+        let mut buf = Vec::new();
+        relay.read_to_end(&mut buf).await?;
+        Ok::<_, std::io::Error>(buf)
+    };
+
+    let buf = time::timeout(TIMEOUT, relay).await.unwrap().unwrap();
+    assert_eq!(buf, b"hello over tuic");
+}
+
+#[tokio::test]
+async fn packet_round_trip() {
+    let server = TestServer::start(json!({})).await;
+    let target = common::udp_echo().await;
+
+    // a connection relays UDP in the mode of its first packet only
+    for (assoc_id, native) in [(0, true), (1, false)] {
+        let client = server.connect_authed().await;
+        let pkt = format!("datagram of session {assoc_id}");
+
+        if native {
+            client
+                .model
+                .packet_native(&pkt, Address::SocketAddress(target), assoc_id)
+                .unwrap();
+        } else {
+            client
+                .model
+                .packet_quic(&pkt, Address::SocketAddress(target), assoc_id)
+                .await
+                .unwrap();
+        }
+
+        let (echo, addr, echo_assoc_id) = client.recv_packet().await;
+        assert_eq!(echo, pkt.as_bytes());
+        assert_eq!(addr, Address::SocketAddress(target));
+        assert_eq!(echo_assoc_id, assoc_id);
+    }
+}
+
+#[tokio::test]
+async fn wrong_password_closes_with_auth_failed() {
+    let server = TestServer::start(json!({})).await;
+    let client = server.connect().await;
+
+    client.authenticate("wrong password").await;
+    assert_eq!(client.closed().await.into_inner(), 0x02);
+}
+
+#[tokio::test]
+async fn no_authentication_closes_with_auth_timeout() {
+    let server =
+        TestServer::start(json!({ "auth_timeout": { "secs": 0, "nanos": 200_000_000 } })).await;
+    let client = server.connect().await;
+
+    assert_eq!(client.closed().await.into_inner(), 0x03);
+}
+
+#[tokio::test]
+async fn commands_sent_before_authentication_wait_for_it() {
+    let server = TestServer::start(json!({})).await;
+    let tcp_target = common::tcp_echo().await;
+    let udp_target = common::udp_echo().await;
+    let client = server.connect().await;
+
+    // the commands are on streams of their own, the server may well read them before the
+    // authentication
+    client
+        .model
+        .packet_quic("sent before auth", Address::SocketAddress(udp_target), 0)
+        .await
+        .unwrap();
+    let relay = client.model.connect(Address::SocketAddress(tcp_target));
+    let (relay, ()) = tokio::join!(relay, client.authenticate(PASSWORD));
+
+    let (echo, _, _) = client.recv_packet().await;
+    assert_eq!(echo, "sent before auth".as_bytes());
+
+    let mut relay = relay.unwrap().compat();
+
+    let relay = async {
+        relay.write_all(b"sent before auth").await?;
+        relay.shutdown().await?;
+
+        let mut buf = Vec::new();
+        relay.read_to_end(&mut buf).await?;
+        Ok::<_, std::io::Error>(buf)
+    };
+
+    let buf = time::timeout(TIMEOUT, relay).await.unwrap().unwrap();
+    assert_eq!(buf, b"sent before auth");
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use self::common::{Client, TestServer, PASSWORD, TIMEOUT, UUID};
+use bytes::{Bytes, BytesMut};
+use quinn::{ReadError, ReadToEndError, RecvStream, SendStream, VarInt};
+use serde_json::json;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::time;
+use tuic::{
+    Address, Associate, Authenticate, Bind, Connect, ConnectBound, ConnectLinked, Dissociate,
+    DissociateAll, Header, Heartbeat, Packet, Stats,
+};
+use uuid::Uuid;
+
+/// The code of streams, or the connection with `strict_protocol`, carrying a command of the wrong
+/// stream type
+const WRONG_STREAM_TYPE: u32 = 0x06;
+
+/// The code of streams carrying an extension command the client did not negotiate
+const EXTENSION_NOT_NEGOTIATED: u32 = 0x09;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Uni,
+    Bi,
+    Datagram,
+}
+
+/// Every command, with the kinds of stream it is allowed on
+fn commands() -> Vec<(Header, &'static [Kind])> {
+    let addr = Address::SocketAddress(SocketAddr::from((Ipv4Addr::LOCALHOST, 1)));
+
+    vec![
+        (
+            Header::Authenticate(Authenticate::new(Uuid::nil(), [0; 32])),
+            &[Kind::Uni],
+        ),
+        (Header::Connect(Connect::new(addr.clone())), &[Kind::Bi]),
+        (
+            Header::Packet(Packet::new(0, 0, 1, 0, 0, addr.clone())),
+            &[Kind::Uni, Kind::Datagram],
+        ),
+        (Header::Dissociate(Dissociate::new(0)), &[Kind::Uni]),
+        (Header::Heartbeat(Heartbeat::new()), &[Kind::Datagram]),
+        (Header::Stats(Stats::new()), &[Kind::Bi]),
+        (
+            Header::ConnectBound(ConnectBound::new(addr.clone())),
+            &[Kind::Bi],
+        ),
+        (Header::DissociateAll(DissociateAll::new()), &[Kind::Uni]),
+        (Header::Bind(Bind::new(addr.clone())), &[Kind::Bi]),
+        (
+            Header::ConnectLinked(ConnectLinked::new(0, addr)),
+            &[Kind::Bi],
+        ),
+        (Header::Associate(Associate::new(0, 0)), &[Kind::Uni]),
+    ]
+}
+
+/// The commands not allowed on the kind of stream
+fn mismatched(kind: Kind) -> impl Iterator<Item = Header> {
+    commands()
+        .into_iter()
+        .filter(move |(_, allowed)| !allowed.contains(&kind))
+        .map(|(header, _)| header)
+}
+
+fn marshal(header: &Header) -> Bytes {
+    let mut buf = BytesMut::new();
+    header.write(&mut buf);
+    buf.freeze()
+}
+
+/// A command sent by `send`, with the stream it is on
+enum Sent {
+    Uni(SendStream),
+    Bi(SendStream, RecvStream),
+    Datagram,
+}
+
+/// Sends `header` over a new stream of `kind`, or as a datagram
+async fn send(client: &Client, kind: Kind, header: &Header) -> Sent {
+    let buf = marshal(header);
+
+    match kind {
+        Kind::Uni => {
+            let mut send = client.conn.open_uni().await.unwrap();
+            send.write_all(&buf).await.unwrap();
+            Sent::Uni(send)
+        }
+        Kind::Bi => {
+            let (mut send, recv) = client.conn.open_bi().await.unwrap();
+            send.write_all(&buf).await.unwrap();
+            Sent::Bi(send, recv)
+        }
+        Kind::Datagram => {
+            client.conn.send_datagram(buf).unwrap();
+            Sent::Datagram
+        }
+    }
+}
+
+/// Waits for the server to reject the stream of `sent`, returning the code it did so with
+async fn rejection(sent: Sent) -> Option<VarInt> {
+    let code = async {
+        match sent {
+            Sent::Uni(mut send) => send.stopped().await.ok(),
+            Sent::Bi(_send, recv) => match recv.read_to_end(usize::MAX).await {
+                Err(ReadToEndError::Read(ReadError::Reset(code))) => Some(code),
+                _ => None,
+            },
+            // a datagram has no stream to reject
+            Sent::Datagram => None,
+        }
+    };
+
+    time::timeout(TIMEOUT, code)
+        .await
+        .expect("stream not rejected")
+}
+
+async fn check_rejected(kind: Kind) {
+    let server = TestServer::start(json!({})).await;
+    let client = server.connect_authed().await;
+
+    for header in mismatched(kind) {
+        let code = rejection(send(&client, kind, &header).await).await;
+        assert_eq!(
+            code,
+            Some(VarInt::from_u32(WRONG_STREAM_TYPE)),
+            "{header:?} on {kind:?}"
+        );
+    }
+
+    // only the streams are rejected
+    assert!(client.conn.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn wrong_commands_on_uni_streams_reset_the_stream() {
+    check_rejected(Kind::Uni).await;
+}
+
+#[tokio::test]
+async fn wrong_commands_on_bi_streams_reset_the_stream() {
+    check_rejected(Kind::Bi).await;
+}
+
+#[tokio::test]
+async fn wrong_commands_as_datagrams_are_dropped() {
+    let server = TestServer::start(json!({})).await;
+    let target = common::udp_echo().await;
+    let client = server.connect_authed().await;
+
+    for header in mismatched(Kind::Datagram) {
+        send(&client, Kind::Datagram, &header).await;
+    }
+
+    // a datagram has no stream to reset, it is just dropped and the connection keeps working
+    client
+        .model
+        .packet_native("after", Address::SocketAddress(target), 0)
+        .unwrap();
+
+    let (echo, _, _) = client.recv_packet().await;
+    assert_eq!(echo, "after".as_bytes());
+    assert!(client.conn.close_reason().is_none());
+}
+
+#[tokio::test]
+async fn wrong_commands_close_the_connection_with_strict_protocol() {
+    let server = TestServer::start(json!({ "strict_protocol": true })).await;
+
+    for kind in [Kind::Uni, Kind::Bi, Kind::Datagram] {
+        for header in mismatched(kind) {
+            let client = server.connect_authed().await;
+
+            let _sent = send(&client, kind, &header).await;
+            assert_eq!(
+                client.closed().await.into_inner(),
+                u64::from(WRONG_STREAM_TYPE),
+                "{header:?} on {kind:?}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn extension_commands_are_refused_unless_negotiated() {
+    let server = TestServer::start(json!({})).await;
+    let client = server.connect().await;
+    client.model.authenticate(UUID, PASSWORD).await.unwrap();
+
+    let code = rejection(send(&client, Kind::Bi, &Header::Stats(Stats::new())).await).await;
+    assert_eq!(code, Some(VarInt::from_u32(EXTENSION_NOT_NEGOTIATED)));
+    assert!(client.conn.close_reason().is_none());
+}
@@ -0,0 +1,249 @@
+mod common;
+
+use self::common::{Client, TestServer, TIMEOUT};
+use bytes::BytesMut;
+use quinn::{ReadError, VarInt};
+use serde_json::json;
+use socket2::SockRef;
+use std::{
+    io::Error as IoError,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::oneshot,
+    time,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::{Address, Connect, ConnectBound, Header, UnmarshalError};
+use tuic_quinn::{Connect as Relay, Error as ModelError};
+
+/// The stream reset code of a `Bind` the server does not serve
+const FORBIDDEN: u32 = 0x05;
+
+/// The stream reset code of a `Bind` no peer connected to in time
+const TIMED_OUT: u32 = 0x03;
+
+/// The stream reset code of a relay whose target reset the TCP connection
+const RESET: u32 = 0x07;
+
+fn reset_code(err: &IoError) -> Option<VarInt> {
+    match err.get_ref()?.downcast_ref::<ReadError>()? {
+        ReadError::Reset(code) => Some(*code),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn target_reset_resets_the_relay() {
+    let server = TestServer::start(json!({})).await;
+    let client = server.connect_authed().await;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let target = listener.local_addr().unwrap();
+
+    // the target answers part of the request, then aborts the connection
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(b"partial").await.unwrap();
+        SockRef::from(&stream)
+            .set_linger(Some(Duration::ZERO))
+            .unwrap();
+    });
+
+    let relay = client
+        .model
+        .connect(Address::SocketAddress(target))
+        .await
+        .unwrap();
+    let mut relay = relay.compat();
+
+    let relay = async {
+        relay.write_all(b"hello").await.unwrap();
+
+        let mut buf = Vec::new();
+        relay.read_to_end(&mut buf).await
+    };
+
+    let err = time::timeout(TIMEOUT, relay).await.unwrap().unwrap_err();
+    assert_eq!(reset_code(&err), Some(VarInt::from_u32(RESET)), "{err}");
+}
+
+#[tokio::test]
+async fn client_reset_closes_the_target() {
+    let server = TestServer::start(json!({})).await;
+    let client = server.connect_authed().await;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let target_addr = listener.local_addr().unwrap();
+    let (received_tx, received_rx) = oneshot::channel();
+
+    let target = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        received_tx.send(()).unwrap();
+        stream.read(&mut buf).await
+    });
+
+    let relay = client
+        .model
+        .connect(Address::SocketAddress(target_addr))
+        .await
+        .unwrap();
+    let mut relay = relay.compat();
+    relay.write_all(b"hello").await.unwrap();
+
+    time::timeout(TIMEOUT, received_rx).await.unwrap().unwrap();
+    relay.into_inner().reset(VarInt::from_u32(0x42));
+
+    // a reset from the client is not passed on as one, the target sees the connection closed
+    let res = time::timeout(TIMEOUT, target).await.unwrap().unwrap();
+    assert_eq!(res.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn client_gone_after_connect_closes_the_target() {
+    let server = TestServer::start(json!({})).await;
+
+    for bound in [false, true] {
+        let client = server.connect_authed().await;
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let target_addr = Address::SocketAddress(listener.local_addr().unwrap());
+
+        let header = if bound {
+            Header::ConnectBound(ConnectBound::new(target_addr))
+        } else {
+            Header::Connect(Connect::new(target_addr))
+        };
+        let mut buf = BytesMut::new();
+        header.write(&mut buf);
+
+        let (mut send, _recv) = client.conn.open_bi().await.unwrap();
+        send.write_all(&buf).await.unwrap();
+
+        // the client vanishes as soon as the server has connected to the target, before or while
+        // the server answers
+        let (mut stream, _) = time::timeout(TIMEOUT, listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        client.conn.close(VarInt::from_u32(0), b"");
+
+        let mut buf = [0; 1];
+        let res = time::timeout(TIMEOUT, stream.read(&mut buf))
+            .await
+            .unwrap_or_else(|_| panic!("target left connected, bound: {bound}"));
+        assert_eq!(res.unwrap(), 0, "bound: {bound}");
+    }
+}
+
+/// Starts a `Bind` for `peer`, returning the relay with the address to connect to the server at
+async fn bind(client: &Client, peer: SocketAddr) -> (Relay, SocketAddr) {
+    let (relay, bound_addr) = client
+        .model
+        .bind(Address::SocketAddress(peer))
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(mut bound_addr) = bound_addr else {
+        panic!("{bound_addr:?}");
+    };
+
+    if bound_addr.ip().is_unspecified() {
+        bound_addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    (relay, bound_addr)
+}
+
+#[tokio::test]
+async fn bind_relays_the_peer() {
+    let server = TestServer::start(json!({ "bind_timeout": TIMEOUT })).await;
+    let client = server.connect_authed().await;
+
+    let (mut relay, bound_addr) = bind(&client, SocketAddr::from(([0, 0, 0, 0], 0))).await;
+    let mut peer = TcpStream::connect(bound_addr).await.unwrap();
+
+    let peer_addr = time::timeout(TIMEOUT, relay.recv_peer_addr())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        peer_addr,
+        Address::SocketAddress(peer.local_addr().unwrap())
+    );
+
+    let mut relay = relay.compat();
+
+    let relayed = async {
+        peer.write_all(b"ping").await?;
+        let mut buf = [0; 4];
+        relay.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"ping");
+
+        relay.write_all(b"pong").await?;
+        peer.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"pong");
+        Ok::<_, IoError>(())
+    };
+
+    time::timeout(TIMEOUT, relayed).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn bind_is_refused_without_bind_timeout() {
+    let server = TestServer::start(json!({})).await;
+    let client = server.connect_authed().await;
+
+    let peer = Address::SocketAddress(SocketAddr::from(([0, 0, 0, 0], 0)));
+    let res = time::timeout(TIMEOUT, client.model.bind(peer))
+        .await
+        .unwrap();
+
+    match res {
+        Err(ModelError::UnmarshalBoundAddress(UnmarshalError::Io(err))) => {
+            assert_eq!(reset_code(&err), Some(VarInt::from_u32(FORBIDDEN)), "{err}");
+        }
+        res => panic!("{res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn bind_only_takes_the_named_peer() {
+    let bind_timeout = Duration::from_millis(200);
+    let server = TestServer::start(json!({ "bind_timeout": bind_timeout })).await;
+    let client = server.connect_authed().await;
+
+    let (mut relay, bound_addr) = bind(&client, SocketAddr::from(([127, 0, 0, 2], 0))).await;
+
+    // a connection from any other address is dropped, and the server goes on waiting
+    let socket = TcpSocket::new_v4().unwrap();
+    socket
+        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .unwrap();
+    let mut stranger = socket.connect(bound_addr).await.unwrap();
+
+    let mut buf = [0; 1];
+    let res = time::timeout(TIMEOUT, stranger.read(&mut buf))
+        .await
+        .unwrap();
+    assert_eq!(res.unwrap(), 0);
+
+    let err = time::timeout(TIMEOUT, relay.recv_peer_addr())
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    match err {
+        ModelError::UnmarshalBoundAddress(UnmarshalError::Io(err)) => {
+            assert_eq!(reset_code(&err), Some(VarInt::from_u32(TIMED_OUT)), "{err}");
+        }
+        err => panic!("{err}"),
+    }
+}
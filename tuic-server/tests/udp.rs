@@ -0,0 +1,207 @@
+mod common;
+
+use self::common::{TestServer, TIMEOUT};
+use bytes::BytesMut;
+use quinn::VarInt;
+use serde_json::json;
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+    time,
+};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tuic::{Address, Header, Packet};
+
+/// The code of packet streams refused with `udp_relay` disabled
+const UDP_RELAY_DISABLED: u32 = 0x08;
+
+/// Starts a UDP server answering every packet with the address it came from, which is the
+/// address of the relay socket of the session
+async fn udp_whoami() -> SocketAddr {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = vec![0; 65535];
+
+        while let Ok((_, addr)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(addr.to_string().as_bytes(), addr).await;
+        }
+    });
+
+    addr
+}
+
+fn is_bound(port: u16) -> bool {
+    match StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)) {
+        Ok(_) => false,
+        Err(err) if err.kind() == ErrorKind::AddrInUse => true,
+        Err(err) => panic!("{err}"),
+    }
+}
+
+#[tokio::test]
+async fn dissociating_a_missing_session_is_harmless() {
+    let server = TestServer::start(json!({})).await;
+    let target = common::udp_echo().await;
+    let client = server.connect_authed().await;
+
+    client.model.dissociate(7).await.unwrap();
+
+    client
+        .model
+        .packet_native("after dissociating", Address::SocketAddress(target), 7)
+        .unwrap();
+
+    let (echo, _, assoc_id) = client.recv_packet().await;
+    assert_eq!(echo, "after dissociating".as_bytes());
+    assert_eq!(assoc_id, 7);
+}
+
+#[tokio::test]
+async fn failed_send_keeps_the_session() {
+    let server = TestServer::start(json!({})).await;
+    let target = common::udp_echo().await;
+    let client = server.connect_authed().await;
+
+    // nothing can be sent to port 0
+    let unreachable = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    client
+        .model
+        .packet_native("dropped", Address::SocketAddress(unreachable), 0)
+        .unwrap();
+    client
+        .model
+        .packet_native("relayed", Address::SocketAddress(target), 0)
+        .unwrap();
+
+    let (echo, _, _) = client.recv_packet().await;
+    assert_eq!(echo, "relayed".as_bytes());
+}
+
+#[tokio::test]
+async fn partial_packet_is_aborted_after_packet_timeout() {
+    let packet_timeout = Duration::from_millis(200);
+    let server = TestServer::start(json!({ "packet_timeout": packet_timeout })).await;
+    let target = common::udp_echo().await;
+    let client = server.connect_authed().await;
+
+    let header = Header::Packet(Packet::new(0, 0, 1, 0, 64, Address::SocketAddress(target)));
+    let mut buf = BytesMut::new();
+    header.write(&mut buf);
+
+    // the stream is left open, with only half of the payload declared in the header
+    let mut send = client.conn.open_uni().await.unwrap();
+    send.write_all(&buf).await.unwrap();
+    send.write_all(&[0; 32]).await.unwrap();
+
+    let start = Instant::now();
+    let stopped = time::timeout(TIMEOUT, send.stopped()).await.unwrap();
+    assert_eq!(stopped.unwrap(), VarInt::from_u32(0));
+    assert!(
+        start.elapsed() >= packet_timeout / 2,
+        "{:?}",
+        start.elapsed()
+    );
+
+    // only the stream is aborted
+    client
+        .model
+        .packet_quic("complete", Address::SocketAddress(target), 0)
+        .await
+        .unwrap();
+
+    let (echo, _, _) = client.recv_packet().await;
+    assert_eq!(echo, "complete".as_bytes());
+}
+
+#[tokio::test]
+async fn closing_the_connection_releases_the_sockets_of_its_sessions() {
+    let server = TestServer::start(json!({})).await;
+    let target = udp_whoami().await;
+    let client = server.connect_authed().await;
+
+    let mut ports = Vec::new();
+
+    for assoc_id in 0..3 {
+        client
+            .model
+            .packet_native("whoami", Address::SocketAddress(target), assoc_id)
+            .unwrap();
+
+        let (relay_addr, _, _) = client.recv_packet().await;
+        let relay_addr = std::str::from_utf8(&relay_addr).unwrap();
+        let relay_addr = relay_addr.parse::<SocketAddr>().unwrap();
+        ports.push(relay_addr.port());
+    }
+
+    assert!(ports.iter().all(|port| is_bound(*port)), "{ports:?}");
+
+    client.conn.close(VarInt::from_u32(0), b"");
+
+    let released = async {
+        while ports.iter().any(|port| is_bound(*port)) {
+            time::sleep(TIMEOUT / 100).await;
+        }
+    };
+
+    time::timeout(TIMEOUT, released)
+        .await
+        .unwrap_or_else(|_| panic!("sockets still bound: {ports:?}"));
+}
+
+#[tokio::test]
+async fn refused_packets_keep_the_tcp_relays() {
+    let server = TestServer::start(json!({ "udp_relay": false })).await;
+    let tcp_target = common::tcp_echo().await;
+    let udp_target = common::udp_echo().await;
+    let client = server.connect_authed().await;
+
+    let relay = client
+        .model
+        .connect(Address::SocketAddress(tcp_target))
+        .await
+        .unwrap();
+    let mut relay = relay.compat();
+    relay.write_all(b"before").await.unwrap();
+
+    client
+        .model
+        .packet_native("refused", Address::SocketAddress(udp_target), 0)
+        .unwrap();
+
+    let header = Header::Packet(Packet::new(
+        0,
+        0,
+        1,
+        0,
+        7,
+        Address::SocketAddress(udp_target),
+    ));
+    let mut buf = BytesMut::new();
+    header.write(&mut buf);
+    buf.extend_from_slice(b"refused");
+
+    let mut send = client.conn.open_uni().await.unwrap();
+    send.write_all(&buf).await.unwrap();
+    let stopped = time::timeout(TIMEOUT, send.stopped()).await.unwrap();
+    assert_eq!(stopped.unwrap(), VarInt::from_u32(UDP_RELAY_DISABLED));
+
+    let relay = async {
+        relay.write_all(b" and after").await?;
+        relay.shutdown().await?;
+
+        let mut buf = Vec::new();
+        relay.read_to_end(&mut buf).await?;
+        Ok::<_, std::io::Error>(buf)
+    };
+
+    let buf = time::timeout(TIMEOUT, relay).await.unwrap().unwrap();
+    assert_eq!(buf, b"before and after");
+    assert!(client.conn.close_reason().is_none());
+}
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use tuic::Header;
+
+// Unmarshals arbitrary bytes as a command header. Any input must produce either a header or an
+// error, never a panic. A successfully parsed header must marshal back to the bytes it was read
+// from.
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+
+    if let Ok(header) = Header::unmarshal(&mut cursor) {
+        let consumed = &data[..cursor.position() as usize];
+
+        let mut buf = Vec::with_capacity(header.len());
+        header.write(&mut buf);
+        assert_eq!(buf, consumed);
+    }
+});
@@ -3,7 +3,9 @@
 mod protocol;
 
 pub use self::protocol::{
-    Address, Authenticate, Connect, Dissociate, Header, Heartbeat, Packet, VERSION,
+    Address, Associate, Authenticate, Bind, Connect, ConnectBound, ConnectLinked, Dissociate,
+    DissociateAll, Header, Heartbeat, Packet, Stats, StatsResponse, EXTENSION_COMMANDS,
+    MAX_DOMAIN_LEN, MAX_HEADER_LEN, MAX_PACKET_SIZE, VERSION,
 };
 
 #[cfg(any(feature = "async_marshal", feature = "marshal"))]
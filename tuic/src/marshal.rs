@@ -1,5 +1,9 @@
-use crate::{Address, Authenticate, Connect, Dissociate, Header, Heartbeat, Packet, VERSION};
+use crate::{
+    Address, Associate, Authenticate, Bind, Connect, ConnectBound, ConnectLinked, Dissociate,
+    DissociateAll, Header, Heartbeat, Packet, Stats, StatsResponse, VERSION,
+};
 use bytes::{BufMut, BytesMut};
+#[cfg(feature = "async_marshal")]
 use futures_util::{AsyncWrite, AsyncWriteExt};
 use std::{
     io::{Error as IoError, Write},
@@ -34,11 +38,33 @@ impl Header {
             Self::Packet(packet) => packet.write(buf),
             Self::Dissociate(dissociate) => dissociate.write(buf),
             Self::Heartbeat(heartbeat) => heartbeat.write(buf),
+            Self::Stats(stats) => stats.write(buf),
+            Self::ConnectBound(conn) => conn.write(buf),
+            Self::DissociateAll(dissociate_all) => dissociate_all.write(buf),
+            Self::ConnectLinked(conn) => conn.write(buf),
+            Self::Associate(assoc) => assoc.write(buf),
+            Self::Bind(bind) => bind.write(buf),
         }
     }
 }
 
 impl Address {
+    /// Marshals the address into an `AsyncWrite` stream
+    #[cfg(feature = "async_marshal")]
+    pub async fn async_marshal(&self, s: &mut (impl AsyncWrite + Unpin)) -> Result<(), IoError> {
+        let mut buf = BytesMut::with_capacity(self.len());
+        self.write(&mut buf);
+        s.write_all(&buf).await
+    }
+
+    /// Marshals the address into a `Write` stream
+    #[cfg(feature = "marshal")]
+    pub fn marshal(&self, s: &mut impl Write) -> Result<(), IoError> {
+        let mut buf = BytesMut::with_capacity(self.len());
+        self.write(&mut buf);
+        s.write_all(&buf)
+    }
+
     fn write(&self, buf: &mut impl BufMut) {
         buf.put_u8(self.type_code());
 
@@ -76,6 +102,25 @@ impl Connect {
     }
 }
 
+impl ConnectBound {
+    fn write(&self, buf: &mut impl BufMut) {
+        self.addr().write(buf);
+    }
+}
+
+impl Bind {
+    fn write(&self, buf: &mut impl BufMut) {
+        self.addr().write(buf);
+    }
+}
+
+impl ConnectLinked {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.assoc_id());
+        self.addr().write(buf);
+    }
+}
+
 impl Packet {
     fn write(&self, buf: &mut impl BufMut) {
         buf.put_u16(self.assoc_id());
@@ -96,3 +141,44 @@ impl Dissociate {
 impl Heartbeat {
     fn write(&self, _buf: &mut impl BufMut) {}
 }
+
+impl Stats {
+    fn write(&self, _buf: &mut impl BufMut) {}
+}
+
+impl DissociateAll {
+    fn write(&self, _buf: &mut impl BufMut) {}
+}
+
+impl Associate {
+    fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.assoc_id());
+        buf.put_u32(self.timeout());
+    }
+}
+
+impl StatsResponse {
+    /// Marshals the response into an `AsyncWrite` stream
+    #[cfg(feature = "async_marshal")]
+    pub async fn async_marshal(&self, s: &mut (impl AsyncWrite + Unpin)) -> Result<(), IoError> {
+        let mut buf = BytesMut::with_capacity(self.len());
+        self.write(&mut buf);
+        s.write_all(&buf).await
+    }
+
+    /// Marshals the response into a `Write` stream
+    #[cfg(feature = "marshal")]
+    pub fn marshal(&self, s: &mut impl Write) -> Result<(), IoError> {
+        let mut buf = BytesMut::with_capacity(self.len());
+        self.write(&mut buf);
+        s.write_all(&buf)
+    }
+
+    /// Writes the response into a `BufMut`
+    pub fn write(&self, buf: &mut impl BufMut) {
+        buf.put_u64(self.upload());
+        buf.put_u64(self.download());
+        buf.put_u16(self.associations());
+        buf.put_u64(self.uptime());
+    }
+}
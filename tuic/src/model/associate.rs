@@ -0,0 +1,90 @@
+use super::side::{self, Side};
+use crate::{Associate as AssociateHeader, Header};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    time::Duration,
+};
+
+/// The model of the `Associate` command
+pub struct Associate<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl Associate<side::Tx> {
+    pub(super) fn new(assoc_id: u16, timeout: Option<Duration>) -> Self {
+        // `0` leaves the timeout to the server, so a timeout shorter than a millisecond is sent as
+        // the shortest one instead, and one too long to encode as the longest
+        let timeout = timeout.map_or(0, |timeout| {
+            u32::try_from(timeout.as_millis())
+                .unwrap_or(u32::MAX)
+                .max(1)
+        });
+
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::Associate(AssociateHeader::new(assoc_id, timeout)),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `Associate` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for Associate<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("Associate")
+            .field("header", &tx.header)
+            .finish()
+    }
+}
+
+struct Rx {
+    assoc_id: u16,
+    timeout: Option<Duration>,
+}
+
+impl Associate<side::Rx> {
+    pub(super) fn new(assoc_id: u16, timeout: u32) -> Self {
+        Self {
+            inner: Side::Rx(Rx {
+                assoc_id,
+                timeout: (timeout != 0).then(|| Duration::from_millis(u64::from(timeout))),
+            }),
+            _marker: side::Rx,
+        }
+    }
+
+    /// Returns the UDP session ID
+    pub fn assoc_id(&self) -> u16 {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.assoc_id
+    }
+
+    /// Returns the idle timeout the client wants for the session, `None` if it leaves it to the
+    /// server
+    pub fn timeout(&self) -> Option<Duration> {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.timeout
+    }
+}
+
+impl Debug for Associate<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        f.debug_struct("Associate")
+            .field("assoc_id", &rx.assoc_id)
+            .field("timeout", &rx.timeout)
+            .finish()
+    }
+}
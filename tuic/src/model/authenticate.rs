@@ -1,6 +1,7 @@
 use super::side::{self, Side};
 use crate::{Authenticate as AuthenticateHeader, Header};
 use std::fmt::{Debug, Formatter, Result as FmtResult};
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
 /// The model of the `Authenticate` command
@@ -71,14 +72,15 @@ impl Authenticate<side::Rx> {
         rx.token
     }
 
-    /// Returns whether the token is valid
+    /// Returns whether the token is valid. The comparison is done in constant time
     pub fn is_valid(
         &self,
         password: impl AsRef<[u8]>,
         exporter: &impl KeyingMaterialExporter,
     ) -> bool {
         let Side::Rx(rx) = &self.inner else { unreachable!() };
-        rx.token == exporter.export_keying_material(rx.uuid.as_ref(), password.as_ref())
+        let token = exporter.export_keying_material(rx.uuid.as_ref(), password.as_ref());
+        rx.token.ct_eq(&token).into()
     }
 }
 
@@ -1,5 +1,5 @@
 use super::side::{self, Side};
-use crate::{Address, Connect as ConnectHeader, Header};
+use crate::{Address, Header};
 use register_count::Register;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 
@@ -15,17 +15,17 @@ struct Tx {
 }
 
 impl Connect<side::Tx> {
-    pub(super) fn new(task_reg: Register, addr: Address) -> Self {
+    pub(super) fn new(task_reg: Register, header: Header) -> Self {
         Self {
             inner: Side::Tx(Tx {
-                header: Header::Connect(ConnectHeader::new(addr)),
+                header,
                 _task_reg: task_reg,
             }),
             _marker: side::Tx,
         }
     }
 
-    /// Returns the header of the `Connect` command
+    /// Returns the header of the `Connect`, `ConnectBound`, `ConnectLinked` or `Bind` command
     pub fn header(&self) -> &Header {
         let Side::Tx(tx) = &self.inner else { unreachable!() };
         &tx.header
@@ -43,14 +43,16 @@ impl Debug for Connect<side::Tx> {
 
 struct Rx {
     addr: Address,
+    assoc_id: Option<u16>,
     _task_reg: Register,
 }
 
 impl Connect<side::Rx> {
-    pub(super) fn new(task_reg: Register, addr: Address) -> Self {
+    pub(super) fn new(task_reg: Register, addr: Address, assoc_id: Option<u16>) -> Self {
         Self {
             inner: Side::Rx(Rx {
                 addr,
+                assoc_id,
                 _task_reg: task_reg,
             }),
             _marker: side::Rx,
@@ -62,11 +64,20 @@ impl Connect<side::Rx> {
         let Side::Rx(rx) = &self.inner else { unreachable!() };
         &rx.addr
     }
+
+    /// Returns the UDP relay session ID the relay is linked to, if received as a `ConnectLinked`
+    pub fn assoc_id(&self) -> Option<u16> {
+        let Side::Rx(rx) = &self.inner else { unreachable!() };
+        rx.assoc_id
+    }
 }
 
 impl Debug for Connect<side::Rx> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         let Side::Rx(rx) = &self.inner else { unreachable!() };
-        f.debug_struct("Connect").field("addr", &rx.addr).finish()
+        f.debug_struct("Connect")
+            .field("addr", &rx.addr)
+            .field("assoc_id", &rx.assoc_id)
+            .finish()
     }
 }
@@ -0,0 +1,56 @@
+use super::side::{self, Side};
+use crate::{DissociateAll as DissociateAllHeader, Header};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The model of the `DissociateAll` command
+pub struct DissociateAll<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl DissociateAll<side::Tx> {
+    pub(super) fn new() -> Self {
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::DissociateAll(DissociateAllHeader::new()),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `DissociateAll` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for DissociateAll<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("DissociateAll")
+            .field("header", &tx.header)
+            .finish()
+    }
+}
+
+struct Rx;
+
+impl DissociateAll<side::Rx> {
+    pub(super) fn new() -> Self {
+        Self {
+            inner: Side::Rx(Rx),
+            _marker: side::Rx,
+        }
+    }
+}
+
+impl Debug for DissociateAll<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("DissociateAll").finish()
+    }
+}
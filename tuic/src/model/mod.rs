@@ -1,8 +1,11 @@
 //! An abstraction of a TUIC connection, with packet fragmentation management and task counters. No I/O operation is involved internally
 
 use crate::{
-    Address, Authenticate as AuthenticateHeader, Connect as ConnectHeader,
-    Dissociate as DissociateHeader, Heartbeat as HeartbeatHeader, Packet as PacketHeader,
+    Address, Associate as AssociateHeader, Authenticate as AuthenticateHeader, Bind as BindHeader,
+    Connect as ConnectHeader, ConnectBound as ConnectBoundHeader,
+    ConnectLinked as ConnectLinkedHeader, Dissociate as DissociateHeader,
+    DissociateAll as DissociateAllHeader, Header, Heartbeat as HeartbeatHeader,
+    Packet as PacketHeader, Stats as StatsHeader,
 };
 use parking_lot::Mutex;
 use register_count::{Counter, Register};
@@ -19,18 +22,24 @@ use std::{
 use thiserror::Error;
 use uuid::Uuid;
 
+mod associate;
 mod authenticate;
 mod connect;
 mod dissociate;
+mod dissociate_all;
 mod heartbeat;
 mod packet;
+mod stats;
 
 pub use self::{
+    associate::Associate,
     authenticate::{Authenticate, KeyingMaterialExporter},
     connect::Connect,
     dissociate::Dissociate,
+    dissociate_all::DissociateAll,
     heartbeat::Heartbeat,
     packet::{Fragments, Packet},
+    stats::Stats,
 };
 
 /// An abstraction of a TUIC connection, with packet fragmentation management and task counters. No I/O operation is involved internally
@@ -75,13 +84,50 @@ where
 
     /// Sends a `Connect`
     pub fn send_connect(&self, addr: Address) -> Connect<side::Tx> {
-        Connect::<side::Tx>::new(self.task_connect_count.reg(), addr)
+        let header = Header::Connect(ConnectHeader::new(addr));
+        Connect::<side::Tx>::new(self.task_connect_count.reg(), header)
+    }
+
+    /// Sends a `ConnectBound`, modelled as a `Connect`
+    pub fn send_connect_bound(&self, addr: Address) -> Connect<side::Tx> {
+        let header = Header::ConnectBound(ConnectBoundHeader::new(addr));
+        Connect::<side::Tx>::new(self.task_connect_count.reg(), header)
+    }
+
+    /// Sends a `ConnectLinked`, modelled as a `Connect`
+    pub fn send_connect_linked(&self, assoc_id: u16, addr: Address) -> Connect<side::Tx> {
+        let header = Header::ConnectLinked(ConnectLinkedHeader::new(assoc_id, addr));
+        Connect::<side::Tx>::new(self.task_connect_count.reg(), header)
+    }
+
+    /// Sends a `Bind`, modelled as a `Connect`
+    pub fn send_bind(&self, addr: Address) -> Connect<side::Tx> {
+        let header = Header::Bind(BindHeader::new(addr));
+        Connect::<side::Tx>::new(self.task_connect_count.reg(), header)
     }
 
     /// Receives a `Connect`
     pub fn recv_connect(&self, header: ConnectHeader) -> Connect<side::Rx> {
         let (addr,) = header.into();
-        Connect::<side::Rx>::new(self.task_connect_count.reg(), addr)
+        Connect::<side::Rx>::new(self.task_connect_count.reg(), addr, None)
+    }
+
+    /// Receives a `ConnectBound`, modelled as a `Connect`
+    pub fn recv_connect_bound(&self, header: ConnectBoundHeader) -> Connect<side::Rx> {
+        let (addr,) = header.into();
+        Connect::<side::Rx>::new(self.task_connect_count.reg(), addr, None)
+    }
+
+    /// Receives a `ConnectLinked`, modelled as a `Connect`
+    pub fn recv_connect_linked(&self, header: ConnectLinkedHeader) -> Connect<side::Rx> {
+        let (assoc_id, addr) = header.into();
+        Connect::<side::Rx>::new(self.task_connect_count.reg(), addr, Some(assoc_id))
+    }
+
+    /// Receives a `Bind`, modelled as a `Connect`
+    pub fn recv_bind(&self, header: BindHeader) -> Connect<side::Rx> {
+        let (addr,) = header.into();
+        Connect::<side::Rx>::new(self.task_connect_count.reg(), addr, None)
     }
 
     /// Sends a `Packet`
@@ -135,6 +181,37 @@ where
         self.udp_sessions.lock().recv_dissociate(assoc_id)
     }
 
+    /// Sends an `Associate`
+    ///
+    /// The session itself starts with its first `Packet`, as without the command.
+    pub fn send_associate(&self, assoc_id: u16, timeout: Option<Duration>) -> Associate<side::Tx> {
+        Associate::<side::Tx>::new(assoc_id, timeout)
+    }
+
+    /// Receives an `Associate`
+    pub fn recv_associate(&self, header: AssociateHeader) -> Associate<side::Rx> {
+        let (assoc_id, timeout) = header.into();
+        Associate::<side::Rx>::new(assoc_id, timeout)
+    }
+
+    /// Terminates the UDP relay session linked to a finished `ConnectLinked` relay
+    ///
+    /// No command is sent for this, as both sides see the relay end.
+    pub fn dissociate_linked(&self, assoc_id: u16) {
+        self.udp_sessions.lock().dissociate_linked(assoc_id);
+    }
+
+    /// Sends a `DissociateAll`
+    pub fn send_dissociate_all(&self) -> DissociateAll<side::Tx> {
+        self.udp_sessions.lock().send_dissociate_all()
+    }
+
+    /// Receives a `DissociateAll`
+    pub fn recv_dissociate_all(&self, header: DissociateAllHeader) -> DissociateAll<side::Rx> {
+        let () = header.into();
+        self.udp_sessions.lock().recv_dissociate_all()
+    }
+
     /// Sends a `Heartbeat`
     pub fn send_heartbeat(&self) -> Heartbeat<side::Tx> {
         Heartbeat::<side::Tx>::new()
@@ -146,6 +223,17 @@ where
         Heartbeat::<side::Rx>::new()
     }
 
+    /// Sends a `Stats`
+    pub fn send_stats(&self) -> Stats<side::Tx> {
+        Stats::<side::Tx>::new()
+    }
+
+    /// Receives a `Stats`
+    pub fn recv_stats(&self, header: StatsHeader) -> Stats<side::Rx> {
+        let () = header.into();
+        Stats::<side::Rx>::new()
+    }
+
     /// Returns the number of `Connect` tasks
     pub fn task_connect_count(&self) -> usize {
         self.task_connect_count.count()
@@ -259,6 +347,20 @@ where
         Dissociate::<side::Rx>::new(assoc_id)
     }
 
+    fn dissociate_linked(&mut self, assoc_id: u16) {
+        self.sessions.remove(&assoc_id);
+    }
+
+    fn send_dissociate_all(&mut self) -> DissociateAll<side::Tx> {
+        self.sessions.clear();
+        DissociateAll::<side::Tx>::new()
+    }
+
+    fn recv_dissociate_all(&mut self) -> DissociateAll<side::Rx> {
+        self.sessions.clear();
+        DissociateAll::<side::Rx>::new()
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn insert(
         &mut self,
@@ -418,6 +520,14 @@ where
     ) -> Result<Option<Assemblable<B>>, AssembleError> {
         assert_eq!(data.as_ref().len(), size as usize);
 
+        // the buffer is sized by the first fragment received, later ones must agree with it
+        if frag_total != self.frag_total {
+            return Err(AssembleError::FragmentTotalMismatch(
+                self.frag_total,
+                frag_total,
+            ));
+        }
+
         if frag_id >= frag_total {
             return Err(AssembleError::InvalidFragmentId(frag_total, frag_id));
         }
@@ -516,4 +626,15 @@ pub enum AssembleError {
     InvalidAddress(&'static str),
     #[error("duplicated fragment: {0}")]
     DuplicatedFragment(u8),
+    #[error("expecting {0} fragments but got a fragment of {1}")]
+    FragmentTotalMismatch(u8, u8),
+}
+
+/// An error that can occur when fragmenting a packet
+#[derive(Debug, Error)]
+pub enum FragmentError {
+    #[error("packet of {0} bytes needs more than 255 fragments")]
+    TooManyFragments(usize),
+    #[error("max packet size {0} leaves no room for payload")]
+    MaxPacketSizeTooSmall(usize),
 }
@@ -1,8 +1,8 @@
 use super::{
     side::{self, Side},
-    Assemblable, AssembleError, UdpSessions,
+    Assemblable, AssembleError, FragmentError, UdpSessions,
 };
-use crate::{Address, Header, Packet as PacketHeader};
+use crate::{Address, Header, Packet as PacketHeader, MAX_PACKET_SIZE};
 use parking_lot::Mutex;
 use std::{
     fmt::{Debug, Formatter, Result as FmtResult},
@@ -37,7 +37,11 @@ impl<B> Packet<side::Tx, B> {
     }
 
     /// Fragment the payload into multiple packets
-    pub fn into_fragments<'a, P>(self, payload: P) -> Fragments<'a, P>
+    ///
+    /// Each fragment, including its header, fits in `max_pkt_size` bytes and carries at most
+    /// `MAX_PACKET_SIZE` bytes of payload. Fails if the payload would need more than 255 fragments, or if
+    /// `max_pkt_size` is too small to fit any payload.
+    pub fn into_fragments<'a, P>(self, payload: P) -> Result<Fragments<'a, P>, FragmentError>
     where
         P: AsRef<[u8]>,
     {
@@ -201,18 +205,29 @@ impl<'a, P> Fragments<'a, P>
 where
     P: AsRef<[u8]> + 'a,
 {
-    fn new(assoc_id: u16, pkt_id: u16, addr: Address, max_pkt_size: usize, payload: P) -> Self {
-        let first_frag_size = max_pkt_size - PacketHeader::len_without_addr() - addr.len();
-        let frag_size_addr_none =
-            max_pkt_size - PacketHeader::len_without_addr() - Address::None.len();
+    fn new(
+        assoc_id: u16,
+        pkt_id: u16,
+        addr: Address,
+        max_pkt_size: usize,
+        payload: P,
+    ) -> Result<Self, FragmentError> {
+        let first_frag_size = Self::frag_size(max_pkt_size, &addr);
+        let frag_size_addr_none = Self::frag_size(max_pkt_size, &Address::None);
+        let payload_len = payload.as_ref().len();
 
-        let frag_total = if first_frag_size < payload.as_ref().len() {
-            (1 + (payload.as_ref().len() - first_frag_size) / frag_size_addr_none + 1) as u8
+        let frag_total = if payload_len <= first_frag_size {
+            1
+        } else if first_frag_size == 0 || frag_size_addr_none == 0 {
+            return Err(FragmentError::MaxPacketSizeTooSmall(max_pkt_size));
         } else {
-            1u8
+            1 + (payload_len - first_frag_size + frag_size_addr_none - 1) / frag_size_addr_none
         };
 
-        Self {
+        let frag_total =
+            u8::try_from(frag_total).map_err(|_| FragmentError::TooManyFragments(payload_len))?;
+
+        Ok(Self {
             assoc_id,
             pkt_id,
             addr,
@@ -222,7 +237,14 @@ where
             next_frag_start: 0,
             payload,
             _marker: PhantomData,
-        }
+        })
+    }
+
+    fn frag_size(max_pkt_size: usize, addr: &Address) -> usize {
+        // the header is prefixed with `VER` and `TYPE`
+        max_pkt_size
+            .saturating_sub(2 + PacketHeader::len_without_addr() + addr.len())
+            .min(MAX_PACKET_SIZE as usize)
     }
 }
 
@@ -234,8 +256,7 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.next_frag_id < self.frag_total {
-            let payload_size =
-                self.max_pkt_size - PacketHeader::len_without_addr() - self.addr.len();
+            let payload_size = Self::frag_size(self.max_pkt_size, &self.addr);
             let next_frag_end =
                 (self.next_frag_start + payload_size).min(self.payload.as_ref().len());
 
@@ -248,9 +269,8 @@ where
                 self.addr.take(),
             ));
 
-            let payload_ptr = &(self.payload.as_ref()[self.next_frag_start]) as *const u8;
-            let payload =
-                unsafe { slice::from_raw_parts(payload_ptr, next_frag_end - self.next_frag_start) };
+            let payload = &self.payload.as_ref()[self.next_frag_start..next_frag_end];
+            let payload = unsafe { slice::from_raw_parts(payload.as_ptr(), payload.len()) };
 
             self.next_frag_id += 1;
             self.next_frag_start = next_frag_end;
@@ -270,3 +290,172 @@ where
         self.frag_total as usize
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Connection;
+    use std::net::SocketAddr;
+
+    const MAX_PKT_SIZE: usize = 1200;
+
+    fn addr() -> Address {
+        Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 5353)))
+    }
+
+    fn fragment(
+        addr: Address,
+        max_pkt_size: usize,
+        payload: &[u8],
+    ) -> Result<Vec<(PacketHeader, &[u8])>, FragmentError> {
+        let conn = Connection::<Vec<u8>>::new();
+        let frags = conn
+            .send_packet(0, addr, max_pkt_size)
+            .into_fragments(payload)?
+            .map(|(header, frag)| match header {
+                Header::Packet(header) => (header, frag),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        Ok(frags)
+    }
+
+    fn reassemble(frags: &[(PacketHeader, &[u8])]) -> (Vec<u8>, Address) {
+        let conn = Connection::<Vec<u8>>::new();
+        let mut assemblable = None;
+
+        for (header, frag) in frags {
+            assert!(assemblable.is_none(), "assembled before the last fragment");
+            let pkt = conn.recv_packet_unrestricted(header.clone());
+            assemblable = pkt.assemble(frag.to_vec()).unwrap();
+        }
+
+        let mut buf = Vec::new();
+        let (addr, _) = assemblable.expect("not assembled").assemble(&mut buf);
+        (buf, addr)
+    }
+
+    /// Marshals each fragment and unmarshals it back, as the receiver would
+    fn over_the_wire<'a>(frags: &[(PacketHeader, &'a [u8])]) -> Vec<(PacketHeader, &'a [u8])> {
+        frags
+            .iter()
+            .map(|(header, frag)| {
+                let mut buf = Vec::new();
+                Header::Packet(header.clone()).marshal(&mut buf).unwrap();
+                buf.extend_from_slice(frag);
+
+                let mut s = buf.as_slice();
+                let Header::Packet(header) = Header::unmarshal(&mut s).unwrap() else {
+                    unreachable!()
+                };
+                assert_eq!((header.size() as usize, s), (frag.len(), *frag));
+
+                (header, *frag)
+            })
+            .collect()
+    }
+
+    fn bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn empty_payload_is_one_fragment() {
+        let frags = fragment(addr(), MAX_PKT_SIZE, &[]).unwrap();
+
+        assert_eq!(frags.len(), 1);
+        assert_eq!(frags[0].0.size(), 0);
+        assert_eq!(reassemble(&frags), (Vec::new(), addr()));
+    }
+
+    #[test]
+    fn exact_multiples_of_the_fragment_size() {
+        let first = Fragments::<&[u8]>::frag_size(MAX_PKT_SIZE, &addr());
+        let rest = Fragments::<&[u8]>::frag_size(MAX_PKT_SIZE, &Address::None);
+
+        for (len, frag_total) in [(first, 1), (first + rest, 2), (first + 3 * rest, 4)] {
+            let payload = bytes(len);
+            let frags = fragment(addr(), MAX_PKT_SIZE, &payload).unwrap();
+
+            assert_eq!(frags.len(), frag_total, "payload of {len} bytes");
+
+            for (frag_id, (header, frag)) in frags.iter().enumerate() {
+                assert_eq!(header.frag_id() as usize, frag_id);
+                assert_eq!(header.frag_total() as usize, frag_total);
+                assert_eq!(header.size() as usize, frag.len());
+                // every fragment is full, filling the packet up to the limit
+                assert_eq!(2 + header.len() + frag.len(), MAX_PKT_SIZE);
+            }
+
+            assert_eq!(reassemble(&frags), (payload, addr()));
+        }
+    }
+
+    #[test]
+    fn one_byte_over_a_multiple_takes_another_fragment() {
+        let first = Fragments::<&[u8]>::frag_size(MAX_PKT_SIZE, &addr());
+        let rest = Fragments::<&[u8]>::frag_size(MAX_PKT_SIZE, &Address::None);
+
+        let payload = bytes(first + rest + 1);
+        let frags = fragment(addr(), MAX_PKT_SIZE, &payload).unwrap();
+
+        assert_eq!(frags.len(), 3);
+        assert_eq!(frags[2].0.size(), 1);
+        assert_eq!(reassemble(&frags), (payload, addr()));
+    }
+
+    #[test]
+    fn payload_of_u16_max() {
+        // fragments are capped at what a receiver accepts, however large packets may be
+        let payload = bytes(u16::MAX as usize);
+        let frags = fragment(addr(), usize::MAX, &payload).unwrap();
+
+        assert_eq!(frags.len(), 2);
+        assert_eq!(frags[0].0.size(), MAX_PACKET_SIZE);
+        assert_eq!(frags[1].0.size(), u16::MAX - MAX_PACKET_SIZE);
+        assert_eq!(reassemble(&over_the_wire(&frags)), (payload, addr()));
+    }
+
+    #[test]
+    fn payload_of_u16_max_in_small_packets() {
+        let first = Fragments::<&[u8]>::frag_size(MAX_PKT_SIZE, &addr());
+        let rest = Fragments::<&[u8]>::frag_size(MAX_PKT_SIZE, &Address::None);
+        let payload = bytes(u16::MAX as usize);
+        let frags = fragment(addr(), MAX_PKT_SIZE, &payload).unwrap();
+
+        assert_eq!(
+            frags.len(),
+            1 + (u16::MAX as usize - first + rest - 1) / rest
+        );
+        assert_eq!(reassemble(&over_the_wire(&frags)), (payload, addr()));
+    }
+
+    #[test]
+    fn too_many_fragments() {
+        let rest = Fragments::<&[u8]>::frag_size(MAX_PKT_SIZE, &Address::None);
+
+        let payload = bytes(rest * 255);
+        let frags = fragment(Address::None, MAX_PKT_SIZE, &payload).unwrap();
+        assert_eq!(frags.len(), 255);
+
+        let payload = bytes(rest * 255 + 1);
+        assert!(matches!(
+            fragment(Address::None, MAX_PKT_SIZE, &payload),
+            Err(FragmentError::TooManyFragments(len)) if len == rest * 255 + 1
+        ));
+    }
+
+    #[test]
+    fn max_packet_size_too_small() {
+        let max_pkt_size = 2 + PacketHeader::len_without_addr() + addr().len();
+
+        // nothing to fragment still fits
+        assert_eq!(fragment(addr(), max_pkt_size, &[]).unwrap().len(), 1);
+
+        assert!(matches!(
+            fragment(addr(), max_pkt_size, &[0]),
+            Err(FragmentError::MaxPacketSizeTooSmall(size)) if size == max_pkt_size
+        ));
+    }
+}
@@ -0,0 +1,56 @@
+use super::side::{self, Side};
+use crate::{Header, Stats as StatsHeader};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The model of the `Stats` command
+pub struct Stats<M> {
+    inner: Side<Tx, Rx>,
+    _marker: M,
+}
+
+struct Tx {
+    header: Header,
+}
+
+impl Stats<side::Tx> {
+    pub(super) fn new() -> Self {
+        Self {
+            inner: Side::Tx(Tx {
+                header: Header::Stats(StatsHeader::new()),
+            }),
+            _marker: side::Tx,
+        }
+    }
+
+    /// Returns the header of the `Stats` command
+    pub fn header(&self) -> &Header {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        &tx.header
+    }
+}
+
+impl Debug for Stats<side::Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let Side::Tx(tx) = &self.inner else { unreachable!() };
+        f.debug_struct("Stats")
+            .field("header", &tx.header)
+            .finish()
+    }
+}
+
+struct Rx;
+
+impl Stats<side::Rx> {
+    pub(super) fn new() -> Self {
+        Self {
+            inner: Side::Rx(Rx),
+            _marker: side::Rx,
+        }
+    }
+}
+
+impl Debug for Stats<side::Rx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("Stats").finish()
+    }
+}
@@ -0,0 +1,55 @@
+/// Command `Associate`
+/// ```plain
+/// +----------+---------+
+/// | ASSOC_ID | TIMEOUT |
+/// +----------+---------+
+/// |    2     |    4    |
+/// +----------+---------+
+/// ```
+///
+/// where:
+///
+/// - `ASSOC_ID` - UDP relay session ID
+/// - `TIMEOUT` - how long the session may stay idle before the server terminates it, in
+///   milliseconds. `0` leaves it to the server
+#[derive(Clone, Debug)]
+pub struct Associate {
+    assoc_id: u16,
+    timeout: u32,
+}
+
+impl Associate {
+    const TYPE_CODE: u8 = 0x09;
+
+    /// Creates a new `Associate` command
+    pub const fn new(assoc_id: u16, timeout: u32) -> Self {
+        Self { assoc_id, timeout }
+    }
+
+    /// Returns the UDP relay session ID
+    pub fn assoc_id(&self) -> u16 {
+        self.assoc_id
+    }
+
+    /// Returns the idle timeout in milliseconds, `0` if the client leaves it to the server
+    pub fn timeout(&self) -> u32 {
+        self.timeout
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2 + 4
+    }
+}
+
+impl From<Associate> for (u16, u32) {
+    fn from(assoc: Associate) -> Self {
+        (assoc.assoc_id, assoc.timeout)
+    }
+}
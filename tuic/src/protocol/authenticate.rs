@@ -13,6 +13,10 @@ use uuid::Uuid;
 ///
 /// - `UUID` - client UUID
 /// - `TOKEN` - client token. The client raw password is hashed into a 256-bit long token using [TLS Keying Material Exporter](https://www.rfc-editor.org/rfc/rfc5705) on current TLS session. While exporting, the `label` should be the client UUID and the `context` should be the raw password.
+///
+/// A client may follow the command with one byte of extension flags, such as
+/// [`EXTENSION_COMMANDS`](crate::EXTENSION_COMMANDS), before finishing the stream. It is not part
+/// of the command, so servers not knowing of any extension never read it.
 #[derive(Clone, Debug)]
 pub struct Authenticate {
     uuid: Uuid,
@@ -0,0 +1,53 @@
+use super::Address;
+
+/// Command `Bind`
+/// ```plain
+/// +----------+
+/// |   ADDR   |
+/// +----------+
+/// | Variable |
+/// +----------+
+/// ```
+///
+/// where:
+///
+/// - `ADDR` - address of the peer expected to connect
+///
+/// The server listens for a TCP connection from the peer, as with a SOCKS5 `BIND`. It first sends
+/// the address it listens on, then the address of the peer once connected, both as an
+/// [`Address`], before relaying any data
+#[derive(Clone, Debug)]
+pub struct Bind {
+    addr: Address,
+}
+
+impl Bind {
+    const TYPE_CODE: u8 = 0x0a;
+
+    /// Creates a new `Bind` command
+    pub const fn new(addr: Address) -> Self {
+        Self { addr }
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.addr.len()
+    }
+}
+
+impl From<Bind> for (Address,) {
+    fn from(bind: Bind) -> Self {
+        (bind.addr,)
+    }
+}
@@ -0,0 +1,52 @@
+use super::Address;
+
+/// Command `ConnectBound`
+/// ```plain
+/// +----------+
+/// |   ADDR   |
+/// +----------+
+/// | Variable |
+/// +----------+
+/// ```
+///
+/// where:
+///
+/// - `ADDR` - target address
+///
+/// Same as `Connect`, except that once connected, the server first sends the local address of its
+/// connection to the target on the stream, as an [`Address`], before relaying any data
+#[derive(Clone, Debug)]
+pub struct ConnectBound {
+    addr: Address,
+}
+
+impl ConnectBound {
+    const TYPE_CODE: u8 = 0x06;
+
+    /// Creates a new `ConnectBound` command
+    pub const fn new(addr: Address) -> Self {
+        Self { addr }
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.addr.len()
+    }
+}
+
+impl From<ConnectBound> for (Address,) {
+    fn from(conn: ConnectBound) -> Self {
+        (conn.addr,)
+    }
+}
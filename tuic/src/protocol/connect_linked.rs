@@ -0,0 +1,59 @@
+use super::Address;
+
+/// Command `ConnectLinked`
+/// ```plain
+/// +----------+----------+
+/// | ASSOC_ID |   ADDR   |
+/// +----------+----------+
+/// |    2     | Variable |
+/// +----------+----------+
+/// ```
+///
+/// where:
+///
+/// - `ASSOC_ID` - UDP relay session ID to tie to the relay
+/// - `ADDR` - target address
+///
+/// Same as `Connect`, except that the UDP relay session `ASSOC_ID` lives no longer than the TCP
+/// relay: once the relay ends, both sides dissociate it, as if a `Dissociate` was sent
+#[derive(Clone, Debug)]
+pub struct ConnectLinked {
+    assoc_id: u16,
+    addr: Address,
+}
+
+impl ConnectLinked {
+    const TYPE_CODE: u8 = 0x08;
+
+    /// Creates a new `ConnectLinked` command
+    pub const fn new(assoc_id: u16, addr: Address) -> Self {
+        Self { assoc_id, addr }
+    }
+
+    /// Returns the UDP relay session ID
+    pub fn assoc_id(&self) -> u16 {
+        self.assoc_id
+    }
+
+    /// Returns the address
+    pub fn addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2 + self.addr.len()
+    }
+}
+
+impl From<ConnectLinked> for (u16, Address) {
+    fn from(conn: ConnectLinked) -> Self {
+        (conn.assoc_id, conn.addr)
+    }
+}
@@ -0,0 +1,35 @@
+/// Command `DissociateAll`
+/// ```plain
+/// +-+
+/// | |
+/// +-+
+/// | |
+/// +-+
+/// ```
+#[derive(Clone, Debug)]
+pub struct DissociateAll;
+
+impl DissociateAll {
+    const TYPE_CODE: u8 = 0x07;
+
+    /// Creates a new `DissociateAll` command
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        0
+    }
+}
+
+impl From<DissociateAll> for () {
+    fn from(_: DissociateAll) -> Self {}
+}
@@ -4,20 +4,50 @@ use std::{
     net::SocketAddr,
 };
 
+mod associate;
 mod authenticate;
+mod bind;
 mod connect;
+mod connect_bound;
+mod connect_linked;
 mod dissociate;
+mod dissociate_all;
 mod heartbeat;
 mod packet;
+mod stats;
 
 pub use self::{
-    authenticate::Authenticate, connect::Connect, dissociate::Dissociate, heartbeat::Heartbeat,
+    associate::Associate,
+    authenticate::Authenticate,
+    bind::Bind,
+    connect::Connect,
+    connect_bound::ConnectBound,
+    connect_linked::ConnectLinked,
+    dissociate::Dissociate,
+    dissociate_all::DissociateAll,
+    heartbeat::Heartbeat,
     packet::Packet,
+    stats::{Stats, StatsResponse},
 };
 
 /// The TUIC protocol version
 pub const VERSION: u8 = 0x05;
 
+/// The maximum length of a domain name in an [`Address`], in bytes
+///
+/// The length prefix could declare up to 255 bytes, but no valid domain name is longer than 253.
+pub const MAX_DOMAIN_LEN: usize = 253;
+
+/// The maximum payload size declared by a [`Packet`], the largest UDP payload over IPv6
+pub const MAX_PACKET_SIZE: u16 = 65527;
+
+/// The maximum serialized length of a [`Header`], reached by a `Packet` carrying a domain address
+pub const MAX_HEADER_LEN: usize = 2 + 8 + (1 + 1 + MAX_DOMAIN_LEN + 2);
+
+/// The extension flag a client sets after [`Authenticate`] to use the commands from `Stats` to
+/// `Bind`, which are not part of TUIC v5
+pub const EXTENSION_COMMANDS: u8 = 0b0000_0001;
+
 /// The command header for negotiating tasks
 /// ```plain
 /// +-----+------+----------+
@@ -35,15 +65,28 @@ pub const VERSION: u8 = 0x05;
 ///
 /// ## Command Types
 ///
-/// There are five types of command:
+/// There are eleven types of command:
 ///
 /// - `0x00` - `Authenticate` - for authenticating the multiplexed stream
 /// - `0x01` - `Connect` - for establishing a TCP relay
 /// - `0x02` - `Packet` - for relaying (fragmented part of) a UDP packet
 /// - `0x03` - `Dissociate` - for terminating a UDP relaying session
 /// - `0x04` - `Heartbeat` - for keeping the QUIC connection alive
+/// - `0x05` - `Stats` - for querying the statistics of the connection
+/// - `0x06` - `ConnectBound` - for establishing a TCP relay, learning the address the server
+///   connected from
+/// - `0x07` - `DissociateAll` - for terminating every UDP relaying session of the connection
+/// - `0x08` - `ConnectLinked` - for establishing a TCP relay that a UDP relaying session is
+///   terminated with
+/// - `0x09` - `Associate` - for setting up a UDP relaying session with the idle timeout the client
+///   wants
+/// - `0x0a` - `Bind` - for establishing a TCP relay with a peer connecting to the server
 ///
-/// Command `Connect` and `Packet` carry payload (stream / packet fragment)
+/// Commands `0x05` to `0x0a` are non-standard extensions, only to be sent once negotiated with
+/// [`EXTENSION_COMMANDS`]
+///
+/// Command `Connect`, `ConnectBound`, `ConnectLinked`, `Bind` and `Packet` carry payload
+/// (stream / packet fragment)
 #[non_exhaustive]
 #[derive(Clone, Debug)]
 pub enum Header {
@@ -52,6 +95,12 @@ pub enum Header {
     Packet(Packet),
     Dissociate(Dissociate),
     Heartbeat(Heartbeat),
+    Stats(Stats),
+    ConnectBound(ConnectBound),
+    DissociateAll(DissociateAll),
+    ConnectLinked(ConnectLinked),
+    Associate(Associate),
+    Bind(Bind),
 }
 
 impl Header {
@@ -60,6 +109,12 @@ impl Header {
     pub const TYPE_CODE_PACKET: u8 = Packet::type_code();
     pub const TYPE_CODE_DISSOCIATE: u8 = Dissociate::type_code();
     pub const TYPE_CODE_HEARTBEAT: u8 = Heartbeat::type_code();
+    pub const TYPE_CODE_STATS: u8 = Stats::type_code();
+    pub const TYPE_CODE_CONNECT_BOUND: u8 = ConnectBound::type_code();
+    pub const TYPE_CODE_DISSOCIATE_ALL: u8 = DissociateAll::type_code();
+    pub const TYPE_CODE_CONNECT_LINKED: u8 = ConnectLinked::type_code();
+    pub const TYPE_CODE_ASSOCIATE: u8 = Associate::type_code();
+    pub const TYPE_CODE_BIND: u8 = Bind::type_code();
 
     /// Returns the command type code
     pub const fn type_code(&self) -> u8 {
@@ -69,6 +124,12 @@ impl Header {
             Self::Packet(_) => Packet::type_code(),
             Self::Dissociate(_) => Dissociate::type_code(),
             Self::Heartbeat(_) => Heartbeat::type_code(),
+            Self::Stats(_) => Stats::type_code(),
+            Self::ConnectBound(_) => ConnectBound::type_code(),
+            Self::DissociateAll(_) => DissociateAll::type_code(),
+            Self::ConnectLinked(_) => ConnectLinked::type_code(),
+            Self::Associate(_) => Associate::type_code(),
+            Self::Bind(_) => Bind::type_code(),
         }
     }
 
@@ -81,6 +142,12 @@ impl Header {
             Self::Packet(packet) => packet.len(),
             Self::Dissociate(dissociate) => dissociate.len(),
             Self::Heartbeat(heartbeat) => heartbeat.len(),
+            Self::Stats(stats) => stats.len(),
+            Self::ConnectBound(conn) => conn.len(),
+            Self::DissociateAll(dissociate_all) => dissociate_all.len(),
+            Self::ConnectLinked(conn) => conn.len(),
+            Self::Associate(assoc) => assoc.len(),
+            Self::Bind(bind) => bind.len(),
         }
     }
 }
@@ -111,8 +178,9 @@ impl Header {
 /// Address type `None` is used in `Packet` commands that is not the first fragment of a UDP packet.
 ///
 /// The port number is encoded in 2 bytes after the Domain name / IP address.
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Address {
+    #[default]
     None,
     DomainAddress(String, u16),
     SocketAddress(SocketAddr),
@@ -179,14 +247,12 @@ impl Display for Address {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
             Self::None => write!(f, "none"),
+            // IPv6 literals sent as domain names are bracketed, so the port stays unambiguous
+            Self::DomainAddress(addr, port) if addr.contains(':') && !addr.starts_with('[') => {
+                write!(f, "[{addr}]:{port}")
+            }
             Self::DomainAddress(addr, port) => write!(f, "{addr}:{port}"),
             Self::SocketAddress(addr) => write!(f, "{addr}"),
         }
     }
 }
-
-impl Default for Address {
-    fn default() -> Self {
-        Self::None
-    }
-}
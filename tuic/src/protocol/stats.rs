@@ -0,0 +1,101 @@
+/// Command `Stats`
+///
+/// ```plain
+/// +-+
+/// | |
+/// +-+
+/// | |
+/// +-+
+/// ```
+///
+/// Sent by the client through a `bidirectional_stream`, the server answers with a
+/// [`StatsResponse`] on the same stream
+#[derive(Clone, Debug)]
+pub struct Stats;
+
+impl Stats {
+    const TYPE_CODE: u8 = 0x05;
+
+    /// Creates a new `Stats` command
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// Returns the command type code
+    pub const fn type_code() -> u8 {
+        Self::TYPE_CODE
+    }
+
+    /// Returns the serialized length of the command
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        0
+    }
+}
+
+impl From<Stats> for () {
+    fn from(_: Stats) -> Self {}
+}
+
+/// The response to a `Stats` command
+///
+/// ```plain
+/// +--------+----------+--------------+--------+
+/// | UPLOAD | DOWNLOAD | ASSOCIATIONS | UPTIME |
+/// +--------+----------+--------------+--------+
+/// |   8    |    8     |      2       |   8    |
+/// +--------+----------+--------------+--------+
+/// ```
+///
+/// where:
+///
+/// - `UPLOAD` - bytes relayed from the client to remote hosts on this connection
+/// - `DOWNLOAD` - bytes relayed from remote hosts to the client on this connection
+/// - `ASSOCIATIONS` - number of active UDP relay sessions
+/// - `UPTIME` - seconds since the connection was established
+#[derive(Clone, Debug)]
+pub struct StatsResponse {
+    upload: u64,
+    download: u64,
+    associations: u16,
+    uptime: u64,
+}
+
+impl StatsResponse {
+    /// Creates a new `StatsResponse`
+    pub const fn new(upload: u64, download: u64, associations: u16, uptime: u64) -> Self {
+        Self {
+            upload,
+            download,
+            associations,
+            uptime,
+        }
+    }
+
+    /// Returns the bytes relayed from the client to remote hosts
+    pub fn upload(&self) -> u64 {
+        self.upload
+    }
+
+    /// Returns the bytes relayed from remote hosts to the client
+    pub fn download(&self) -> u64 {
+        self.download
+    }
+
+    /// Returns the number of active UDP relay sessions
+    pub fn associations(&self) -> u16 {
+        self.associations
+    }
+
+    /// Returns the seconds since the connection was established
+    pub fn uptime(&self) -> u64 {
+        self.uptime
+    }
+
+    /// Returns the serialized length of the response
+    #[allow(clippy::len_without_is_empty)]
+    pub const fn len(&self) -> usize {
+        8 + 8 + 2 + 8
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{Address, Authenticate, Connect, Dissociate, Header, Heartbeat, Packet, VERSION};
+use crate::{
+    Address, Associate, Authenticate, Bind, Connect, ConnectBound, ConnectLinked, Dissociate,
+    DissociateAll, Header, Heartbeat, Packet, Stats, StatsResponse, MAX_DOMAIN_LEN,
+    MAX_PACKET_SIZE, VERSION,
+};
+#[cfg(feature = "async_marshal")]
 use futures_util::{AsyncRead, AsyncReadExt};
 use std::{
     io::{Error as IoError, Read},
@@ -32,6 +37,18 @@ impl Header {
             Header::TYPE_CODE_PACKET => Packet::async_read(s).await.map(Self::Packet),
             Header::TYPE_CODE_DISSOCIATE => Dissociate::async_read(s).await.map(Self::Dissociate),
             Header::TYPE_CODE_HEARTBEAT => Heartbeat::async_read(s).await.map(Self::Heartbeat),
+            Header::TYPE_CODE_STATS => Stats::async_read(s).await.map(Self::Stats),
+            Header::TYPE_CODE_CONNECT_BOUND => {
+                ConnectBound::async_read(s).await.map(Self::ConnectBound)
+            }
+            Header::TYPE_CODE_DISSOCIATE_ALL => {
+                DissociateAll::async_read(s).await.map(Self::DissociateAll)
+            }
+            Header::TYPE_CODE_CONNECT_LINKED => {
+                ConnectLinked::async_read(s).await.map(Self::ConnectLinked)
+            }
+            Header::TYPE_CODE_ASSOCIATE => Associate::async_read(s).await.map(Self::Associate),
+            Header::TYPE_CODE_BIND => Bind::async_read(s).await.map(Self::Bind),
             _ => Err(UnmarshalError::InvalidCommand(cmd)),
         }
     }
@@ -57,12 +74,30 @@ impl Header {
             Header::TYPE_CODE_PACKET => Packet::read(s).map(Self::Packet),
             Header::TYPE_CODE_DISSOCIATE => Dissociate::read(s).map(Self::Dissociate),
             Header::TYPE_CODE_HEARTBEAT => Heartbeat::read(s).map(Self::Heartbeat),
+            Header::TYPE_CODE_STATS => Stats::read(s).map(Self::Stats),
+            Header::TYPE_CODE_CONNECT_BOUND => ConnectBound::read(s).map(Self::ConnectBound),
+            Header::TYPE_CODE_DISSOCIATE_ALL => DissociateAll::read(s).map(Self::DissociateAll),
+            Header::TYPE_CODE_CONNECT_LINKED => ConnectLinked::read(s).map(Self::ConnectLinked),
+            Header::TYPE_CODE_ASSOCIATE => Associate::read(s).map(Self::Associate),
+            Header::TYPE_CODE_BIND => Bind::read(s).map(Self::Bind),
             _ => Err(UnmarshalError::InvalidCommand(cmd)),
         }
     }
 }
 
 impl Address {
+    /// Unmarshals an address from an `AsyncRead` stream
+    #[cfg(feature = "async_marshal")]
+    pub async fn async_unmarshal(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        Self::async_read(s).await
+    }
+
+    /// Unmarshals an address from a `Read` stream
+    #[cfg(feature = "marshal")]
+    pub fn unmarshal(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        Self::read(s)
+    }
+
     #[cfg(feature = "async_marshal")]
     async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
         let mut buf = [0; 1];
@@ -76,6 +111,10 @@ impl Address {
                 s.read_exact(&mut buf).await?;
                 let len = buf[0] as usize;
 
+                if len > MAX_DOMAIN_LEN {
+                    return Err(UnmarshalError::DomainTooLong(len));
+                }
+
                 let mut buf = vec![0; len + 2];
                 s.read_exact(&mut buf).await?;
                 let port = u16::from_be_bytes([buf[len], buf[len + 1]]);
@@ -125,6 +164,10 @@ impl Address {
                 s.read_exact(&mut buf)?;
                 let len = buf[0] as usize;
 
+                if len > MAX_DOMAIN_LEN {
+                    return Err(UnmarshalError::DomainTooLong(len));
+                }
+
                 let mut buf = vec![0; len + 2];
                 s.read_exact(&mut buf)?;
                 let port = u16::from_be_bytes([buf[len], buf[len + 1]]);
@@ -174,7 +217,7 @@ impl Authenticate {
 
     #[cfg(feature = "marshal")]
     fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
-        let mut buf = [0; 32];
+        let mut buf = [0; 48];
         s.read_exact(&mut buf)?;
         let uuid = Uuid::from_slice(&buf[..16])?;
         let token = TryFrom::try_from(&buf[16..]).unwrap();
@@ -194,6 +237,48 @@ impl Connect {
     }
 }
 
+impl ConnectBound {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        Ok(Self::new(Address::async_read(s).await?))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        Ok(Self::new(Address::read(s)?))
+    }
+}
+
+impl Bind {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        Ok(Self::new(Address::async_read(s).await?))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        Ok(Self::new(Address::read(s)?))
+    }
+}
+
+impl ConnectLinked {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf).await?;
+        let assoc_id = u16::from_be_bytes(buf);
+        Ok(Self::new(assoc_id, Address::async_read(s).await?))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 2];
+        s.read_exact(&mut buf)?;
+        let assoc_id = u16::from_be_bytes(buf);
+        Ok(Self::new(assoc_id, Address::read(s)?))
+    }
+}
+
 impl Packet {
     #[cfg(feature = "async_marshal")]
     async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
@@ -205,6 +290,15 @@ impl Packet {
         let frag_total = buf[4];
         let frag_id = buf[5];
         let size = u16::from_be_bytes([buf[6], buf[7]]);
+
+        if frag_id >= frag_total {
+            return Err(UnmarshalError::InvalidFragment(frag_total, frag_id));
+        }
+
+        if size > MAX_PACKET_SIZE {
+            return Err(UnmarshalError::PacketTooLarge(size));
+        }
+
         let addr = Address::async_read(s).await?;
 
         Ok(Self::new(assoc_id, pkt_id, frag_total, frag_id, size, addr))
@@ -220,6 +314,15 @@ impl Packet {
         let frag_total = buf[4];
         let frag_id = buf[5];
         let size = u16::from_be_bytes([buf[6], buf[7]]);
+
+        if frag_id >= frag_total {
+            return Err(UnmarshalError::InvalidFragment(frag_total, frag_id));
+        }
+
+        if size > MAX_PACKET_SIZE {
+            return Err(UnmarshalError::PacketTooLarge(size));
+        }
+
         let addr = Address::read(s)?;
 
         Ok(Self::new(assoc_id, pkt_id, frag_total, frag_id, size, addr))
@@ -244,6 +347,26 @@ impl Dissociate {
     }
 }
 
+impl Associate {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 6];
+        s.read_exact(&mut buf).await?;
+        let assoc_id = u16::from_be_bytes([buf[0], buf[1]]);
+        let timeout = u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]]);
+        Ok(Self::new(assoc_id, timeout))
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 6];
+        s.read_exact(&mut buf)?;
+        let assoc_id = u16::from_be_bytes([buf[0], buf[1]]);
+        let timeout = u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]]);
+        Ok(Self::new(assoc_id, timeout))
+    }
+}
+
 impl Heartbeat {
     #[cfg(feature = "async_marshal")]
     async fn async_read(_s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
@@ -256,6 +379,56 @@ impl Heartbeat {
     }
 }
 
+impl Stats {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(_s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        Ok(Self::new())
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(_s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        Ok(Self::new())
+    }
+}
+
+impl DissociateAll {
+    #[cfg(feature = "async_marshal")]
+    async fn async_read(_s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        Ok(Self::new())
+    }
+
+    #[cfg(feature = "marshal")]
+    fn read(_s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        Ok(Self::new())
+    }
+}
+
+impl StatsResponse {
+    /// Unmarshals a response from an `AsyncRead` stream
+    #[cfg(feature = "async_marshal")]
+    pub async fn async_unmarshal(s: &mut (impl AsyncRead + Unpin)) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 26];
+        s.read_exact(&mut buf).await?;
+        Ok(Self::from_bytes(buf))
+    }
+
+    /// Unmarshals a response from a `Read` stream
+    #[cfg(feature = "marshal")]
+    pub fn unmarshal(s: &mut impl Read) -> Result<Self, UnmarshalError> {
+        let mut buf = [0; 26];
+        s.read_exact(&mut buf)?;
+        Ok(Self::from_bytes(buf))
+    }
+
+    fn from_bytes(buf: [u8; 26]) -> Self {
+        let upload = u64::from_be_bytes(buf[0..8].try_into().unwrap());
+        let download = u64::from_be_bytes(buf[8..16].try_into().unwrap());
+        let associations = u16::from_be_bytes([buf[16], buf[17]]);
+        let uptime = u64::from_be_bytes(buf[18..26].try_into().unwrap());
+        Self::new(upload, download, associations, uptime)
+    }
+}
+
 /// Errors that can occur when unmarshalling a packet
 #[derive(Debug, Error)]
 pub enum UnmarshalError {
@@ -269,6 +442,312 @@ pub enum UnmarshalError {
     InvalidUuid(#[from] UuidError),
     #[error("invalid address type: {0}")]
     InvalidAddressType(u8),
+    #[error("invalid fragment id {1} in total {0} fragments")]
+    InvalidFragment(u8, u8),
     #[error("address parsing error: {0}")]
     AddressParse(#[from] FromUtf8Error),
+    #[error("domain name of {0} bytes exceeds the maximum of {MAX_DOMAIN_LEN}")]
+    DomainTooLong(usize),
+    #[error("packet size {0} exceeds the maximum of {MAX_PACKET_SIZE}")]
+    PacketTooLarge(u16),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{io::Cursor, task::noop_waker};
+    use std::{
+        future::Future,
+        io::ErrorKind,
+        mem,
+        net::{Ipv6Addr, SocketAddrV6},
+        task::{Context, Poll},
+    };
+
+    // reading from a buffer never blocks, so a single poll is enough
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        match Box::pin(fut).as_mut().poll(&mut cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
+    fn marshal(header: &Header) -> Vec<u8> {
+        let mut buf = Vec::new();
+        header.marshal(&mut buf).unwrap();
+        assert_eq!(buf.len(), header.len());
+        buf
+    }
+
+    /// Unmarshals `buf` in both flavors, checking that all of it is consumed and that marshaling
+    /// the result gives `buf` back
+    fn round_trip(buf: &[u8]) -> Header {
+        let mut s = buf;
+        let header = Header::unmarshal(&mut s).unwrap();
+        assert!(s.is_empty());
+        assert_eq!(marshal(&header), buf);
+
+        let mut s = Cursor::new(buf);
+        let async_header = block_on(Header::async_unmarshal(&mut s)).unwrap();
+        assert_eq!(s.position() as usize, buf.len());
+        assert_eq!(marshal(&async_header), buf);
+
+        header
+    }
+
+    fn addrs() -> Vec<Address> {
+        vec![
+            Address::SocketAddress(SocketAddr::from(([192, 0, 2, 1], 443))),
+            Address::SocketAddress(SocketAddr::from(([0, 0, 0, 0], 0))),
+            Address::SocketAddress(SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], 65535))),
+            Address::SocketAddress(SocketAddr::from((Ipv6Addr::LOCALHOST, 53))),
+            Address::DomainAddress(String::from("example.com"), 80),
+            Address::DomainAddress(String::from("[::1]"), 8080),
+            Address::DomainAddress(String::new(), 1),
+            Address::DomainAddress("a".repeat(MAX_DOMAIN_LEN), 443),
+        ]
+    }
+
+    #[test]
+    fn address_round_trip() {
+        for addr in addrs().into_iter().chain([Address::None]) {
+            let mut buf = Vec::new();
+            addr.marshal(&mut buf).unwrap();
+            assert_eq!(buf.len(), addr.len());
+
+            let mut s = buf.as_slice();
+            assert_eq!(Address::unmarshal(&mut s).unwrap(), addr);
+            assert!(s.is_empty());
+
+            let mut s = Cursor::new(buf.as_slice());
+            assert_eq!(block_on(Address::async_unmarshal(&mut s)).unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn ipv6_scope_is_not_carried() {
+        let ip = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let addr = Address::SocketAddress(SocketAddr::V6(SocketAddrV6::new(ip, 443, 7, 2)));
+
+        let mut buf = Vec::new();
+        addr.marshal(&mut buf).unwrap();
+
+        assert_eq!(
+            Address::unmarshal(&mut buf.as_slice()).unwrap(),
+            Address::SocketAddress(SocketAddr::from((ip, 443)))
+        );
+    }
+
+    #[test]
+    fn header_round_trip() {
+        for addr in addrs() {
+            let header = round_trip(&marshal(&Header::Connect(Connect::new(addr.clone()))));
+            assert!(matches!(header, Header::Connect(conn) if *conn.addr() == addr));
+
+            let header = Header::ConnectBound(ConnectBound::new(addr.clone()));
+            let header = round_trip(&marshal(&header));
+            assert!(matches!(header, Header::ConnectBound(conn) if *conn.addr() == addr));
+
+            let header = round_trip(&marshal(&Header::Bind(Bind::new(addr.clone()))));
+            assert!(matches!(header, Header::Bind(bind) if *bind.addr() == addr));
+
+            let header = Header::ConnectLinked(ConnectLinked::new(7, addr.clone()));
+            let header = round_trip(&marshal(&header));
+            assert!(matches!(
+                header,
+                Header::ConnectLinked(conn) if conn.assoc_id() == 7 && *conn.addr() == addr
+            ));
+
+            let header = Header::Packet(Packet::new(1, 2, 3, 2, MAX_PACKET_SIZE, addr.clone()));
+            let Header::Packet(pkt) = round_trip(&marshal(&header)) else {
+                panic!("not a packet");
+            };
+            assert_eq!(
+                (
+                    pkt.assoc_id(),
+                    pkt.pkt_id(),
+                    pkt.frag_total(),
+                    pkt.frag_id()
+                ),
+                (1, 2, 3, 2)
+            );
+            assert_eq!((pkt.size(), pkt.addr()), (MAX_PACKET_SIZE, &addr));
+        }
+
+        let uuid = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+        let header = Header::Authenticate(Authenticate::new(uuid, [0xa5; 32]));
+        let header = round_trip(&marshal(&header));
+        assert!(matches!(
+            header,
+            Header::Authenticate(auth) if auth.uuid() == uuid && auth.token() == [0xa5; 32]
+        ));
+
+        round_trip(&marshal(&Header::Dissociate(Dissociate::new(u16::MAX))));
+        round_trip(&marshal(&Header::Heartbeat(Heartbeat::new())));
+        round_trip(&marshal(&Header::Stats(Stats::new())));
+        round_trip(&marshal(&Header::DissociateAll(DissociateAll::new())));
+        round_trip(&marshal(&Header::Associate(Associate::new(3, 60_000))));
+    }
+
+    /// Unmarshals `buf` in both flavors, expecting both to fail the same way
+    fn unmarshal_err(buf: &[u8]) -> UnmarshalError {
+        let err = Header::unmarshal(&mut &*buf).unwrap_err();
+        let async_err = block_on(Header::async_unmarshal(&mut Cursor::new(buf))).unwrap_err();
+        assert_eq!(mem::discriminant(&err), mem::discriminant(&async_err));
+        err
+    }
+
+    fn connect_to_domain(len: u8) -> Vec<u8> {
+        let mut buf = vec![
+            VERSION,
+            Header::TYPE_CODE_CONNECT,
+            Address::TYPE_CODE_DOMAIN,
+            len,
+        ];
+        buf.extend(vec![b'a'; len as usize]);
+        buf.extend(443u16.to_be_bytes());
+        buf
+    }
+
+    fn packet_of_size(size: u16) -> Vec<u8> {
+        let mut buf = vec![VERSION, Header::TYPE_CODE_PACKET, 0, 1, 0, 2, 1, 0];
+        buf.extend(size.to_be_bytes());
+        buf.push(Address::TYPE_CODE_NONE);
+        buf
+    }
+
+    #[test]
+    fn rejects_too_long_domain() {
+        round_trip(&connect_to_domain(MAX_DOMAIN_LEN as u8));
+
+        for len in [MAX_DOMAIN_LEN as u8 + 1, u8::MAX] {
+            assert!(matches!(
+                unmarshal_err(&connect_to_domain(len)),
+                UnmarshalError::DomainTooLong(l) if l == len as usize
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_too_large_packet() {
+        round_trip(&packet_of_size(MAX_PACKET_SIZE));
+
+        for size in [MAX_PACKET_SIZE + 1, u16::MAX] {
+            assert!(matches!(
+                unmarshal_err(&packet_of_size(size)),
+                UnmarshalError::PacketTooLarge(s) if s == size
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_length_beyond_the_input() {
+        // a domain declared longer than what follows is never read past the input
+        let mut buf = connect_to_domain(10);
+        buf.truncate(buf.len() - 3);
+
+        assert!(matches!(
+            unmarshal_err(&buf),
+            UnmarshalError::Io(err) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+
+        for buf in [
+            vec![],
+            vec![VERSION],
+            vec![VERSION, Header::TYPE_CODE_AUTHENTICATE, 0],
+        ] {
+            assert!(matches!(
+                unmarshal_err(&buf),
+                UnmarshalError::Io(err) if err.kind() == ErrorKind::UnexpectedEof
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert!(matches!(
+            unmarshal_err(&[VERSION - 1, Header::TYPE_CODE_HEARTBEAT]),
+            UnmarshalError::InvalidVersion(_)
+        ));
+        assert!(matches!(
+            unmarshal_err(&[VERSION, 0xff]),
+            UnmarshalError::InvalidCommand(0xff)
+        ));
+        assert!(matches!(
+            unmarshal_err(&[VERSION, Header::TYPE_CODE_CONNECT, 0x7f]),
+            UnmarshalError::InvalidAddressType(0x7f)
+        ));
+
+        // `FRAG_ID` must be below `FRAG_TOTAL`
+        let mut buf = packet_of_size(0);
+        buf[6] = 2;
+        buf[7] = 2;
+        assert!(matches!(
+            unmarshal_err(&buf),
+            UnmarshalError::InvalidFragment(2, 2)
+        ));
+
+        let mut buf = connect_to_domain(2);
+        buf[4] = 0xff;
+        assert!(matches!(
+            unmarshal_err(&buf),
+            UnmarshalError::AddressParse(_)
+        ));
+    }
+
+    #[test]
+    fn stats_round_trip() {
+        let header = round_trip(&[VERSION, Header::TYPE_CODE_STATS]);
+        assert!(matches!(header, Header::Stats(_)));
+
+        for resp in [
+            StatsResponse::new(0, 0, 0, 0),
+            StatsResponse::new(1 << 40, 7, 3, 86_400),
+            StatsResponse::new(u64::MAX, u64::MAX, u16::MAX, u64::MAX),
+        ] {
+            let mut buf = Vec::new();
+            resp.marshal(&mut buf).unwrap();
+            assert_eq!(buf.len(), resp.len());
+
+            let fields = |resp: &StatsResponse| {
+                (
+                    resp.upload(),
+                    resp.download(),
+                    resp.associations(),
+                    resp.uptime(),
+                )
+            };
+
+            let mut s = buf.as_slice();
+            assert_eq!(
+                fields(&StatsResponse::unmarshal(&mut s).unwrap()),
+                fields(&resp)
+            );
+            assert!(s.is_empty());
+
+            let mut s = Cursor::new(buf.as_slice());
+            let async_resp = block_on(StatsResponse::async_unmarshal(&mut s)).unwrap();
+            assert_eq!(fields(&async_resp), fields(&resp));
+            assert_eq!(s.position() as usize, buf.len());
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_stats_response() {
+        let mut buf = Vec::new();
+        StatsResponse::new(1, 2, 3, 4).marshal(&mut buf).unwrap();
+        buf.pop();
+
+        assert!(matches!(
+            StatsResponse::unmarshal(&mut buf.as_slice()),
+            Err(UnmarshalError::Io(err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+        assert!(matches!(
+            block_on(StatsResponse::async_unmarshal(&mut Cursor::new(buf.as_slice()))),
+            Err(UnmarshalError::Io(err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+    }
 }